@@ -0,0 +1,44 @@
+/// Group of put/delete operations applied as a unit by `Persister::write_batch`.
+///
+/// When the same key appears several times in a batch the last operation on it wins, e.g. a put
+/// followed by a delete of the same key ends up as a delete
+pub struct WriteBatch<K> {
+    ops: Vec<(K, Option<Vec<u8>>)>,
+}
+
+impl<K> WriteBatch<K> {
+    pub fn new() -> Self {
+        Self { ops: vec![] }
+    }
+
+    /// Stores `value` under `key`, inserting the key or replacing its current value
+    pub fn put(&mut self, key: K, value: Vec<u8>) -> &mut Self {
+        self.ops.push((key, Some(value)));
+        self
+    }
+
+    /// Removes `key`, the whole batch fails with `KVError::KeyDoesNotExist` if the key is not
+    /// stored when the batch is applied
+    pub fn delete(&mut self, key: K) -> &mut Self {
+        self.ops.push((key, None));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub(crate) fn into_ops(self) -> Vec<(K, Option<Vec<u8>>)> {
+        self.ops
+    }
+}
+
+impl<K> Default for WriteBatch<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
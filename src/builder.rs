@@ -0,0 +1,198 @@
+use std::fmt::Debug;
+use std::io::ErrorKind;
+use std::path::Path;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::cache::CacheConfig;
+use crate::compression::Compression;
+use crate::fileheader::{FileHeader, OpenMode};
+use crate::freelist::AllocationStrategy;
+use crate::persist::{EofPolicy, KVError, Persister, SyncMode};
+
+/// Options to open a `Persister` with. The defaults open the datastore the way `Persister::new`
+/// does: it is created if missing, never truncated, writable, unlimited, synced on demand,
+/// uncompressed, read without a cache, and its released space is not zeroed.
+///
+/// ```
+/// use embedkv::{Persister, PersisterBuilder, SyncMode};
+///
+/// let dir = tempfile::tempdir()?;
+/// let persister: Persister<String> = PersisterBuilder::new()
+///     .path(dir.path().join("store"))
+///     .storage_limit(1024 * 1024)
+///     .sync_mode(SyncMode::Always)
+///     .open()?;
+/// assert!(persister.is_empty());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PersisterBuilder {
+    path: Option<String>, // None for a uuid named datastore in the current directory
+    temporary: bool,
+    pub(crate) mode: OpenMode,
+    pub(crate) storage_limit: usize,
+    pub(crate) sync_mode: SyncMode,
+    pub(crate) cache: CacheConfig,
+    pub(crate) secure_delete: bool,
+    pub(crate) eof_policy: EofPolicy,
+    pub(crate) allocation_strategy: AllocationStrategy,
+    pub(crate) label: Option<String>,
+}
+
+impl PersisterBuilder {
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            temporary: false,
+            mode: OpenMode::default(),
+            storage_limit: 0,
+            sync_mode: SyncMode::Never,
+            cache: CacheConfig::default(),
+            secure_delete: false,
+            eof_policy: EofPolicy::Fail,
+            allocation_strategy: AllocationStrategy::default(),
+            label: None,
+        }
+    }
+
+    /// Path of the db file, the index file and the write-ahead log live next to it
+    pub fn path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.path = Some(path.as_ref().to_string_lossy().to_string());
+        self
+    }
+
+    /// Backs the datastore with anonymous temporary files instead of the files at `path`. The
+    /// store starts empty, works like any other and leaves nothing behind once dropped, which
+    /// suits tests and throwaway caches
+    ///
+    /// ```
+    /// use embedkv::{Persister, PersisterBuilder};
+    ///
+    /// let mut persister: Persister<String> = PersisterBuilder::new().temporary().open()?;
+    /// persister.insert_kv(&"key".to_string(), &b"value".to_vec())?;
+    /// assert_eq!(b"value".to_vec(), persister.get_value(&"key".to_string())?);
+    /// # Ok::<(), embedkv::KVError>(())
+    /// ```
+    pub fn temporary(&mut self) -> &mut Self {
+        self.temporary = true;
+        self
+    }
+
+    /// Whether a missing datastore is created, otherwise opening it fails with
+    /// `KVError::DatastoreDoesNotExist`
+    pub fn create_if_missing(&mut self, create_if_missing: bool) -> &mut Self {
+        self.mode.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// Whether the datastore is emptied when it is opened
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.mode.truncate = truncate;
+        self
+    }
+
+    /// Opens the files without write access, every method changing the store fails with
+    /// `KVError::ReadOnly`. A read-only datastore is never created, and one left with writes
+    /// to recover by a crash can't be opened until it is opened writable once
+    pub fn read_only(&mut self, read_only: bool) -> &mut Self {
+        self.mode.read_only = read_only;
+        self
+    }
+
+    /// Size in bytes the db file can't grow past, writes needing more space fail with
+    /// `KVError::StorageLimitExceeded`. An update needs room for the new value while the
+    /// previous one is still stored. 0 means no limit
+    pub fn storage_limit(&mut self, bytes: usize) -> &mut Self {
+        self.storage_limit = bytes;
+        self
+    }
+
+    pub fn sync_mode(&mut self, mode: SyncMode) -> &mut Self {
+        self.sync_mode = mode;
+        self
+    }
+
+    /// Compresses the values written to the store. The compression a datastore is created with
+    /// is recorded in its files: a datastore created with compression must be opened with some
+    /// compression, any algorithm, and one created without it must be opened without it, or
+    /// opening it fails with `KVError::InvalidFormat`
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.mode.compression = compression;
+        self
+    }
+
+    /// Keeps the values most recently read by `Persister::get_value` in memory, up to
+    /// `config.max_bytes`. Writes drop the cached value of their keys, and the hits and misses
+    /// are counted in `Persister::stats`
+    pub fn cache(&mut self, config: CacheConfig) -> &mut Self {
+        self.cache = config;
+        self
+    }
+
+    /// Overwrites with zeros the space of every value deleted or replaced, see
+    /// `Persister::set_secure_delete`
+    pub fn secure_delete(&mut self, enabled: bool) -> &mut Self {
+        self.secure_delete = enabled;
+        self
+    }
+
+    /// What reads do with a key whose slot extends past the end of the db file, see `EofPolicy`
+    pub fn eof_policy(&mut self, policy: EofPolicy) -> &mut Self {
+        self.eof_policy = policy;
+        self
+    }
+
+    /// Which free slot new values go to when several of them fit, see `AllocationStrategy`
+    pub fn allocation_strategy(&mut self, strategy: AllocationStrategy) -> &mut Self {
+        self.allocation_strategy = strategy;
+        self
+    }
+
+    /// Free-form label recorded along with the options of the datastore when it is created, see
+    /// `Persister::creation_info`. Opening an existing datastore leaves its label as it is
+    pub fn label<S: Into<String>>(&mut self, label: S) -> &mut Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Opens the datastore, loading the index of the keys already stored. Files that aren't the
+    /// files of a datastore fail with `KVError::NotADatastore`, the ones of a damaged datastore
+    /// with `KVError::CorruptDatastore` and the ones of a later format version with
+    /// `KVError::UnsupportedVersion`
+    pub fn open<K>(&self) -> Result<Persister<K>, KVError>
+    where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+        if self.mode.read_only && self.mode.truncate {
+            return Err(KVError::InvalidArgument("a read-only datastore can't be truncated".to_string()));
+        }
+
+        if self.temporary && self.mode.read_only {
+            return Err(KVError::InvalidArgument("a temporary datastore can't be read-only".to_string()));
+        }
+
+        let header = match self.temporary {
+            true => FileHeader::temporary(self.mode.compression)?,
+            false => FileHeader::new(self.path.clone(), self.mode)
+                .map_err(|io_error| match io_error.kind() {
+                    ErrorKind::NotFound if !self.mode.create_if_missing || self.mode.read_only => KVError::DatastoreDoesNotExist,
+                    _ => self.opening_error(KVError::from(io_error)),
+                })?,
+        };
+
+        Persister::open_configured(header, self).map_err(|error| self.opening_error(error))
+    }
+
+    // files that aren't a datastore or are corrupt are reported with the path of the datastore,
+    // stores named by a uuid or backed by temporary files are created empty
+    fn opening_error(&self, error: KVError) -> KVError {
+        match (&self.path, self.temporary) {
+            (Some(path), false) => KVError::opening(Path::new(path), error),
+            _ => error,
+        }
+    }
+}
+
+impl Default for PersisterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
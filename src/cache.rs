@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+/// Size of the cache of values read by `Persister::get_value`, see `PersisterBuilder::cache`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheConfig {
+    /// bytes of values the cache holds at most, 0 disables the cache. Values bigger than the
+    /// whole budget are never cached
+    pub max_bytes: usize,
+}
+
+/// Least recently used values, evicted once their total length goes over the budget. Every
+/// value written or removed through the store is dropped from the cache before the files are
+/// touched, so it never holds a value the files no longer do
+pub(crate) struct ValueCache<K> {
+    max_bytes: usize,
+    bytes: usize,
+    entries: BTreeMap<K, (Vec<u8>, u64)>, // key -> value and tick of its last use
+    recency: BTreeMap<u64, K>, // tick of the last use -> key, the oldest first
+    next_tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<K: Ord + Clone> ValueCache<K> {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            max_bytes: config.max_bytes,
+            bytes: 0,
+            entries: BTreeMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
+    }
+
+    /// Copy of the cached value, which becomes the most recently used. Lookups are only
+    /// counted while the cache is enabled
+    pub fn get(&mut self, key: &K) -> Option<Vec<u8>> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let tick = self.next_tick;
+        let Some((value, last_used)) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+
+        let key = self.recency.remove(last_used).unwrap_or_else(|| key.clone());
+        *last_used = tick;
+        self.recency.insert(tick, key);
+        self.next_tick += 1;
+        self.hits += 1;
+
+        Some(value.clone())
+    }
+
+    /// Caches the value read for the key, evicting the least recently used values until it fits
+    pub fn insert(&mut self, key: &K, value: &[u8]) {
+        if !self.is_enabled() || value.len() > self.max_bytes {
+            return;
+        }
+
+        self.remove(key);
+        while self.bytes + value.len() > self.max_bytes {
+            let Some((_, evicted)) = self.recency.pop_first() else { break };
+            if let Some((value, _)) = self.entries.remove(&evicted) {
+                self.bytes -= value.len();
+            }
+        }
+
+        self.entries.insert(key.clone(), (value.to_vec(), self.next_tick));
+        self.recency.insert(self.next_tick, key.clone());
+        self.next_tick += 1;
+        self.bytes += value.len();
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((value, last_used)) = self.entries.remove(key) {
+            self.recency.remove(&last_used);
+            self.bytes -= value.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.bytes = 0;
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_keys(cache: &ValueCache<&'static str>) -> Vec<&'static str> {
+        cache.recency.values().copied().collect()
+    }
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let mut cache = ValueCache::new(CacheConfig { max_bytes: 10 });
+        cache.insert(&"a", &[1; 4]);
+        cache.insert(&"b", &[2; 4]);
+        assert_eq!(Some(vec![1; 4]), cache.get(&"a"));
+
+        // b is the least recently used
+        cache.insert(&"c", &[3; 4]);
+        assert_eq!(vec!["a", "c"], cached_keys(&cache));
+        assert_eq!(None, cache.get(&"b"));
+
+        // making room for 9 bytes evicts both
+        cache.insert(&"d", &[4; 9]);
+        assert_eq!(vec!["d"], cached_keys(&cache));
+        assert_eq!(9, cache.bytes);
+        assert_eq!((1, 1), (cache.hits(), cache.misses()));
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut cache = ValueCache::new(CacheConfig { max_bytes: 10 });
+        cache.insert(&"a", &[1; 4]);
+        cache.insert(&"a", &[2; 6]);
+        assert_eq!(6, cache.bytes);
+        assert_eq!(Some(vec![2; 6]), cache.get(&"a"));
+
+        cache.remove(&"a");
+        cache.remove(&"missing");
+        assert_eq!(0, cache.bytes);
+        assert_eq!(None, cache.get(&"a"));
+        assert!(cached_keys(&cache).is_empty());
+    }
+
+    #[test]
+    fn test_values_over_budget_and_disabled_cache() {
+        let mut cache = ValueCache::new(CacheConfig { max_bytes: 10 });
+        cache.insert(&"a", &[1; 4]);
+        cache.insert(&"big", &[2; 11]);
+        assert_eq!(vec!["a"], cached_keys(&cache));
+        cache.insert(&"empty", &[]);
+        assert_eq!(Some(vec![]), cache.get(&"empty"));
+
+        let mut disabled = ValueCache::new(CacheConfig::default());
+        disabled.insert(&"a", &[1]);
+        assert_eq!(None, disabled.get(&"a"));
+        assert_eq!((0, 0), (disabled.hits(), disabled.misses()));
+    }
+}
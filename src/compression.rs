@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+use crate::persist::KVError;
+
+/// How values are compressed before being written to the db file. Only `None` is available
+/// without the `compression` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// values are stored as given
+    #[default]
+    None,
+    #[cfg(feature = "compression")]
+    Lz4,
+    /// `level` as understood by zstd, 0 picks its default level
+    #[cfg(feature = "compression")]
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    pub(crate) fn is_enabled(self) -> bool {
+        self != Compression::None
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            #[cfg(feature = "compression")]
+            Compression::Lz4 => "lz4",
+            #[cfg(feature = "compression")]
+            Compression::Zstd { .. } => "zstd",
+        }
+    }
+}
+
+// first byte of every non-empty value written with compression enabled
+const RAW: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+
+/// Longest frame header: the tag and the uncompressed length as a LEB128 u64
+pub(crate) const MAX_HEADER_LEN: usize = 11;
+
+/// Start of a value written with compression enabled
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FrameHeader {
+    tag: u8,
+    pub value_len: usize,
+    pub header_len: usize,
+}
+
+impl FrameHeader {
+    /// Whether the value follows the header as given
+    pub fn is_raw(&self) -> bool {
+        self.tag == RAW
+    }
+}
+
+/// Stored form of a value. With compression enabled a value is framed by a tag and, when
+/// compressed, its uncompressed length. Values that don't shrink are stored raw behind the tag
+/// alone, and empty values are always stored empty
+pub(crate) fn encode(compression: Compression, value: &[u8]) -> Result<Cow<'_, [u8]>, KVError> {
+    if value.is_empty() {
+        return Ok(Cow::Borrowed(value));
+    }
+    let (tag, compressed) = match compress(compression, value)? {
+        Some(compressed) => compressed,
+        None => return Ok(Cow::Borrowed(value)),
+    };
+
+    let mut stored = vec![tag];
+    write_varint(&mut stored, value.len() as u64);
+    if stored.len() + compressed.len() > value.len() {
+        stored.clear();
+        stored.push(RAW);
+        stored.extend_from_slice(value);
+    } else {
+        stored.extend_from_slice(&compressed);
+    }
+
+    Ok(Cow::Owned(stored))
+}
+
+/// Value held by the stored bytes of a slot, the reverse of `encode`
+pub(crate) fn decode(compression: Compression, mut stored: Vec<u8>) -> Result<Vec<u8>, KVError> {
+    if !compression.is_enabled() || stored.is_empty() {
+        return Ok(stored);
+    }
+
+    let header = parse_header(&stored, stored.len())?;
+    if header.is_raw() {
+        stored.remove(0);
+        return Ok(stored);
+    }
+
+    decompress(header.tag, &stored[header.header_len..], header.value_len)
+}
+
+/// Header to write before a value streamed raw into the store
+pub(crate) fn raw_header(compression: Compression, len: usize) -> &'static [u8] {
+    match compression.is_enabled() && len > 0 {
+        true => &[RAW],
+        false => &[],
+    }
+}
+
+/// Parses the header at the start of a non-empty slot of `space` bytes, `prefix` holds its
+/// first `MAX_HEADER_LEN` bytes or the whole slot if it is shorter
+pub(crate) fn parse_header(prefix: &[u8], space: usize) -> Result<FrameHeader, KVError> {
+    match prefix.first() {
+        Some(&RAW) => Ok(FrameHeader { tag: RAW, value_len: space - 1, header_len: 1 }),
+        Some(&tag) if tag == LZ4 || tag == ZSTD => {
+            let (value_len, len) = read_varint(&prefix[1..])
+                .ok_or_else(|| corrupt("truncated uncompressed length"))?;
+            Ok(FrameHeader { tag, value_len: value_len as usize, header_len: 1 + len })
+        },
+        Some(tag) => Err(corrupt(&format!("unknown compression tag {}", tag))),
+        None => Err(corrupt("missing compression tag")),
+    }
+}
+
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+fn compress(compression: Compression, value: &[u8]) -> Result<Option<(u8, Vec<u8>)>, KVError> {
+    match compression {
+        Compression::None => Ok(None),
+        #[cfg(feature = "compression")]
+        Compression::Lz4 => Ok(Some((LZ4, lz4_flex::block::compress(value)))),
+        #[cfg(feature = "compression")]
+        Compression::Zstd { level } => zstd::bulk::compress(value, level)
+            .map(|compressed| Some((ZSTD, compressed)))
+            .map_err(|io_error| KVError::CompressionError(io_error.to_string())),
+    }
+}
+
+#[cfg(feature = "compression")]
+fn decompress(tag: u8, payload: &[u8], len: usize) -> Result<Vec<u8>, KVError> {
+    let value = match tag {
+        LZ4 => lz4_flex::block::decompress(payload, len)
+            .map_err(|error| corrupt(&error.to_string()))?,
+        _ => zstd::bulk::decompress(payload, len)
+            .map_err(|io_error| corrupt(&io_error.to_string()))?,
+    };
+    if value.len() != len {
+        return Err(corrupt(&format!("expected {} bytes once decompressed, got {}", len, value.len())));
+    }
+
+    Ok(value)
+}
+
+#[cfg(not(feature = "compression"))]
+fn decompress(_tag: u8, _payload: &[u8], _len: usize) -> Result<Vec<u8>, KVError> {
+    Err(KVError::CompressionError("built without the compression feature".to_string()))
+}
+
+fn corrupt(reason: &str) -> KVError {
+    KVError::Corruption(format!("corrupt compressed value: {}", reason))
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+// the value and the number of bytes it took, None if the bytes end before the value does
+fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0;
+    for (i, byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut buffer = vec![];
+            write_varint(&mut buffer, value);
+            assert!(buffer.len() < MAX_HEADER_LEN);
+            assert_eq!(Some((value, buffer.len())), read_varint(&buffer));
+            assert_eq!(None, read_varint(&buffer[..buffer.len() - 1]));
+        }
+    }
+
+    #[test]
+    fn test_no_compression_stores_values_as_given() {
+        assert_eq!(b"value", encode(Compression::None, b"value").unwrap().as_ref());
+        assert_eq!(b"value".to_vec(), decode(Compression::None, b"value".to_vec()).unwrap());
+        assert!(raw_header(Compression::None, 5).is_empty());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(FrameHeader { tag: RAW, value_len: 4, header_len: 1 }, parse_header(&[RAW, 1, 2], 5).unwrap());
+        assert_eq!(FrameHeader { tag: LZ4, value_len: 300, header_len: 3 }, parse_header(&[LZ4, 0xac, 0x02, 9], 20).unwrap());
+        assert!(matches!(parse_header(&[ZSTD, 0xac], 2), Err(KVError::Corruption(_))));
+        assert!(matches!(parse_header(&[7, 1], 2), Err(KVError::Corruption(_))));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_round_trip() {
+        let compressible = b"abcdefgh".repeat(100);
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let incompressible: Vec<u8> = (0..200).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect();
+
+        for compression in [Compression::Lz4, Compression::Zstd { level: 0 }, Compression::Zstd { level: 19 }] {
+            let stored = encode(compression, &compressible).unwrap().into_owned();
+            assert!(stored.len() < compressible.len() / 4);
+            assert!(!parse_header(&stored, stored.len()).unwrap().is_raw());
+            assert_eq!(compressible, decode(compression, stored).unwrap());
+
+            // values that don't shrink only pay for the tag
+            let stored = encode(compression, &incompressible).unwrap().into_owned();
+            assert_eq!(incompressible.len() + 1, stored.len());
+            assert_eq!(incompressible, decode(compression, stored).unwrap());
+
+            assert!(encode(compression, &[]).unwrap().is_empty());
+            assert!(decode(compression, vec![]).unwrap().is_empty());
+        }
+
+        // every value carries its own tag, so any enabled compression reads it back
+        let stored = encode(Compression::Lz4, &compressible).unwrap().into_owned();
+        assert_eq!(compressible, decode(Compression::Zstd { level: 3 }, stored).unwrap());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_corrupt_payload() {
+        let mut stored = encode(Compression::Lz4, &b"abcdefgh".repeat(100)).unwrap().into_owned();
+        stored.truncate(stored.len() / 2);
+        assert!(matches!(decode(Compression::Lz4, stored), Err(KVError::Corruption(_))));
+    }
+}
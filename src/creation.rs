@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use crate::persist::KVError;
+
+const CRATE_VERSION: &str = "crate_version";
+const CREATED_AT: &str = "created_at";
+const COMPRESSION: &str = "compression";
+const LABEL: &str = "label";
+
+/// Options and details of a datastore recorded when it was created, see
+/// `Persister::creation_info`. Only the options that shape the files are recorded, the ones
+/// that can change from one open to the next are not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreationInfo {
+    /// version of the crate that created the datastore
+    pub crate_version: String,
+    /// milliseconds since the unix epoch
+    pub created_at: u64,
+    /// compression the datastore was created with: "none", "lz4" or "zstd"
+    pub compression: String,
+    /// label given by `PersisterBuilder::label`
+    pub label: Option<String>,
+    // fields written by later releases, kept as they are when the block is written again
+    extra: BTreeMap<String, String>,
+}
+
+impl CreationInfo {
+    pub(crate) fn new(created_at: u64, compression: &str, label: Option<String>) -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at,
+            compression: compression.to_string(),
+            label,
+            extra: BTreeMap::new(),
+        }
+    }
+
+    /// Serializes the block as a map of named fields, so releases can add fields that earlier
+    /// ones skip
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut fields = self.extra.clone();
+        fields.insert(CRATE_VERSION.to_string(), self.crate_version.clone());
+        fields.insert(CREATED_AT.to_string(), self.created_at.to_string());
+        fields.insert(COMPRESSION.to_string(), self.compression.clone());
+        if let Some(label) = self.label.as_ref() {
+            fields.insert(LABEL.to_string(), label.clone());
+        }
+
+        // a map of strings always serializes
+        bincode::serialize(&fields).unwrap_or_default()
+    }
+
+    pub(crate) fn decode(bytes: &[u8]) -> Result<Self, KVError> {
+        let mut fields: BTreeMap<String, String> = bincode::deserialize(bytes)
+            .map_err(|error| KVError::Corruption(format!("creation info: {}", error)))?;
+        let mut take = |name: &str| fields.remove(name)
+            .ok_or_else(|| KVError::Corruption(format!("creation info has no {}", name)));
+
+        let crate_version = take(CRATE_VERSION)?;
+        let created_at = take(CREATED_AT)?.parse()
+            .map_err(|error| KVError::Corruption(format!("creation info: {}: {}", CREATED_AT, error)))?;
+        let compression = take(COMPRESSION)?;
+        let label = fields.remove(LABEL);
+
+        Ok(Self { crate_version, created_at, compression, label, extra: fields })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let info = CreationInfo::new(1_700_000_000_000, "lz4", Some("orders, eu-west".to_string()));
+        assert_eq!(env!("CARGO_PKG_VERSION"), info.crate_version);
+        assert_eq!(Ok(info.clone()), CreationInfo::decode(&info.encode()));
+
+        let unlabeled = CreationInfo::new(0, "none", None);
+        assert_eq!(Ok(unlabeled.clone()), CreationInfo::decode(&unlabeled.encode()));
+    }
+
+    #[test]
+    fn test_unknown_fields_are_kept() {
+        // a block written by a later release with a field this one doesn't know
+        let mut fields: BTreeMap<String, String> = BTreeMap::new();
+        fields.insert(CRATE_VERSION.to_string(), "9.0.0".to_string());
+        fields.insert(CREATED_AT.to_string(), "42".to_string());
+        fields.insert(COMPRESSION.to_string(), "none".to_string());
+        fields.insert("checksum".to_string(), "crc32c".to_string());
+        let bytes = bincode::serialize(&fields).unwrap();
+
+        let info = CreationInfo::decode(&bytes).unwrap();
+        assert_eq!(("9.0.0", 42, None), (info.crate_version.as_str(), info.created_at, info.label.as_deref()));
+        assert_eq!(Some(&"crc32c".to_string()), info.extra.get("checksum"));
+        assert_eq!(bytes, info.encode());
+    }
+
+    #[test]
+    fn test_decode_rejects_damaged_blocks() {
+        assert!(matches!(CreationInfo::decode(&[1, 2, 3]), Err(KVError::Corruption(_))));
+
+        let mut fields: BTreeMap<String, String> = BTreeMap::new();
+        fields.insert(CRATE_VERSION.to_string(), "0.1.0".to_string());
+        fields.insert(CREATED_AT.to_string(), "yesterday".to_string());
+        fields.insert(COMPRESSION.to_string(), "none".to_string());
+        assert!(matches!(CreationInfo::decode(&bincode::serialize(&fields).unwrap()), Err(KVError::Corruption(_))));
+
+        fields.insert(CREATED_AT.to_string(), "1".to_string());
+        fields.remove(COMPRESSION);
+        assert_eq!(
+            Err(KVError::Corruption("creation info has no compression".to_string())),
+            CreationInfo::decode(&bincode::serialize(&fields).unwrap())
+        );
+    }
+}
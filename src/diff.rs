@@ -0,0 +1,155 @@
+use std::cmp::Ordering;
+use std::fmt::Debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::persist::{KVError, Persister};
+
+/// Options of `diff`
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// maximum number of keys kept as examples for each kind of difference, the counts of the
+    /// report are exact regardless
+    pub max_examples: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { max_examples: 100 }
+    }
+}
+
+/// Differences between two stores, examples are listed in key order
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffReport<K> {
+    pub only_in_a: Vec<K>,
+    pub only_in_a_count: usize,
+    pub only_in_b: Vec<K>,
+    pub only_in_b_count: usize,
+    pub differing: Vec<K>,
+    pub differing_count: usize,
+}
+
+impl<K> DiffReport<K> {
+    fn new() -> Self {
+        Self {
+            only_in_a: vec![],
+            only_in_a_count: 0,
+            only_in_b: vec![],
+            only_in_b_count: 0,
+            differing: vec![],
+            differing_count: 0,
+        }
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.only_in_a_count == 0 && self.only_in_b_count == 0 && self.differing_count == 0
+    }
+}
+
+/// Compares two stores by walking both indexes in key order. Values are only read for keys
+/// present in both stores and holding values of the same length, one pair at a time, so
+/// memory stays bounded by the number of examples kept
+pub fn diff<K>(a: &Persister<K>, b: &Persister<K>, opts: DiffOptions) -> Result<DiffReport<K>, KVError>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    let mut report = DiffReport::new();
+    let mut keys_a = a.keys().peekable();
+    let mut keys_b = b.keys().peekable();
+
+    loop {
+        let order = match (keys_a.peek(), keys_b.peek()) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(key_a), Some(key_b)) => key_a.cmp(key_b),
+        };
+
+        match order {
+            Ordering::Less => {
+                let key = keys_a.next().unwrap();
+                record(&mut report.only_in_a, &mut report.only_in_a_count, key, &opts);
+            },
+            Ordering::Greater => {
+                let key = keys_b.next().unwrap();
+                record(&mut report.only_in_b, &mut report.only_in_b_count, key, &opts);
+            },
+            Ordering::Equal => {
+                let key = keys_a.next().unwrap();
+                keys_b.next();
+
+                let same = a.value_len(key)? == b.value_len(key)? && a.get_value(key)? == b.get_value(key)?;
+                if !same {
+                    record(&mut report.differing, &mut report.differing_count, key, &opts);
+                }
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+fn record<K: Clone>(examples: &mut Vec<K>, count: &mut usize, key: &K, opts: &DiffOptions) {
+    if examples.len() < opts.max_examples {
+        examples.push(key.clone());
+    }
+    *count += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_persister(entries: &[(&str, &str)]) -> Persister<String> {
+        let mut persister = Persister::new_temporary().unwrap();
+        for (key, value) in entries {
+            persister.insert_kv(&key.to_string(), value.as_bytes()).unwrap();
+        }
+
+        persister
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let entries = [("a", "1"), ("b", "22"), ("c", ""), ("d", "4444")];
+        let a = new_persister(&entries);
+        // same contents laid out differently in the db file
+        let mut b = new_persister(&[("d", "4444"), ("x", "tmp"), ("c", ""), ("b", "22"), ("a", "1")]);
+        b.delete_kv(&"x".to_string()).unwrap();
+
+        let report = diff(&a, &b, DiffOptions::default()).unwrap();
+        assert!(report.is_identical());
+        assert_eq!(DiffReport::new(), report);
+        assert!(diff(&new_persister(&[]), &new_persister(&[]), DiffOptions::default()).unwrap().is_identical());
+    }
+
+    #[test]
+    fn test_diff_modified_clone() {
+        let a = new_persister(&[("a", "1"), ("b", "22"), ("c", "333"), ("d", "4444")]);
+        let mut b = new_persister(&[("a", "1"), ("b", "22"), ("c", "333"), ("d", "4444")]);
+        b.insert_kv(&"e".to_string(), b"55555").unwrap();
+        b.delete_kv(&"b".to_string()).unwrap();
+        b.update_value(&"c".to_string(), b"3x3").unwrap();
+
+        let report = diff(&a, &b, DiffOptions::default()).unwrap();
+        assert_eq!(DiffReport {
+            only_in_a: vec!["b".to_string()],
+            only_in_a_count: 1,
+            only_in_b: vec!["e".to_string()],
+            only_in_b_count: 1,
+            differing: vec!["c".to_string()],
+            differing_count: 1,
+        }, report);
+        assert!(!report.is_identical());
+    }
+
+    #[test]
+    fn test_diff_example_cap() {
+        let a = new_persister(&[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4"), ("e", "5")]);
+        let b = new_persister(&[("a", "x"), ("b", "yy"), ("c", "3"), ("d", "z"), ("f", "6")]);
+
+        let report = diff(&a, &b, DiffOptions { max_examples: 2 }).unwrap();
+        assert_eq!(vec!["a".to_string(), "b".to_string()], report.differing);
+        assert_eq!(3, report.differing_count);
+        assert_eq!(vec!["e".to_string()], report.only_in_a);
+        assert_eq!(vec!["f".to_string()], report.only_in_b);
+    }
+}
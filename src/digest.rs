@@ -0,0 +1,91 @@
+use sha2::{Digest, Sha256};
+
+pub const DIGEST_LEN: usize = 32;
+
+// domains keep the digest of a whole store apart from the hash of a single entry
+pub const CONTENT_DOMAIN: &[u8] = b"embedkv/content/v1";
+pub const ENTRY_DOMAIN: &[u8] = b"embedkv/entry/v1";
+
+/// SHA-256 over a sequence of entries, each one framed by the lengths of its key and value so
+/// that no two different sequences hash the same bytes
+pub(crate) struct DigestBuilder {
+    hasher: Sha256,
+}
+
+impl DigestBuilder {
+    pub fn new(domain: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(domain);
+
+        Self { hasher }
+    }
+
+    /// Starts an entry, its `value_len` bytes must follow through `update`
+    pub fn entry(&mut self, key: &[u8], value_len: usize) {
+        self.hasher.update((key.len() as u64).to_le_bytes());
+        self.hasher.update(key);
+        self.hasher.update((value_len as u64).to_le_bytes());
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.hasher.update(bytes);
+    }
+
+    pub fn finish(self) -> [u8; DIGEST_LEN] {
+        self.hasher.finalize().into()
+    }
+}
+
+pub(crate) fn entry_hash(key: &[u8], value: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut builder = DigestBuilder::new(ENTRY_DOMAIN);
+    builder.entry(key, value.len());
+    builder.update(value);
+    builder.finish()
+}
+
+/// Adds or removes an entry hash from a set digest, both are the same operation
+pub(crate) fn toggle(digest: &mut [u8; DIGEST_LEN], entry: &[u8; DIGEST_LEN]) {
+    for (byte, entry_byte) in digest.iter_mut().zip(entry) {
+        *byte ^= entry_byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_framing() {
+        // moving bytes between the key and the value changes the hash
+        assert_ne!(entry_hash(b"ab", b"c"), entry_hash(b"a", b"bc"));
+        assert_ne!(entry_hash(b"", b""), entry_hash(b"", b"\0"));
+        assert_eq!(entry_hash(b"ab", b"c"), entry_hash(b"ab", b"c"));
+
+        // streaming the value gives the same hash as passing it whole
+        let mut builder = DigestBuilder::new(ENTRY_DOMAIN);
+        builder.entry(b"key", 6);
+        builder.update(b"val");
+        builder.update(b"ue!");
+        assert_eq!(entry_hash(b"key", b"value!"), builder.finish());
+    }
+
+    #[test]
+    fn test_toggle() {
+        let mut digest = [0; DIGEST_LEN];
+        let a = entry_hash(b"a", b"1");
+        let b = entry_hash(b"b", b"2");
+
+        toggle(&mut digest, &a);
+        toggle(&mut digest, &b);
+        let both = digest;
+
+        // the order entries are added in doesn't matter, removing one takes it out
+        let mut other = [0; DIGEST_LEN];
+        toggle(&mut other, &b);
+        toggle(&mut other, &a);
+        assert_eq!(both, other);
+
+        toggle(&mut digest, &a);
+        assert_eq!(b, digest);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+use crate::persist::KVError;
+
+pub const DEFAULT_ERROR_LOG_CAPACITY: usize = 16;
+
+/// Error returned by a public `Persister` method. Records never hold key material
+#[derive(Debug, Clone)]
+pub struct ErrorRecord {
+    pub timestamp: SystemTime,
+    pub operation: &'static str,
+    pub error: String,
+}
+
+/// Bounded ring of the most recent errors, the oldest record is dropped once the capacity is
+/// reached. A capacity of 0 disables the log
+pub(crate) struct ErrorLog {
+    capacity: usize,
+    records: Mutex<VecDeque<ErrorRecord>>,
+}
+
+impl ErrorLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn push(&self, operation: &'static str, error: &KVError) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.lock();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(ErrorRecord {
+            timestamp: SystemTime::now(),
+            operation,
+            error: format!("{:?}", error),
+        });
+    }
+
+    /// Records from the oldest to the most recent
+    pub fn records(&self) -> Vec<ErrorRecord> {
+        self.lock().iter().cloned().collect()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        let records = self.records.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while records.len() > capacity {
+            records.pop_front();
+        }
+        records.shrink_to(capacity);
+        self.capacity = capacity;
+    }
+
+    // a panic while holding the lock can't leave the ring in a broken state, so poisoning is
+    // ignored
+    fn lock(&self) -> MutexGuard<'_, VecDeque<ErrorRecord>> {
+        self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operations(log: &ErrorLog) -> Vec<&'static str> {
+        log.records().iter().map(|record| record.operation).collect()
+    }
+
+    #[test]
+    fn test_push_drops_oldest() {
+        let log = ErrorLog::new(3);
+        for operation in ["op1", "op2", "op3", "op4", "op5"] {
+            log.push(operation, &KVError::KeyDoesNotExist);
+        }
+
+        assert_eq!(vec!["op3", "op4", "op5"], operations(&log));
+        assert_eq!("KeyDoesNotExist", log.records()[0].error);
+
+        log.clear();
+        assert!(log.records().is_empty());
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let mut log = ErrorLog::new(4);
+        for operation in ["op1", "op2", "op3", "op4"] {
+            log.push(operation, &KVError::KeyAlreadyExist);
+        }
+
+        log.set_capacity(2);
+        assert_eq!(vec!["op3", "op4"], operations(&log));
+
+        log.set_capacity(0);
+        log.push("op5", &KVError::KeyAlreadyExist);
+        assert!(log.records().is_empty());
+    }
+}
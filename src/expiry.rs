@@ -0,0 +1,47 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Source of the current time used to expire keys, see `Persister::set_clock`
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the unix epoch
+    fn now_millis(&self) -> u64;
+}
+
+/// Reads the time from the system clock, the clock of every store by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        // a clock set before the epoch expires nothing rather than failing every write
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+/// Outcome of `Persister::purge_expired`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PurgeReport {
+    /// expired keys removed from the index
+    pub removed: usize,
+    /// bytes of the db file released by the removed keys
+    pub freed: usize,
+}
+
+/// Expiration timestamp of a key written at `now` with the given time to live
+pub(crate) fn deadline(now: u64, ttl: Duration) -> u64 {
+    now.saturating_add(ttl.as_millis().min(u64::MAX as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadline() {
+        assert_eq!(1_500, deadline(1_000, Duration::from_millis(500)));
+        assert_eq!(1_000, deadline(1_000, Duration::ZERO));
+        assert_eq!(u64::MAX, deadline(1_000, Duration::MAX));
+        assert!(SystemClock.now_millis() > 0);
+    }
+}
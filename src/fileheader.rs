@@ -1,39 +1,322 @@
 use std::fs::{File, OpenOptions};
 use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
+use crate::compression::Compression;
+use crate::expiry::{Clock, SystemClock};
+use crate::format::{FileKind, FormatError, FormatHeader, FORMAT_HEADER_LEN};
+use crate::positional::PositionalFile;
 
 pub struct FileHeader {
     pub(crate) db_file: File,
     pub(crate) index_file: File,
+    // write-ahead log of the changes not yet checkpointed, headers built by hand go without one
+    pub(crate) wal_file: Option<File>,
+    // bytes of the format header at the start of the db and index files, cursors and index
+    // records are relative to the end of it. Headers built by hand go without one
+    pub(crate) offset: u64,
+}
+
+/// How `FileHeader::new` opens the files of a datastore
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct OpenMode {
+    pub create_if_missing: bool,
+    pub truncate: bool,
+    pub read_only: bool,
+    pub compression: Compression,
+}
+
+impl Default for OpenMode {
+    fn default() -> Self {
+        Self { create_if_missing: true, truncate: false, read_only: false, compression: Compression::None }
+    }
 }
 
 impl FileHeader {
-    pub fn new(datastore_name: Option<String>) -> Result<Self, std::io::Error> {
+    /// Opens the files of the datastore as told by `mode`. Read-only datastores are never created
+    /// nor truncated, `mode.truncate` is ignored and missing files fail with `ErrorKind::NotFound`.
+    /// Files created with compression can only be opened with compression and the other way
+    /// round, see `FormatHeader::check_compression`
+    pub fn new(datastore_name: Option<String>, mode: OpenMode) -> Result<Self, std::io::Error> {
         let mut name = Uuid::new_v4().to_string();
         if let Some(ds_name) = datastore_name {
             name = ds_name
         }
 
-        let db_file_handler = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .truncate(true) // todo(): remove this one
-            .open(&name);
+        let mut options = OpenOptions::new();
+        if mode.read_only {
+            options.read(true);
+        } else {
+            options.write(true).read(true).create(mode.create_if_missing).truncate(mode.truncate);
+        }
+
+        Self::open(&name, &options, mode)
+    }
+
+    /// Creates the files of a fresh datastore, fails with `ErrorKind::AlreadyExists` if any of
+    /// them is already present
+    pub fn create_new(datastore_name: &str, compression: Compression) -> Result<Self, std::io::Error> {
+        let (db_path, index_path, wal_path) = Self::paths(datastore_name);
+        if db_path.exists() || index_path.exists() || wal_path.exists() {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("datastore {} already exists", datastore_name)));
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).read(true).create_new(true);
+
+        Self::open(datastore_name, &options, OpenMode { compression, ..OpenMode::default() })
+    }
+
+    /// Backs a fresh datastore with anonymous temporary files, which are never visible in the
+    /// filesystem and go away once the last handle to them is closed
+    pub fn temporary(compression: Compression) -> Result<Self, std::io::Error> {
+        let db_file = tempfile::tempfile()?;
+        let index_file = tempfile::tempfile()?;
+        let created_at = SystemClock.now_millis();
+        Self::check_format(&db_file, FileKind::Db, created_at, true, compression)?;
+        Self::check_format(&index_file, FileKind::Index, created_at, true, compression)?;
+
+        Ok(Self {
+            db_file,
+            index_file,
+            wal_file: Some(tempfile::tempfile()?),
+            offset: FORMAT_HEADER_LEN,
+        })
+    }
+
+    /// Deletes the files of the datastore, a missing file is not an error
+    pub fn remove_files(datastore_name: &str) -> Result<(), std::io::Error> {
+        let (db_path, index_path, wal_path) = Self::paths(datastore_name);
+        for path in [db_path, index_path, wal_path] {
+            match std::fs::remove_file(path) {
+                Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renames the files of the datastore, the db file last: the datastore only shows up under
+    /// its new name once all its files are there
+    pub fn rename_files(from: &str, to: &str) -> Result<(), std::io::Error> {
+        let (from_db, from_index, from_wal) = Self::paths(from);
+        let (to_db, to_index, to_wal) = Self::paths(to);
+        if from_wal.exists() {
+            std::fs::rename(from_wal, to_wal)?;
+        }
+        std::fs::rename(from_index, to_index)?;
+        std::fs::rename(from_db, to_db)
+    }
+
+    /// Flushes both files and syncs their data to disk
+    pub fn sync_data(&self) -> Result<(), std::io::Error> {
+        Self::sync(&self.db_file)?;
+        Self::sync(&self.index_file)
+    }
+
+    /// Syncs the data of the db file alone
+    pub fn sync_db(&self) -> Result<(), std::io::Error> {
+        Self::sync(&self.db_file)
+    }
+
+    /// Syncs the write-ahead log, if there is one
+    pub fn sync_wal(&self) -> Result<(), std::io::Error> {
+        match self.wal_file.as_ref() {
+            Some(wal_file) => Self::sync(wal_file),
+            None => Ok(()),
+        }
+    }
+
+    /// Empties the write-ahead log, once everything it holds is durable in the other files. The
+    /// log is left for `sync_wal` to sync
+    pub fn truncate_wal(&self) -> Result<(), std::io::Error> {
+        match self.wal_file.as_ref() {
+            Some(wal_file) => wal_file.set_len(0),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the bytes of a slot at `cursor` of the db file
+    pub fn write_data_at(&self, data: &[u8], cursor: usize) -> Result<(), std::io::Error> {
+        Self::write_at(&self.db_file, FileKind::Db, data, self.offset + cursor as u64)
+    }
+
+    /// Appends encoded records to the index file. A failed append is cut back to the previous
+    /// end of the file, a torn record would hide every record appended after it when the index
+    /// is loaded
+    pub fn append_index(&self, records: &[u8]) -> Result<(), std::io::Error> {
+        let len = self.index_file.metadata()?.len();
+        Self::write_at(&self.index_file, FileKind::Index, records, len).inspect_err(|_| {
+            let _ = self.index_file.set_len(len);
+        })
+    }
+
+    /// Reads the bytes of a slot at `cursor` of the db file, fails with
+    /// `ErrorKind::UnexpectedEof` if the file ends before the buffer is filled
+    pub fn read_data_at(&self, buffer: &mut [u8], cursor: usize) -> Result<(), std::io::Error> {
+        self.db_file.read_exact_at(buffer, self.offset + cursor as u64)
+    }
+
+    /// Length of the data in the db file, past the format header
+    pub fn data_len(&self) -> Result<u64, std::io::Error> {
+        Ok(self.db_file.metadata()?.len().saturating_sub(self.offset))
+    }
+
+    /// Truncates or extends the db file so the data is `len` bytes long
+    pub fn set_data_len(&self, len: u64) -> Result<(), std::io::Error> {
+        self.db_file.set_len(self.offset + len)
+    }
+
+    /// Duplicates the handles of the files
+    #[cfg(feature = "mmap")]
+    pub fn try_clone(&self) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            db_file: self.db_file.try_clone()?,
+            index_file: self.index_file.try_clone()?,
+            wal_file: self.wal_file.as_ref().map(File::try_clone).transpose()?,
+            offset: self.offset,
+        })
+    }
+
+    // the index file and the write-ahead log live next to the db file, prefixed with "index_"
+    // and "wal_"
+    fn paths(datastore_name: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let db_path = PathBuf::from(datastore_name);
+        let file_name = db_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        let index_path = db_path.with_file_name(format!("index_{}", file_name));
+        let wal_path = db_path.with_file_name(format!("wal_{}", file_name));
+
+        (db_path, index_path, wal_path)
+    }
+
+    // the write-ahead log is created whenever the datastore is writable and emptied along with
+    // the other files, a read-only datastore goes without one if it has none yet. The db file
+    // is checked before the index file is opened, so no index file is created next to a file
+    // that isn't a datastore
+    fn open(datastore_name: &str, options: &OpenOptions, mode: OpenMode) -> Result<Self, std::io::Error> {
+        let (db_path, index_path, wal_path) = Self::paths(datastore_name);
+        let created_at = SystemClock.now_millis();
+
+        let db_file = Self::open_checked(&db_path, FileKind::Db, options, mode, created_at)?;
+        let index_file = Self::open_checked(&index_path, FileKind::Index, options, mode, created_at)?;
+        let wal_file = match mode.read_only {
+            true => match OpenOptions::new().read(true).open(&wal_path) {
+                Ok(wal_file) => Some(wal_file),
+                Err(error) if error.kind() == ErrorKind::NotFound => None,
+                Err(error) => return Err(error),
+            },
+            false => Some(OpenOptions::new().write(true).read(true).create(true).truncate(mode.truncate).open(&wal_path)?),
+        };
+
+        Ok(Self {
+            db_file,
+            index_file,
+            wal_file,
+            offset: FORMAT_HEADER_LEN,
+        })
+    }
+
+    // every write of values to the db file and of records to the index file goes through here,
+    // tests can cut it short
+    #[cfg_attr(not(test), allow(unused_variables))]
+    fn write_at(file: &File, kind: FileKind, data: &[u8], offset: u64) -> Result<(), std::io::Error> {
+        #[cfg(test)]
+        if let Some(written) = faults::take_torn_write(kind) {
+            file.write_all_at(&data[..written.min(data.len())], offset)?;
+            return Err(Error::other("torn write injected by a test"));
+        }
+
+        file.write_all_at(data, offset)
+    }
+
+    // every sync of the files goes through here, tests count them and can fail them
+    fn sync(file: &File) -> Result<(), std::io::Error> {
+        #[cfg(test)]
+        if faults::count_sync() {
+            return Err(Error::other("sync failure injected by a test"));
+        }
+
+        file.sync_data()
+    }
+
+    // a directory can be opened read-only on some platforms, it is told apart up front
+    fn open_checked(path: &Path, kind: FileKind, options: &OpenOptions, mode: OpenMode, created_at: u64) -> Result<File, std::io::Error> {
+        if path.is_dir() {
+            return Err(FormatError::NotADatastore(format!("{} file {:?} is a directory", kind.name(), path)).into());
+        }
+
+        let file = options.open(path)?;
+        let fresh = !mode.read_only && (mode.create_if_missing || mode.truncate);
+        Self::check_format(&file, kind, created_at, fresh, mode.compression)?;
 
-        let index_file_handler = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .truncate(true) // todo(): remove this one
-            .open(format!("{}_{}", "index".to_string(), &name));
+        Ok(file)
+    }
 
-        match (db_file_handler, index_file_handler) {
-            (Ok(db_file), Ok(index_file)) => Ok(Self {
-                db_file,
-                index_file,
-            }),
-            (_, _) => Err(Error::new(ErrorKind::Other, "The key introduced was not registered")),
+    // validates the format header of the file and the compression it is opened with, or writes
+    // it to an empty file, which holds no data that could be misread. An empty file is only
+    // taken for a new datastore when one could be created, otherwise it isn't a datastore
+    fn check_format(file: &File, kind: FileKind, created_at: u64, fresh: bool, compression: Compression) -> Result<(), std::io::Error> {
+        if file.metadata()?.len() == 0 && fresh {
+            return FormatHeader::new(kind, created_at, compression).write_to(file);
         }
+
+        Ok(FormatHeader::read_from(file, kind)?.check_compression(compression)?)
+    }
+}
+
+/// Failures the tests inject into the writes to the db and index files and into the syncs of
+/// the files, which are also counted. They only hit the writes and syncs of the thread that
+/// injected them, so tests running in parallel don't see each other's failures
+#[cfg(test)]
+pub(crate) mod faults {
+    use std::cell::Cell;
+    use crate::format::FileKind;
+
+    thread_local! {
+        // bytes let through by the next write to the db file and to the index file
+        static TORN_WRITES: Cell<[Option<usize>; 2]> = const { Cell::new([None; 2]) };
+        // syncs of any of the files so far
+        static SYNCS: Cell<usize> = const { Cell::new(0) };
+        // upcoming syncs of any of the files that fail
+        static FAILED_SYNCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Lets only the first `written` bytes of the next write to the file through, then fails it
+    pub fn tear_next_write(kind: FileKind, written: usize) {
+        TORN_WRITES.with(|torn| {
+            let mut writes = torn.get();
+            writes[kind as usize] = Some(written);
+            torn.set(writes);
+        });
+    }
+
+    pub(super) fn take_torn_write(kind: FileKind) -> Option<usize> {
+        TORN_WRITES.with(|torn| {
+            let mut writes = torn.get();
+            let written = writes[kind as usize].take();
+            torn.set(writes);
+            written
+        })
+    }
+
+    /// Number of syncs of the files made by the thread so far
+    pub fn syncs() -> usize {
+        SYNCS.with(Cell::get)
     }
-}
\ No newline at end of file
+
+    /// Fails the next `count` syncs of any of the files, without syncing anything
+    pub fn fail_next_syncs(count: usize) {
+        FAILED_SYNCS.with(|failed| failed.set(count));
+    }
+
+    // counts a sync, telling whether it must fail
+    pub(super) fn count_sync() -> bool {
+        SYNCS.with(|syncs| syncs.set(syncs.get() + 1));
+        FAILED_SYNCS.with(|failed| {
+            let fails = failed.get() > 0;
+            failed.set(failed.get().saturating_sub(1));
+            fails
+        })
+    }
+}
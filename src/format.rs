@@ -0,0 +1,281 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use crate::compression::Compression;
+use crate::positional::PositionalFile;
+
+const MAGIC: [u8; 4] = *b"EKV1";
+
+/// Version of the layout of the files, bumped on every incompatible change
+pub(crate) const FORMAT_VERSION: u16 = 1;
+
+/// Bytes taken by the format header at the start of the db and index files, the data of both
+/// files starts right after it
+pub(crate) const FORMAT_HEADER_LEN: u64 = 32;
+
+// bits of the flags naming the algorithm the values were compressed with when the store was
+// created, 0 when they are stored as given
+const COMPRESSION_FLAGS: u32 = 0b11;
+const LZ4_FLAG: u32 = 1;
+const ZSTD_FLAG: u32 = 2;
+
+// optional features a reader must understand to read the files
+const KNOWN_FLAGS: u32 = COMPRESSION_FLAGS;
+
+/// File of the datastore a format header is written to, so the files can't be swapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileKind {
+    Db = 0,
+    Index = 1,
+}
+
+/// Header identifying a file of the datastore.
+///
+/// Layout (integers in little endian):
+///   [magic: 4 bytes = "EKV1"][version: u16][kind: u8][reserved: u8][flags: u32]
+///   [created_at: u64, milliseconds since the unix epoch][reserved: 12 bytes]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FormatHeader {
+    pub version: u16,
+    pub kind: FileKind,
+    pub flags: u32,
+    pub created_at: u64,
+}
+
+/// Reason a file can't be read as a file of the datastore, carried inside the `std::io::Error`
+/// returned when the files are opened and turned into the matching `KVError`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FormatError {
+    /// the file wasn't written by the store: no header, an empty file or a directory
+    NotADatastore(String),
+    /// the file starts like a file of the datastore but its header can't be read
+    Corrupt(String),
+    /// a file of the datastore that can't be opened with the options given
+    Invalid(String),
+    UnsupportedVersion(u16),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::NotADatastore(reason) => write!(f, "not a datastore: {}", reason),
+            FormatError::Corrupt(reason) => write!(f, "corrupt datastore: {}", reason),
+            FormatError::Invalid(reason) => write!(f, "invalid format: {}", reason),
+            FormatError::UnsupportedVersion(version) => write!(f, "unsupported format version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<FormatError> for Error {
+    fn from(format_error: FormatError) -> Self {
+        Error::new(ErrorKind::InvalidData, format_error)
+    }
+}
+
+impl FormatHeader {
+    pub fn new(kind: FileKind, created_at: u64, compression: Compression) -> Self {
+        let flags = match compression {
+            Compression::None => 0,
+            #[cfg(feature = "compression")]
+            Compression::Lz4 => LZ4_FLAG,
+            #[cfg(feature = "compression")]
+            Compression::Zstd { .. } => ZSTD_FLAG,
+        };
+
+        Self { version: FORMAT_VERSION, kind, flags, created_at }
+    }
+
+    pub fn encode(&self) -> [u8; FORMAT_HEADER_LEN as usize] {
+        let mut bytes = [0u8; FORMAT_HEADER_LEN as usize];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.to_le_bytes());
+        bytes[6] = self.kind as u8;
+        bytes[8..12].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.created_at.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes the header of a file expected to be of the given kind. A file cut inside the
+    /// header after the magic number is corrupt, one without the magic number isn't a file of
+    /// a datastore
+    pub fn decode(bytes: &[u8], kind: FileKind) -> Result<Self, FormatError> {
+        if bytes.len() < MAGIC.len() || bytes[0..4] != MAGIC {
+            return Err(FormatError::NotADatastore(format!("{} file has no datastore header", kind.name())));
+        }
+        if bytes.len() < FORMAT_HEADER_LEN as usize {
+            return Err(FormatError::Corrupt(format!("{} file is too short for a datastore header", kind.name())));
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+
+        let found = match bytes[6] {
+            0 => FileKind::Db,
+            1 => FileKind::Index,
+            unknown => return Err(FormatError::Corrupt(format!("unknown file kind {}", unknown))),
+        };
+        // swapped files are the files of another datastore or of a copy made by hand
+        if found != kind {
+            return Err(FormatError::NotADatastore(format!("{} file found where the {} file was expected", found.name(), kind.name())));
+        }
+
+        let flags = u32::from_le_bytes(bytes[8..12].try_into().unwrap_or_default());
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(FormatError::Corrupt(format!("unknown flags {:#x}", flags & !KNOWN_FLAGS)));
+        }
+        if flags & COMPRESSION_FLAGS == COMPRESSION_FLAGS {
+            return Err(FormatError::Corrupt(format!("unknown compression {}", flags & COMPRESSION_FLAGS)));
+        }
+
+        let created_at = u64::from_le_bytes(bytes[12..20].try_into().unwrap_or_default());
+        Ok(Self { version, kind, flags, created_at })
+    }
+
+    /// Checks that values written with `compression` can be read along with the ones already in
+    /// the file: they must be framed if and only if the store was created with compression. The
+    /// algorithm may differ, every framed value names the one it was compressed with
+    pub fn check_compression(&self, compression: Compression) -> Result<(), FormatError> {
+        let requested = Self::new(self.kind, 0, compression).flags;
+        if (self.flags & COMPRESSION_FLAGS != 0) == (requested != 0) {
+            return Ok(());
+        }
+
+        Err(FormatError::Invalid(format!(
+            "{} file was created with compression {}, opened with {}",
+            self.kind.name(), compression_name(self.flags), compression_name(requested),
+        )))
+    }
+
+    /// Writes the header at the start of `file`
+    pub fn write_to(&self, file: &File) -> Result<(), Error> {
+        file.write_all_at(&self.encode(), 0)
+    }
+
+    /// Reads and checks the header at the start of `file`, an empty file isn't a file of a
+    /// datastore
+    pub fn read_from(file: &File, kind: FileKind) -> Result<Self, Error> {
+        let len = file.metadata()?.len().min(FORMAT_HEADER_LEN) as usize;
+        if len == 0 {
+            return Err(FormatError::NotADatastore(format!("{} file is empty", kind.name())).into());
+        }
+
+        let mut bytes = [0u8; FORMAT_HEADER_LEN as usize];
+        file.read_exact_at(&mut bytes[..len], 0)?;
+        Ok(Self::decode(&bytes[..len], kind)?)
+    }
+}
+
+/// Whether the file starts with the magic number of the format header. Files written before
+/// the header was introduced start right with the data
+pub(crate) fn has_magic(file: &File) -> Result<bool, Error> {
+    let mut magic = [0u8; 4];
+    match file.read_exact_at(&mut magic, 0) {
+        Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error),
+        Ok(()) => Ok(magic == MAGIC),
+    }
+}
+
+fn compression_name(flags: u32) -> &'static str {
+    match flags & COMPRESSION_FLAGS {
+        LZ4_FLAG => "lz4",
+        ZSTD_FLAG => "zstd",
+        _ => "none",
+    }
+}
+
+impl FileKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileKind::Db => "db",
+            FileKind::Index => "index",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let header = FormatHeader::new(FileKind::Index, 1_700_000_000_000, Compression::None);
+        let bytes = header.encode();
+        assert_eq!(b"EKV1", &bytes[0..4]);
+        assert_eq!(Ok(header), FormatHeader::decode(&bytes, FileKind::Index));
+    }
+
+    #[test]
+    fn test_decode_rejects_foreign_headers() {
+        let bytes = FormatHeader::new(FileKind::Db, 0, Compression::None).encode();
+        assert!(matches!(FormatHeader::decode(&bytes, FileKind::Index), Err(FormatError::NotADatastore(_))));
+        assert!(matches!(FormatHeader::decode(&bytes[..3], FileKind::Db), Err(FormatError::NotADatastore(_))));
+
+        let mut magic = bytes;
+        magic[0] = b'X';
+        assert!(matches!(FormatHeader::decode(&magic, FileKind::Db), Err(FormatError::NotADatastore(_))));
+
+        let mut version = bytes;
+        version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(Err(FormatError::UnsupportedVersion(FORMAT_VERSION + 1)), FormatHeader::decode(&version, FileKind::Db));
+    }
+
+    #[test]
+    fn test_decode_rejects_corrupt_headers() {
+        // the magic number is there but the rest of the header is cut or damaged
+        let bytes = FormatHeader::new(FileKind::Db, 0, Compression::None).encode();
+        assert_eq!(
+            Err(FormatError::Corrupt("db file is too short for a datastore header".to_string())),
+            FormatHeader::decode(&bytes[..16], FileKind::Db)
+        );
+
+        let mut kind = bytes;
+        kind[6] = 7;
+        assert_eq!(Err(FormatError::Corrupt("unknown file kind 7".to_string())), FormatHeader::decode(&kind, FileKind::Db));
+
+        let mut flags = bytes;
+        flags[8] = 4;
+        assert!(matches!(FormatHeader::decode(&flags, FileKind::Db), Err(FormatError::Corrupt(_))));
+        flags[8] = 3;
+        assert_eq!(Err(FormatError::Corrupt("unknown compression 3".to_string())), FormatHeader::decode(&flags, FileKind::Db));
+    }
+
+    #[test]
+    fn test_check_compression() {
+        let plain = FormatHeader::new(FileKind::Db, 0, Compression::None);
+        assert_eq!(Ok(()), plain.check_compression(Compression::None));
+
+        // a store created with lz4 by a build with compression
+        let mut bytes = plain.encode();
+        bytes[8] = 1;
+        let lz4 = FormatHeader::decode(&bytes, FileKind::Db).unwrap();
+        assert_eq!(
+            Err(FormatError::Invalid("db file was created with compression lz4, opened with none".to_string())),
+            lz4.check_compression(Compression::None)
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_check_compression_algorithms() {
+        let zstd = FormatHeader::new(FileKind::Index, 0, Compression::Zstd { level: 3 });
+        assert_eq!(2, zstd.flags);
+        assert_eq!(Ok(zstd.clone()), FormatHeader::decode(&zstd.encode(), FileKind::Index));
+
+        // the algorithm can change, every framed value names its own
+        assert_eq!(Ok(()), zstd.check_compression(Compression::Lz4));
+        assert_eq!(
+            Err(FormatError::Invalid("index file was created with compression zstd, opened with none".to_string())),
+            zstd.check_compression(Compression::None)
+        );
+        let plain = FormatHeader::new(FileKind::Db, 0, Compression::None);
+        assert_eq!(
+            Err(FormatError::Invalid("db file was created with compression none, opened with lz4".to_string())),
+            plain.check_compression(Compression::Lz4)
+        );
+    }
+}
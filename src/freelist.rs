@@ -1,102 +1,183 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
 use crate::slot::Slot;
 
+/// Which free slot is handed out when several of them can hold the requested space
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllocationStrategy {
+    /// the smallest slot that fits, keeps big slots around for big values
+    #[default]
+    BestFit,
+    /// the slot that fits with the lowest cursor, keeps the data packed at the start of the file
+    FirstFit,
+    /// the biggest slot, leaves leftovers big enough to be reused
+    WorstFit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct FreeList {
-    list: Vec<Slot>,
+    list: Vec<Slot>, // sorted by space and then by cursor
+    by_cursor: BTreeSet<(usize, usize)>, // the same slots as (cursor, space), to find neighbours
     total_free_space: usize,
+    strategy: AllocationStrategy,
 }
 
 impl FreeList {
     pub fn new() -> Self {
+        Self::with_strategy(AllocationStrategy::default())
+    }
+
+    pub fn with_strategy(strategy: AllocationStrategy) -> Self {
         Self {
             list: Vec::new(),
+            by_cursor: BTreeSet::new(),
             total_free_space: 0,
+            strategy,
         }
     }
 
-    pub fn new_from_index<K>(mut used_slot_list: Vec<&Slot>) -> Self {
+    pub fn strategy(&self) -> AllocationStrategy {
+        self.strategy
+    }
+
+    pub fn set_strategy(&mut self, strategy: AllocationStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// Rebuilds the list from the slots in use, every hole between them becomes a free slot.
+    /// Also returns the end of the last slot in use: the space after it is left out of the
+    /// list, new values claim it from the end of the data instead
+    pub fn new_from_index(mut used_slot_list: Vec<&Slot>) -> (Self, usize) {
         let mut total_free_space = 0;
 
-        // sort the elements by cursor
-        used_slot_list.sort_by(|a, b| a.cursor.cmp(&b.cursor));
+        // sort the elements by cursor, empty slots do not occupy any space
+        used_slot_list.retain(|slot| slot.space > 0);
+        used_slot_list.sort_by_key(|slot| slot.cursor);
 
-        // get the free slots by analyzing the occupied slots
+        // get the free slots by analyzing the gaps between the occupied slots
         let mut new_list: Vec<Slot> = vec![];
-        let mut previous_slot: &Slot = &Slot{space: 0, cursor: 0};
-        for (i, current_slot) in used_slot_list.iter().enumerate() {
-            if i == 0 && current_slot.cursor > 0 {
-                new_list.push(Slot{space: current_slot.cursor-1, cursor: 0});
-                total_free_space += current_slot.cursor-1;
-            }
-
-            if i > 0 && current_slot.cursor != (previous_slot.space+previous_slot.cursor+1) {
+        let mut previous_end = 0;
+        for current_slot in used_slot_list.iter() {
+            if current_slot.cursor > previous_end {
                 new_list.push(Slot{
-                    space: current_slot.cursor-previous_slot.cursor,
-                    cursor: previous_slot.cursor+previous_slot.space+1
+                    space: current_slot.cursor - previous_end,
+                    cursor: previous_end,
                 });
-                total_free_space += current_slot.cursor-previous_slot.cursor;
+                total_free_space += current_slot.cursor - previous_end;
             }
 
-            // save the slot for the next iteration
-            previous_slot = current_slot;
+            // save the end of the slot for the next iteration
+            previous_end = previous_end.max(current_slot.cursor + current_slot.space);
         }
 
-        // return updated free list
-        return Self{
+        // return updated free list, sorted by space as the rest of the list operations expect
+        let by_cursor = new_list.iter().map(|slot| (slot.cursor, slot.space)).collect();
+        new_list.sort();
+        let free_list = Self{
             list: new_list,
+            by_cursor,
             total_free_space,
-        }
+            strategy: AllocationStrategy::default(),
+        };
+
+        (free_list, previous_end)
+    }
+
+    /// Bytes held by the free slots
+    pub fn total_free_space(&self) -> usize {
+        self.total_free_space
     }
 
+    /// Number of free slots
+    pub fn fragment_count(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Free slots, sorted by space and then by cursor
+    pub fn fragments(&self) -> &[Slot] {
+        &self.list
+    }
+
+    /// Free slots starting from `range.start` up to `range.end` excluded, sorted by cursor
+    pub fn fragments_in(&self, range: Range<usize>) -> impl Iterator<Item = Slot> + '_ {
+        self.by_cursor.range((range.start, 0)..(range.end, 0))
+            .map(|(cursor, space)| Slot { cursor: *cursor, space: *space })
+    }
+
+    pub fn contains(&self, cursor: usize, space: usize) -> bool {
+        self.by_cursor.contains(&(cursor, space))
+    }
+
+    /// Space of the biggest free slot, 0 when there is none
+    pub fn largest_fragment(&self) -> usize {
+        self.list.last().map_or(0, |slot| slot.space)
+    }
+
+    /// Inserts the free space as is, next to free neighbours or over other free slots, to build
+    /// lists in any shape
+    #[cfg(test)]
     pub fn insert_free_space(&mut self, cursor: usize, space: usize) {
-        let value = Slot { cursor, space };
-        let pos = match self.list.binary_search(&value) {
-            Ok(pos) | Err(pos) => pos,
-        };
+        self.total_free_space += space;
+        self.add(Slot { cursor, space });
+    }
+
+    /// Inserts the free space merging it with the free slots right before and after it, so no
+    /// two free slots are ever left next to each other
+    pub fn insert_and_merge_free_space(&mut self, cursor: usize, space: usize) {
+        let value = self.merge_with_neighbors(Slot { cursor, space });
 
         self.total_free_space += space;
-        self.list.insert(pos, value);
+        self.add(value);
     }
 
     pub fn retrieve_free_space(&mut self, space: usize) -> Option<usize> {
-        let space_cursor = Slot {space: space, cursor: 0};
+        let space_cursor = Slot {space, cursor: 0};
 
-        if let Some(val) = self.retrieve_equal_or_bigger_than(&space_cursor) {
-            self.total_free_space -= val.space;
-            return Some(val.cursor)
-        }
+        // the leftover of a bigger slot stays in the list, only the requested space is taken
+        let claimed = self.retrieve_equal_or_bigger_than(&space_cursor)?;
+        self.total_free_space -= space;
 
-        return None
+        Some(claimed.cursor)
     }
 
-    pub fn compact(&mut self) {
-        let mut new_list: Vec<Slot> = vec![];
-        let mut already_merged: Vec<usize> = vec![];
+    /// Takes out of the list the free slot that ends right at `end`, if any
+    pub fn retrieve_ending_at(&mut self, end: usize) -> Option<Slot> {
+        let (cursor, space) = self.ending_at(end)?;
+        let claimed = self.remove(&Slot { cursor, space });
+        self.total_free_space -= claimed.space;
 
-        // re-sort by cursor so we can execute compact() only once
-        self.list.sort_by(|a, b| a.cursor.cmp(&b.cursor));
+        Some(claimed)
+    }
 
-        // range over all the elements in the list, find all the neighbours and merge them into
-        // a single new list of free spaces. The new free space is calculated on the fly so
-        // we only need one iteration for each element
-        for (x, fs1) in self.list.iter().enumerate() {
-            if already_merged.contains(&x) {
-                continue
-            }
+    /// Returns the free slots that end right where `cursor` starts and that start right where
+    /// `cursor + space` ends, if any
+    pub fn neighbors_of(&self, cursor: usize, space: usize) -> (Option<&Slot>, Option<&Slot>) {
+        let before = self.ending_at(cursor);
+        let after = self.by_cursor.range((cursor + space, 1)..)
+            .next()
+            .filter(|(after_cursor, _)| *after_cursor == cursor + space)
+            .copied();
+
+        let in_list = |(cursor, space)| self.list.binary_search(&Slot { cursor, space }).ok().map(|pos| &self.list[pos]);
+        (before.and_then(in_list), after.and_then(in_list))
+    }
 
-            let mut tmp_fs = fs1.clone();
-            // check for neighbours and merge those that fit
-            for (y, fs2) in self.list.iter().enumerate().skip(x + 1) {
-                if  tmp_fs.is_neighbour_of(fs2) && !already_merged.contains(&y) {
-                    tmp_fs = tmp_fs.merge_with(fs2);
-                    already_merged.push(y);
-                }
-            }
+    /// Merges every run of adjacent free slots into a single slot
+    pub fn compact(&mut self) {
+        // sorted by cursor the neighbours of a free slot come right after it, so a single pass
+        // merges them all
+        self.list.sort_by_key(|slot| slot.cursor);
 
-            // append the new free space with all the spots that matched
-            new_list.push(tmp_fs);
+        let mut new_list: Vec<Slot> = vec![];
+        for slot in self.list.drain(..) {
+            match new_list.last_mut() {
+                Some(last) if last.is_neighbour_of(&slot) => *last = last.merge_with(&slot),
+                _ => new_list.push(slot),
+            }
         }
 
         // sort the list by space and replace the old free list with the already compacted list
+        self.by_cursor = new_list.iter().map(|slot| (slot.cursor, slot.space)).collect();
         new_list.sort();
         self.list = new_list;
     }
@@ -104,24 +185,19 @@ impl FreeList {
     fn retrieve_equal_or_bigger_than(&mut self, expected_amount: &Slot) -> Option<Slot> {
         let mut claimed;
 
-        // search for the first item in the list that have equal or bigger space available
-        let pos = match self.list.binary_search(expected_amount) {
-            Ok(pos) => pos,
-            Err(pos) if pos < self.list.len() => pos,
-            _ => return None,
-        };
-
+        let pos = self.position_for(expected_amount.space)?;
         claimed = self.list.remove(pos);
+        self.by_cursor.remove(&(claimed.cursor, claimed.space));
 
         // store again the free space if the space claimed has been bigger than the space
-        // that is going to be filled
+        // that is going to be filled, merging it with any free neighbour so the leftover
+        // does not end up as a separate fragment next to another free slot
         if claimed.space > expected_amount.space {
-            let free_space = Slot {
+            let free_space = self.merge_with_neighbors(Slot {
                 space: claimed.space - expected_amount.space,
                 cursor: claimed.cursor + expected_amount.space,
-            };
-
-            self.list.insert(pos, free_space);
+            });
+            self.add(free_space);
         }
 
         // update the real space that is going to be retrieved (just for correctness)
@@ -129,6 +205,73 @@ impl FreeList {
 
         Some(claimed)
     }
+
+    // position of the slot picked by the allocation strategy among the ones holding at least
+    // `space`, ties are broken by the lowest cursor
+    fn position_for(&self, space: usize) -> Option<usize> {
+        // the slots that fit are the ones after the first slot with enough space
+        let first_fit = self.list.partition_point(|slot| slot.space < space);
+        if first_fit == self.list.len() {
+            return None;
+        }
+
+        match self.strategy {
+            AllocationStrategy::BestFit => Some(first_fit),
+            AllocationStrategy::FirstFit => self.list[first_fit..].iter()
+                .enumerate()
+                .min_by_key(|(_, slot)| slot.cursor)
+                .map(|(pos, _)| first_fit + pos),
+            AllocationStrategy::WorstFit => {
+                let biggest = self.list[self.list.len() - 1].space;
+                Some(self.list.partition_point(|slot| slot.space < biggest))
+            },
+        }
+    }
+
+    /// List holding exactly the given slots and free space, consistent with each other or not
+    #[cfg(test)]
+    pub(crate) fn from_parts(mut list: Vec<Slot>, total_free_space: usize) -> Self {
+        let by_cursor = list.iter().map(|slot| (slot.cursor, slot.space)).collect();
+        list.sort();
+        Self { list, by_cursor, total_free_space, strategy: AllocationStrategy::default() }
+    }
+
+    // removes the free neighbours of the slot from the list and returns the slot merged with them
+    fn merge_with_neighbors(&mut self, slot: Slot) -> Slot {
+        let (before, after) = self.neighbors_of(slot.cursor, slot.space);
+        let (before, after) = (before.cloned(), after.cloned());
+
+        let mut merged = slot;
+        for neighbour in [before, after].into_iter().flatten() {
+            merged = merged.merge_with(&self.remove(&neighbour));
+        }
+
+        merged
+    }
+
+    // the free slot ending right at `end`, found through the slots sorted by cursor
+    fn ending_at(&self, end: usize) -> Option<(usize, usize)> {
+        self.by_cursor.range(..(end, 0))
+            .rev()
+            .find(|(_, space)| *space > 0)
+            .filter(|(cursor, space)| cursor + space == end)
+            .copied()
+    }
+
+    fn add(&mut self, slot: Slot) {
+        let pos = match self.list.binary_search(&slot) {
+            Ok(pos) | Err(pos) => pos,
+        };
+        self.by_cursor.insert((slot.cursor, slot.space));
+        self.list.insert(pos, slot);
+    }
+
+    // the slot has to be in the list
+    fn remove(&mut self, slot: &Slot) -> Slot {
+        let pos = self.list.binary_search(slot).expect("free slot missing from the list");
+        self.by_cursor.remove(&(slot.cursor, slot.space));
+        self.list.remove(pos)
+    }
 }
 
 #[cfg(test)]
@@ -137,9 +280,51 @@ mod tests {
 
     #[test]
     fn test_new_from_index() {
-        // Btree...
-        // index.values().collect()
-        assert_eq!(1, 2)
+        let from_index = |slots: &[Slot]| FreeList::new_from_index(slots.iter().collect());
+
+        // nothing stored
+        let (free_list, end) = from_index(&[]);
+        assert_eq!((free_list.list, free_list.total_free_space, end), (vec![], 0, 0));
+
+        // a single hole between two slots
+        let (free_list, end) = from_index(&[Slot {cursor: 0, space: 3}, Slot {cursor: 10, space: 5}]);
+        assert_eq!(free_list.list, vec![Slot {space: 7, cursor: 3}]);
+        assert_eq!((free_list.total_free_space, end), (7, 15));
+
+        // adjacent slots leave no hole, whatever the order they come in
+        let (free_list, end) = from_index(&[
+            Slot {cursor: 5, space: 4},
+            Slot {cursor: 0, space: 5},
+            Slot {cursor: 9, space: 1},
+        ]);
+        assert_eq!((free_list.list, free_list.total_free_space, end), (vec![], 0, 10));
+
+        // a hole before the first slot, empty values take no space
+        let (free_list, end) = from_index(&[
+            Slot {cursor: 0, space: 0},
+            Slot {cursor: 4, space: 2},
+            Slot {cursor: 0, space: 0},
+        ]);
+        assert_eq!(free_list.list, vec![Slot {space: 4, cursor: 0}]);
+        assert_eq!((free_list.total_free_space, end), (4, 6));
+
+        // several holes sorted by space and then by cursor
+        let (free_list, end) = from_index(&[
+            Slot {cursor: 30, space: 10},
+            Slot {cursor: 2, space: 3},
+            Slot {cursor: 8, space: 2},
+            Slot {cursor: 12, space: 8},
+            Slot {cursor: 23, space: 5},
+        ]);
+        assert_eq!(free_list.list, vec![
+            Slot {space: 2, cursor: 0},
+            Slot {space: 2, cursor: 10},
+            Slot {space: 2, cursor: 28},
+            Slot {space: 3, cursor: 5},
+            Slot {space: 3, cursor: 20},
+        ]);
+        assert_eq!((free_list.total_free_space, end), (12, 40));
+        assert_eq!(free_list.fragment_count(), 5);
     }
 
     #[test]
@@ -270,6 +455,32 @@ mod tests {
         assert_eq!(free_list.list, vec![Slot {space: 1, cursor: 44}, Slot {space: 43, cursor: 0}]);
     }
 
+    #[test]
+    fn test_free_space_accounting() {
+        let mut free_list = FreeList::new();
+        assert_eq!((0, 0, 0), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+
+        free_list.insert_free_space(0, 10);
+        free_list.insert_free_space(10, 6);
+        free_list.insert_free_space(40, 20);
+        assert_eq!((36, 3, 20), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+
+        // only the requested space is taken off, the leftover stays free
+        assert_eq!(free_list.retrieve_free_space(15), Some(40));
+        assert_eq!((21, 3, 10), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+        assert_eq!(free_list.retrieve_free_space(6), Some(10));
+        assert_eq!((15, 2, 10), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+
+        // compacting merges fragments without changing the free space
+        free_list.insert_free_space(10, 6);
+        free_list.compact();
+        assert_eq!((21, 2, 16), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+
+        assert_eq!(free_list.retrieve_free_space(16), Some(0));
+        assert_eq!(free_list.retrieve_free_space(5), Some(55));
+        assert_eq!((0, 0, 0), (free_list.total_free_space(), free_list.fragment_count(), free_list.largest_fragment()));
+    }
+
     #[test]
     fn test_retrieve_equal_or_bigger_than() {
         let mut free_list = FreeList::new();
@@ -297,4 +508,177 @@ mod tests {
         );
         assert_eq!(free_list.list, vec![Slot {space: 4, cursor: 16}])
     }
+
+    #[test]
+    fn test_retrieve_merges_leftover_with_neighbours() {
+        let mut free_list = FreeList::new();
+        free_list.insert_free_space(0, 10);
+        free_list.insert_free_space(10, 5);
+
+        // the leftover of the claimed slot (6..10) touches the free slot at cursor 10
+        assert_eq!(free_list.retrieve_free_space(6), Some(0));
+        assert_eq!(free_list.list, vec![Slot {space: 9, cursor: 6}]);
+        assert_eq!(free_list.total_free_space, 9);
+    }
+
+    #[test]
+    fn test_insert_and_merge_free_space() {
+        let mut free_list = FreeList::new();
+        free_list.insert_free_space(0, 10);
+        free_list.insert_free_space(20, 5);
+
+        // retrieving and giving back the same space leaves the list as it was
+        assert_eq!(free_list.retrieve_free_space(3), Some(20));
+        assert_eq!(free_list.retrieve_free_space(1), Some(23));
+        free_list.insert_and_merge_free_space(23, 1);
+        free_list.insert_and_merge_free_space(20, 3);
+        assert_eq!(free_list.list, vec![Slot {space: 5, cursor: 20}, Slot {space: 10, cursor: 0}]);
+        assert_eq!(free_list.total_free_space, 15);
+
+        // merge with both sides at once
+        free_list.insert_and_merge_free_space(10, 10);
+        assert_eq!(free_list.list, vec![Slot {space: 25, cursor: 0}]);
+        assert_eq!(free_list.total_free_space, 25);
+    }
+
+    // free slots of 4 at 0, 8 at 10, 6 at 30 and 12 at 50
+    fn fragmented(strategy: AllocationStrategy) -> FreeList {
+        let mut free_list = FreeList::with_strategy(strategy);
+        free_list.insert_free_space(0, 4);
+        free_list.insert_free_space(10, 8);
+        free_list.insert_free_space(30, 6);
+        free_list.insert_free_space(50, 12);
+        free_list
+    }
+
+    #[test]
+    fn test_allocation_strategies() {
+        let mut best_fit = fragmented(AllocationStrategy::BestFit);
+        assert_eq!(best_fit.retrieve_free_space(5), Some(30));
+        assert_eq!(best_fit.list, vec![
+            Slot {space: 1, cursor: 35},
+            Slot {space: 4, cursor: 0},
+            Slot {space: 8, cursor: 10},
+            Slot {space: 12, cursor: 50},
+        ]);
+
+        let mut first_fit = fragmented(AllocationStrategy::FirstFit);
+        assert_eq!(first_fit.retrieve_free_space(5), Some(10));
+        assert_eq!(first_fit.retrieve_free_space(3), Some(0));
+        assert_eq!(first_fit.list, vec![
+            Slot {space: 1, cursor: 3},
+            Slot {space: 3, cursor: 15},
+            Slot {space: 6, cursor: 30},
+            Slot {space: 12, cursor: 50},
+        ]);
+
+        let mut worst_fit = fragmented(AllocationStrategy::WorstFit);
+        assert_eq!(worst_fit.retrieve_free_space(5), Some(50));
+        assert_eq!(worst_fit.retrieve_free_space(5), Some(10));
+        assert_eq!(worst_fit.list, vec![
+            Slot {space: 3, cursor: 15},
+            Slot {space: 4, cursor: 0},
+            Slot {space: 6, cursor: 30},
+            Slot {space: 7, cursor: 55},
+        ]);
+        assert_eq!(worst_fit.total_free_space, 20);
+
+        for strategy in [AllocationStrategy::BestFit, AllocationStrategy::FirstFit, AllocationStrategy::WorstFit] {
+            assert_eq!(fragmented(strategy).retrieve_free_space(13), None);
+        }
+    }
+
+    #[test]
+    fn test_allocation_is_deterministic() {
+        // equal sized slots inserted in different orders are handed out in the same order
+        let run = |cursors: &[usize], strategy: AllocationStrategy| -> Vec<Option<usize>> {
+            let mut free_list = FreeList::with_strategy(strategy);
+            for cursor in cursors {
+                free_list.insert_free_space(*cursor, 5);
+            }
+            (0..4).map(|_| free_list.retrieve_free_space(5)).collect()
+        };
+
+        for strategy in [AllocationStrategy::BestFit, AllocationStrategy::FirstFit, AllocationStrategy::WorstFit] {
+            let expected = vec![Some(10), Some(20), Some(30), None];
+            assert_eq!(expected, run(&[30, 10, 20], strategy));
+            assert_eq!(expected, run(&[20, 30, 10], strategy));
+        }
+    }
+
+    #[test]
+    fn test_neighbors_of() {
+        let mut free_list = FreeList::new();
+        free_list.insert_free_space(0, 5);
+        free_list.insert_free_space(8, 2);
+        free_list.insert_free_space(20, 4);
+
+        assert_eq!(
+            free_list.neighbors_of(5, 3),
+            (Some(&Slot {space: 5, cursor: 0}), Some(&Slot {space: 2, cursor: 8}))
+        );
+        assert_eq!(free_list.neighbors_of(10, 5), (Some(&Slot {space: 2, cursor: 8}), None));
+        assert_eq!(free_list.neighbors_of(12, 8), (None, Some(&Slot {space: 4, cursor: 20})));
+        assert_eq!(free_list.neighbors_of(30, 1), (None, None));
+    }
+
+    #[test]
+    fn test_retrieve_ending_at() {
+        let mut free_list = FreeList::new();
+        free_list.insert_free_space(0, 5);
+        free_list.insert_free_space(8, 2);
+
+        assert_eq!(free_list.retrieve_ending_at(10), Some(Slot {space: 2, cursor: 8}));
+        assert_eq!(free_list.retrieve_ending_at(10), None);
+        assert_eq!(free_list.retrieve_ending_at(4), None);
+        assert_eq!(free_list.total_free_space(), 5);
+        assert_eq!(free_list.fragments(), &[Slot {space: 5, cursor: 0}]);
+    }
+
+    #[test]
+    fn test_random_alloc_free_never_leaves_adjacent_slots() {
+        // small xorshift generator so the sequence is reproducible without extra dependencies
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move |bound: usize| -> usize {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+
+        for _ in 0..50 {
+            let mut free_list = FreeList::new();
+            let mut allocated: Vec<Slot> = vec![];
+            let mut last_cursor = 0;
+
+            for _ in 0..200 {
+                if allocated.is_empty() || next(3) > 0 {
+                    let space = next(16) + 1;
+                    match free_list.retrieve_free_space(space) {
+                        Some(cursor) => allocated.push(Slot {space, cursor}),
+                        None => {
+                            allocated.push(Slot {space, cursor: last_cursor});
+                            last_cursor += space;
+                        }
+                    }
+                } else {
+                    // the path the store frees space through
+                    let slot = allocated.swap_remove(next(allocated.len()));
+                    free_list.insert_and_merge_free_space(slot.cursor, slot.space);
+                }
+
+                assert_no_adjacent_slots(&free_list);
+            }
+        }
+    }
+
+    fn assert_no_adjacent_slots(free_list: &FreeList) {
+        let mut by_cursor: Vec<(usize, usize)> = free_list.list.iter().map(|slot| (slot.cursor, slot.space)).collect();
+        by_cursor.sort();
+        assert_eq!(by_cursor, free_list.by_cursor.iter().copied().collect::<Vec<_>>());
+
+        for slot in free_list.list.iter() {
+            assert_eq!(free_list.neighbors_of(slot.cursor, slot.space), (None, None));
+        }
+    }
 }
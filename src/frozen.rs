@@ -0,0 +1,225 @@
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use memmap2::Mmap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::fileheader::FileHeader;
+use crate::persist::{KVError, Persister};
+
+struct FrozenInner<K> {
+    header: FileHeader,
+    map: Option<Mmap>, // empty db files can't be mapped
+    entries: Vec<(K, usize, usize)>, // (key, cursor, space) sorted by key
+}
+
+/// Immutable read view of a datastore produced by `Persister::freeze`. Values are served
+/// straight from a read-only memory map of the db file, so reads need neither locks nor `&mut`.
+/// Cloning is cheap, all the clones share the same map
+pub struct FrozenPersister<K> {
+    inner: Arc<FrozenInner<K>>,
+}
+
+impl<K> Clone for FrozenPersister<K> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<K: Ord> FrozenPersister<K> {
+    pub(crate) fn new(header: FileHeader, entries: Vec<(K, usize, usize)>) -> Result<Self, KVError> {
+        let file_len = header.data_len()?;
+
+        // make sure every slot can be served from the map so reads can't fail later on
+        if let Some((_, cursor, space)) = entries.iter().find(|(_, cursor, space)| (cursor + space) as u64 > file_len) {
+            return Err(KVError::SlotBeyondEof { cursor: *cursor, len: *space, file_len });
+        }
+
+        let map = match file_len {
+            0 => None,
+            // SAFETY: the db file is only written through a Persister, which was consumed to build
+            // this view, and thaw() only hands it back once the last view is gone
+            _ => Some(unsafe { Mmap::map(&header.db_file) }?),
+        };
+
+        Ok(Self { inner: Arc::new(FrozenInner { header, map, entries }) })
+    }
+
+    pub fn get(&self, key: &K) -> Result<&[u8], KVError> {
+        match self.inner.entries.binary_search_by(|(entry_key, _, _)| entry_key.cmp(key)) {
+            Ok(pos) => Ok(self.value_at(pos)),
+            Err(_) => Err(KVError::KeyDoesNotExist),
+        }
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.inner.entries.binary_search_by(|(entry_key, _, _)| entry_key.cmp(key)).is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.entries.is_empty()
+    }
+
+    /// Iterates over all the key/value pairs in key order
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &[u8])> {
+        self.range(..)
+    }
+
+    /// Iterates in key order over the key/value pairs whose key falls in `range`
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> impl Iterator<Item = (&K, &[u8])> {
+        let entries = &self.inner.entries;
+
+        let start = match range.start_bound() {
+            Bound::Included(key) => entries.partition_point(|(entry_key, _, _)| entry_key < key),
+            Bound::Excluded(key) => entries.partition_point(|(entry_key, _, _)| entry_key <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(key) => entries.partition_point(|(entry_key, _, _)| entry_key <= key),
+            Bound::Excluded(key) => entries.partition_point(|(entry_key, _, _)| entry_key < key),
+            Bound::Unbounded => entries.len(),
+        };
+
+        (start..end.max(start)).map(move |pos| (&self.inner.entries[pos].0, self.value_at(pos)))
+    }
+
+    fn value_at(&self, pos: usize) -> &[u8] {
+        let (_, cursor, space) = &self.inner.entries[pos];
+        let start = self.inner.header.offset as usize + cursor;
+        match &self.inner.map {
+            Some(map) => &map[start..start + space],
+            None => &[],
+        }
+    }
+}
+
+impl<K> FrozenPersister<K> where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    /// Turns the view back into a mutable store by loading its index again. Fails with
+    /// `KVError::FrozenViewInUse` while other clones of the view are alive, since the store
+    /// can't be written while something may still read from the map
+    pub fn thaw(self) -> Result<Persister<K>, KVError> {
+        let inner = Arc::try_unwrap(self.inner).map_err(|_| KVError::FrozenViewInUse)?;
+        drop(inner.map);
+
+        let mut persister = Persister::with_header(inner.header);
+        persister.load_index()?;
+
+        Ok(persister)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_populated_persister(entries: usize) -> Persister<String> {
+        let mut persister = Persister::new_temporary().unwrap();
+
+        for i in 0..entries {
+            let value: Vec<u8> = (0..i % 7).map(|j| (i + j) as u8).collect();
+            persister.insert_kv(&format!("key_{:04}", i), &value).unwrap();
+        }
+        for i in (0..entries).step_by(5) {
+            persister.delete_kv(&format!("key_{:04}", i)).unwrap();
+        }
+
+        persister
+    }
+
+    #[test]
+    fn test_freeze_reads_match_persister() {
+        let persister = new_populated_persister(100);
+        let mut expected: Vec<(String, Vec<u8>)> = vec![];
+        for i in 0..100 {
+            let key = format!("key_{:04}", i);
+            if let Ok(value) = persister.get_value(&key) {
+                expected.push((key, value));
+            }
+        }
+
+        let frozen = persister.freeze().unwrap();
+
+        assert_eq!(expected.len(), frozen.len());
+        for (key, value) in expected.iter() {
+            assert!(frozen.contains(key));
+            assert_eq!(value.as_slice(), frozen.get(key).unwrap());
+        }
+        assert!(!frozen.contains(&"key_0000".to_string()));
+        assert_eq!(KVError::KeyDoesNotExist, frozen.get(&"key_0005".to_string()).unwrap_err());
+
+        let iterated: Vec<(String, Vec<u8>)> = frozen.iter()
+            .map(|(key, value)| (key.clone(), value.to_vec()))
+            .collect();
+        assert_eq!(expected, iterated);
+    }
+
+    #[test]
+    fn test_freeze_range() {
+        let frozen = new_populated_persister(20).freeze().unwrap();
+
+        let keys = |range: (Bound<String>, Bound<String>)| -> Vec<String> {
+            frozen.range(range).map(|(key, _)| key.clone()).collect()
+        };
+
+        assert_eq!(
+            vec!["key_0003", "key_0004", "key_0006"],
+            keys((Bound::Included("key_0003".to_string()), Bound::Excluded("key_0007".to_string())))
+        );
+        assert_eq!(
+            vec!["key_0004", "key_0006", "key_0007"],
+            keys((Bound::Excluded("key_0003".to_string()), Bound::Included("key_0007".to_string())))
+        );
+        assert_eq!(vec!["key_0019"], keys((Bound::Excluded("key_0018".to_string()), Bound::Unbounded)));
+        assert!(keys((Bound::Included("zzz".to_string()), Bound::Unbounded)).is_empty());
+        assert!(keys((Bound::Included("key_0007".to_string()), Bound::Excluded("key_0003".to_string()))).is_empty());
+    }
+
+    #[test]
+    fn test_freeze_empty_store() {
+        let frozen = new_populated_persister(0).freeze().unwrap();
+
+        assert!(frozen.is_empty());
+        assert_eq!(0, frozen.iter().count());
+    }
+
+    #[test]
+    fn test_freeze_concurrent_reads() {
+        let frozen = new_populated_persister(200).freeze().unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let frozen = frozen.clone();
+                scope.spawn(move || {
+                    for i in (1..200).filter(|i| i % 5 != 0) {
+                        let expected: Vec<u8> = (0..i % 7).map(|j| (i + j) as u8).collect();
+                        assert_eq!(expected.as_slice(), frozen.get(&format!("key_{:04}", i)).unwrap());
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_thaw_round_trip() {
+        let frozen = new_populated_persister(30).freeze().unwrap();
+
+        // a clone still reading from the map prevents thawing
+        let other = frozen.clone();
+        assert!(matches!(frozen.thaw(), Err(KVError::FrozenViewInUse)));
+
+        let mut persister = other.thaw().unwrap();
+        assert_eq!(vec![3, 4, 5], persister.get_value(&"key_0003".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key_0010".to_string()).unwrap_err());
+
+        persister.insert_kv(&"key_0010".to_string(), b"ab").unwrap();
+        persister.delete_kv(&"key_0001".to_string()).unwrap();
+
+        let frozen = persister.freeze().unwrap();
+        assert_eq!(b"ab", frozen.get(&"key_0010".to_string()).unwrap());
+        assert!(!frozen.contains(&"key_0001".to_string()));
+    }
+}
@@ -0,0 +1,168 @@
+use std::io::{Error, ErrorKind, Read};
+use crate::slot::Slot;
+
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_PUT_EXPIRING: u8 = 3;
+const OP_CONFIG: u8 = 4;
+
+/// Longest key the index file takes, in bytes of its encoding. A record claiming a longer key
+/// is corrupt rather than torn
+pub const MAX_KEY_LEN: usize = 1 << 20;
+
+/// Record appended to the index file every time the index changes. The index file is a log of
+/// these records: replaying them in order rebuilds the in-memory index.
+///
+/// Layout (integers in little endian):
+///   put:    [op: u8 = 1][key_len: u32][key bytes][cursor: u64][space: u64]
+///   delete: [op: u8 = 2][key_len: u32][key bytes]
+///   put of an expiring key:
+///           [op: u8 = 3][key_len: u32][key bytes][cursor: u64][space: u64][expires_at: u64]
+///   config: [op: u8 = 4][block_len: u32][block bytes]
+///
+/// `expires_at` is in milliseconds since the unix epoch, a put without it makes the key
+/// persistent again. The config block holds the encoded `CreationInfo` of the store, the last
+/// one wins
+#[derive(Debug, PartialEq)]
+pub enum IndexRecord {
+    Put { key: Vec<u8>, slot: Slot, expires_at: Option<u64> },
+    Delete { key: Vec<u8> },
+    Config { block: Vec<u8> },
+}
+
+impl IndexRecord {
+    pub fn encode(&self) -> Vec<u8> {
+        let (op, key) = match self {
+            IndexRecord::Put { key, expires_at: None, .. } => (OP_PUT, key),
+            IndexRecord::Put { key, expires_at: Some(_), .. } => (OP_PUT_EXPIRING, key),
+            IndexRecord::Delete { key } => (OP_DELETE, key),
+            IndexRecord::Config { block } => (OP_CONFIG, block),
+        };
+
+        let mut buffer = Vec::with_capacity(1 + 4 + key.len() + 24);
+        buffer.push(op);
+        buffer.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        buffer.extend_from_slice(key);
+
+        if let IndexRecord::Put { slot, expires_at, .. } = self {
+            buffer.extend_from_slice(&(slot.cursor as u64).to_le_bytes());
+            buffer.extend_from_slice(&(slot.space as u64).to_le_bytes());
+            if let Some(expires_at) = expires_at {
+                buffer.extend_from_slice(&expires_at.to_le_bytes());
+            }
+        }
+
+        buffer
+    }
+
+    /// Reads the next record, returns `None` when the reader is exhausted right at a record
+    /// boundary. A record cut anywhere after its type is reported as `UnexpectedEof`, one whose
+    /// key length is over `MAX_KEY_LEN` as `InvalidData`
+    pub fn decode<R: Read>(reader: &mut R) -> Result<Option<Self>, Error> {
+        let mut op = [0u8; 1];
+        if reader.read(&mut op)? == 0 {
+            return Ok(None);
+        }
+
+        // a corrupt length can be anything, so the key is not allocated up front
+        let key_len = read_u32(reader)? as usize;
+        if key_len > MAX_KEY_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, format!("index record key of {} bytes", key_len)));
+        }
+        let mut key = vec![];
+        reader.by_ref().take(key_len as u64).read_to_end(&mut key)?;
+        if key.len() < key_len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, format!("index record key of {} bytes cut at {}", key_len, key.len())));
+        }
+
+        match op[0] {
+            OP_PUT | OP_PUT_EXPIRING => {
+                let cursor = read_u64(reader)? as usize;
+                let space = read_u64(reader)? as usize;
+                let expires_at = match op[0] {
+                    OP_PUT_EXPIRING => Some(read_u64(reader)?),
+                    _ => None,
+                };
+                Ok(Some(IndexRecord::Put { key, slot: Slot { cursor, space }, expires_at }))
+            },
+            OP_DELETE => Ok(Some(IndexRecord::Delete { key })),
+            OP_CONFIG => Ok(Some(IndexRecord::Config { block: key })),
+            unknown => Err(Error::new(ErrorKind::InvalidData, format!("unknown index record type {}", unknown))),
+        }
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let records = [
+            IndexRecord::Put { key: b"key_1".to_vec(), slot: Slot { cursor: 10, space: 5 }, expires_at: None },
+            IndexRecord::Delete { key: b"key_1".to_vec() },
+            IndexRecord::Put { key: vec![], slot: Slot { cursor: 0, space: 0 }, expires_at: None },
+            IndexRecord::Put { key: vec![0, 0xff, b'\n', 2], slot: Slot { cursor: 1 << 40, space: 3 }, expires_at: None },
+            IndexRecord::Put { key: b"key_2".to_vec(), slot: Slot { cursor: 7, space: 2 }, expires_at: Some(1 << 41) },
+            IndexRecord::Put { key: b"key_2".to_vec(), slot: Slot { cursor: 7, space: 2 }, expires_at: Some(0) },
+            IndexRecord::Config { block: b"config".to_vec() },
+        ];
+
+        let mut log: Vec<u8> = vec![];
+        for record in records.iter() {
+            log.extend(record.encode());
+        }
+
+        let mut reader = log.as_slice();
+        for record in records.iter() {
+            assert_eq!(Some(record), IndexRecord::decode(&mut reader).unwrap().as_ref());
+        }
+        assert_eq!(None, IndexRecord::decode(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_decode_truncated_record() {
+        let encoded = IndexRecord::Put { key: b"key".to_vec(), slot: Slot { cursor: 1, space: 2 }, expires_at: None }.encode();
+
+        let mut reader = &encoded[..encoded.len() - 1];
+        assert_eq!(ErrorKind::UnexpectedEof, IndexRecord::decode(&mut reader).unwrap_err().kind());
+
+        let encoded = IndexRecord::Put { key: b"key".to_vec(), slot: Slot { cursor: 1, space: 2 }, expires_at: Some(3) }.encode();
+
+        let mut reader = &encoded[..encoded.len() - 1];
+        assert_eq!(ErrorKind::UnexpectedEof, IndexRecord::decode(&mut reader).unwrap_err().kind());
+
+        // cut inside the key or its length
+        let encoded = IndexRecord::Delete { key: b"key_12345678".to_vec() }.encode();
+        for len in [1, 3, 5, 8, encoded.len() - 1] {
+            let mut reader = &encoded[..len];
+            assert_eq!(ErrorKind::UnexpectedEof, IndexRecord::decode(&mut reader).unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn test_decode_corrupt_key_length() {
+        // a length of 4GB is not allocated, the record is reported as corrupt
+        let mut encoded = IndexRecord::Delete { key: b"key".to_vec() }.encode();
+        encoded[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut reader = encoded.as_slice();
+        assert_eq!(ErrorKind::InvalidData, IndexRecord::decode(&mut reader).unwrap_err().kind());
+
+        encoded[1..5].copy_from_slice(&(MAX_KEY_LEN as u32 + 1).to_le_bytes());
+        let mut reader = encoded.as_slice();
+        assert_eq!(ErrorKind::InvalidData, IndexRecord::decode(&mut reader).unwrap_err().kind());
+    }
+}
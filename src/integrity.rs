@@ -0,0 +1,274 @@
+use std::ops::Range;
+use serde::{Deserialize, Serialize};
+use crate::slot::Slot;
+
+/// Inconsistency found by `Persister::verify_integrity`. Ranges are cursors in the data of the
+/// db file, the format header at its start left out
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum Violation<K> {
+    /// the slots of two keys share the bytes of `overlap`
+    OverlappingSlots { first: K, second: K, overlap: Range<usize> },
+    /// the slot of a key shares bytes with a free slot, new values would be written over it
+    SlotInFreeSpace { key: K, slot: Range<usize>, free: Range<usize> },
+    /// two free slots share bytes, they could be handed out to two values
+    OverlappingFreeSlots { first: Range<usize>, second: Range<usize> },
+    /// the slot of a key reaches past the end of the db file
+    SlotBeyondEof { key: K, slot: Range<usize>, file_len: u64 },
+    /// the slot of a key reaches past the end of the data, new values would be appended over it
+    SlotBeyondLastCursor { key: K, slot: Range<usize>, last_cursor: usize },
+    /// a free slot reaches past the end of the data, it could be handed out twice
+    FreeSlotBeyondLastCursor { free: Range<usize>, last_cursor: usize },
+    /// the free space counted by the free list doesn't add up to the space of its free slots
+    FreeSpaceMismatch { counted: usize, fragments: usize },
+    /// the value of the key can't be read back, only checked by
+    /// `Persister::verify_integrity_strict`
+    UnreadableValue { key: K, error: String },
+}
+
+/// Outcome of `Persister::verify_integrity`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport<K> {
+    /// keys whose slot was checked
+    pub keys_checked: usize,
+    /// free slots checked
+    pub free_slots_checked: usize,
+    /// values read back, 0 unless the check was strict
+    pub values_read: usize,
+    /// every inconsistency found, an empty list means the store is consistent
+    pub violations: Vec<Violation<K>>,
+}
+
+impl<K> IntegrityReport<K> {
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub(crate) fn empty() -> Self {
+        Self { keys_checked: 0, free_slots_checked: 0, values_read: 0, violations: vec![] }
+    }
+}
+
+impl<K> Violation<K> {
+    /// Keys the violation is about, none for the ones about free slots and counters
+    pub(crate) fn keys(&self) -> Vec<&K> {
+        match self {
+            Violation::OverlappingSlots { first, second, .. } => vec![first, second],
+            Violation::SlotInFreeSpace { key, .. }
+            | Violation::SlotBeyondEof { key, .. }
+            | Violation::SlotBeyondLastCursor { key, .. }
+            | Violation::UnreadableValue { key, .. } => vec![key],
+            Violation::OverlappingFreeSlots { .. }
+            | Violation::FreeSlotBeyondLastCursor { .. }
+            | Violation::FreeSpaceMismatch { .. } => vec![],
+        }
+    }
+}
+
+/// Progress of a verification run step by step by `Persister::verify_step`. It can be
+/// serialized to resume the run in another process
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyState<K> {
+    pub(crate) cursor: usize, // spans starting before it have been checked
+    pub(crate) layout: LayoutCheck<K>,
+    pub(crate) report: IntegrityReport<K>,
+    skipped: usize,
+    done: bool,
+}
+
+impl<K: Clone> VerifyState<K> {
+    /// State of a run that starts at the beginning of the data
+    pub fn new() -> Self {
+        Self { cursor: 0, layout: LayoutCheck::new(), report: IntegrityReport::empty(), skipped: 0, done: false }
+    }
+
+    /// Cursor the next step resumes from
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Violations found by earlier steps and dropped because their keys were deleted since
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // drops the violations about keys that are gone and ends the run
+    pub(crate) fn finish(&mut self, still_stored: impl Fn(&K) -> bool) -> IntegrityReport<K> {
+        let before = self.report.violations.len();
+        self.report.violations.retain(|violation| violation.keys().into_iter().all(&still_stored));
+        self.skipped += before - self.report.violations.len();
+        self.done = true;
+
+        self.report.clone()
+    }
+}
+
+impl<K: Clone> Default for VerifyState<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Outcome of a step of `Persister::verify_step`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome<K> {
+    /// the data left to check is verified by the next steps
+    Continue,
+    /// the whole data was checked, the report is the one of the whole run
+    Done(IntegrityReport<K>),
+}
+
+/// Part of the data checked by `LayoutCheck`, the slot of a key or a free slot
+pub(crate) enum Span<'a, K> {
+    Key(&'a K, Slot),
+    Free(Slot),
+}
+
+impl<K> Span<'_, K> {
+    fn slot(&self) -> &Slot {
+        match self {
+            Span::Key(_, slot) | Span::Free(slot) => slot,
+        }
+    }
+}
+
+/// Sorts the slots of the keys and the free slots by cursor, keys first at the same cursor
+pub(crate) fn sorted_spans<'a, K>(slots: impl Iterator<Item = (&'a K, &'a Slot)>, free: impl Iterator<Item = Slot>) -> Vec<Span<'a, K>> {
+    let mut spans: Vec<Span<K>> = slots.map(|(key, slot)| Span::Key(key, slot.clone()))
+        .chain(free.map(Span::Free))
+        .collect();
+    spans.sort_by_key(|span| span.slot().cursor);
+
+    spans
+}
+
+/// Checks spans handed over in cursor order against each other and against the end of the
+/// data and of the file. Only the span reaching the furthest is kept from one span to the
+/// next, so the check can be spread over several passes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct LayoutCheck<K> {
+    // a span overlaps the span reaching the furthest among the ones before it, None for a free
+    // slot
+    furthest: Option<(Range<usize>, Option<K>)>,
+}
+
+impl<K: Clone> LayoutCheck<K> {
+    pub fn new() -> Self {
+        Self { furthest: None }
+    }
+
+    pub fn check(&mut self, span: &Span<K>, last_cursor: usize, file_len: u64, report: &mut IntegrityReport<K>) {
+        let slot = span.slot();
+        let range = slot.cursor..slot.cursor + slot.space;
+        let owner = match span {
+            Span::Key(key, _) => {
+                report.keys_checked += 1;
+                // empty slots hold no bytes
+                if slot.space == 0 {
+                    return;
+                }
+                if range.end as u64 > file_len {
+                    report.violations.push(Violation::SlotBeyondEof { key: (*key).clone(), slot: range.clone(), file_len });
+                }
+                if range.end > last_cursor {
+                    report.violations.push(Violation::SlotBeyondLastCursor { key: (*key).clone(), slot: range.clone(), last_cursor });
+                }
+                Some(*key)
+            },
+            Span::Free(_) => {
+                report.free_slots_checked += 1;
+                if slot.space == 0 {
+                    return;
+                }
+                if range.end > last_cursor {
+                    report.violations.push(Violation::FreeSlotBeyondLastCursor { free: range.clone(), last_cursor });
+                }
+                None
+            },
+        };
+
+        if let Some((previous, previous_owner)) = self.furthest.as_ref() {
+            if range.start < previous.end {
+                report.violations.push(overlap(previous, previous_owner.as_ref(), &range, owner));
+            }
+            if range.end <= previous.end {
+                return;
+            }
+        }
+        self.furthest = Some((range, owner.cloned()));
+    }
+
+    /// Forgets the span reaching the furthest unless it is still in place, spans checked later
+    /// are not compared against a slot that was released or moved in the meantime
+    pub fn retain_furthest(&mut self, in_place: impl FnOnce(&Range<usize>, Option<&K>) -> bool) {
+        if let Some((range, owner)) = self.furthest.as_ref() {
+            if !in_place(range, owner.as_ref()) {
+                self.furthest = None;
+            }
+        }
+    }
+}
+
+/// Checks that the free space counted by the free list adds up to the space of its free slots
+pub(crate) fn check_free_space<K>(free: &[Slot], total_free_space: usize, report: &mut IntegrityReport<K>) {
+    let fragments = free.iter().map(|slot| slot.space).sum();
+    if fragments != total_free_space {
+        report.violations.push(Violation::FreeSpaceMismatch { counted: total_free_space, fragments });
+    }
+}
+
+fn overlap<K: Clone>(first: &Range<usize>, first_owner: Option<&K>, second: &Range<usize>, second_owner: Option<&K>) -> Violation<K> {
+    match (first_owner, second_owner) {
+        (Some(first_key), Some(second_key)) => Violation::OverlappingSlots {
+            first: first_key.clone(),
+            second: second_key.clone(),
+            overlap: second.start..first.end.min(second.end),
+        },
+        (Some(key), None) => Violation::SlotInFreeSpace { key: key.clone(), slot: first.clone(), free: second.clone() },
+        (None, Some(key)) => Violation::SlotInFreeSpace { key: key.clone(), slot: second.clone(), free: first.clone() },
+        (None, None) => Violation::OverlappingFreeSlots { first: first.clone(), second: second.clone() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(slots: &[(&'static str, Slot)], free: &[Slot], last_cursor: usize) -> Vec<Violation<&'static str>> {
+        let mut report = IntegrityReport::empty();
+        let mut layout = LayoutCheck::new();
+        for span in sorted_spans(slots.iter().map(|(key, slot)| (key, slot)), free.iter().cloned()) {
+            layout.check(&span, last_cursor, last_cursor as u64, &mut report);
+        }
+        assert_eq!((slots.len(), free.len()), (report.keys_checked, report.free_slots_checked));
+
+        report.violations
+    }
+
+    #[test]
+    fn test_consistent_layout() {
+        let slots = [("a", Slot { cursor: 0, space: 4 }), ("b", Slot { cursor: 6, space: 2 }), ("empty", Slot { cursor: 3, space: 0 })];
+        assert!(check(&slots, &[Slot { cursor: 4, space: 2 }], 8).is_empty());
+        assert!(check(&[], &[], 0).is_empty());
+    }
+
+    #[test]
+    fn test_contained_slots_overlap() {
+        // b is inside a, c starts inside a after b ended
+        let slots = [("a", Slot { cursor: 0, space: 10 }), ("b", Slot { cursor: 2, space: 3 }), ("c", Slot { cursor: 8, space: 4 })];
+        assert_eq!(vec![
+            Violation::OverlappingSlots { first: "a", second: "b", overlap: 2..5 },
+            Violation::OverlappingSlots { first: "a", second: "c", overlap: 8..10 },
+        ], check(&slots, &[], 12));
+    }
+
+    #[test]
+    fn test_free_slots_overlap() {
+        let free = [Slot { cursor: 0, space: 4 }, Slot { cursor: 2, space: 4 }];
+        assert_eq!(vec![Violation::OverlappingFreeSlots { first: 0..4, second: 2..6 }], check(&[], &free, 6));
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use crate::persist::KVError;
+
+/// Number of new keys checked for serialized collisions after opening a store, unless the
+/// paranoid mode keeps the check active
+pub const KEY_COLLISION_CHECK_INSERTS: usize = 1024;
+
+/// Detects keys that are different but serialize to the same bytes. The index file only holds
+/// the serialized keys, so such keys would get merged when the index is replayed.
+///
+/// Remembers the encoding of the new keys written during the first inserts, a check costs a
+/// hash lookup and the map is dropped as soon as the window is over
+pub(crate) struct KeyCollisionGuard<K> {
+    seen: HashMap<Vec<u8>, K>,
+    remaining: usize,
+    paranoid: bool,
+}
+
+impl<K: Eq + Clone + Debug> KeyCollisionGuard<K> {
+    pub fn new(checked_inserts: usize) -> Self {
+        Self {
+            seen: HashMap::new(),
+            remaining: checked_inserts,
+            paranoid: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.paranoid || self.remaining > 0
+    }
+
+    /// Keeps checking every new key for as long as the flag is set. Keys written while the
+    /// guard was inactive are unknown to it
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        self.paranoid = paranoid;
+        if !self.is_active() {
+            self.seen = HashMap::new();
+        }
+    }
+
+    /// Fails with `KVError::KeyEncodingCollision` if a different key already used the same
+    /// encoding, otherwise remembers the key while the guard is active
+    pub fn check(&mut self, key: &K, encoded: &[u8]) -> Result<(), KVError> {
+        if !self.is_active() {
+            return Ok(());
+        }
+
+        if let Some(seen) = self.seen.get(encoded) {
+            if seen != key {
+                return Err(KVError::KeyEncodingCollision {
+                    first: format!("{:?}", seen),
+                    second: format!("{:?}", key),
+                });
+            }
+            return Ok(());
+        }
+
+        self.seen.insert(encoded.to_vec(), key.clone());
+        self.remaining = self.remaining.saturating_sub(1);
+        if !self.is_active() {
+            self.seen = HashMap::new();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_detects_collisions() {
+        let mut guard = KeyCollisionGuard::new(10);
+        assert_eq!(Ok(()), guard.check(&"a".to_string(), b"x"));
+        assert_eq!(Ok(()), guard.check(&"a".to_string(), b"x"));
+        assert_eq!(Ok(()), guard.check(&"b".to_string(), b"y"));
+        assert_eq!(
+            Err(KVError::KeyEncodingCollision { first: "\"a\"".to_string(), second: "\"c\"".to_string() }),
+            guard.check(&"c".to_string(), b"x")
+        );
+    }
+
+    #[test]
+    fn test_check_window() {
+        let mut guard = KeyCollisionGuard::new(2);
+        assert_eq!(Ok(()), guard.check(&"a".to_string(), b"x"));
+        assert_eq!(Ok(()), guard.check(&"b".to_string(), b"y"));
+        assert!(!guard.is_active());
+        assert!(guard.seen.is_empty());
+        assert_eq!(Ok(()), guard.check(&"c".to_string(), b"x"));
+
+        // the paranoid mode checks from then on
+        guard.set_paranoid(true);
+        assert_eq!(Ok(()), guard.check(&"d".to_string(), b"z"));
+        assert!(guard.check(&"e".to_string(), b"z").is_err());
+    }
+}
@@ -1,7 +1,141 @@
+mod batch;
+mod builder;
+mod cache;
+mod compression;
+mod creation;
+mod diff;
+mod digest;
+mod errorlog;
+mod expiry;
 mod freelist;
+#[cfg(feature = "mmap")]
+mod frozen;
+mod integrity;
+mod indexlog;
+mod keyguard;
 mod fileheader;
+mod format;
 mod persist;
+mod positional;
+mod prefix;
+#[cfg(feature = "mmap")]
+mod readmap;
+#[cfg(feature = "redis-import")]
+mod redis;
+mod reservation;
+mod shared;
 mod slot;
+mod txn;
+mod wal;
+
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+pub use batch::WriteBatch;
+pub use builder::PersisterBuilder;
+pub use cache::CacheConfig;
+pub use compression::Compression;
+pub use creation::CreationInfo;
+pub use diff::{diff, DiffOptions, DiffReport};
+pub use errorlog::ErrorRecord;
+pub use expiry::{Clock, PurgeReport, SystemClock};
+pub use freelist::AllocationStrategy;
+#[cfg(feature = "mmap")]
+pub use frozen::FrozenPersister;
+pub use integrity::{IntegrityReport, StepOutcome, VerifyState, Violation};
+pub use persist::{CompactionReport, EofPolicy, Iter, KVError, OpenPhase, Persister, PutOutcome, SnapshotInfo, SplitReport, StoreStats, SyncMode};
+pub use prefix::PrefixKey;
+pub use reservation::Reservation;
+pub use shared::SharedPersister;
+pub use txn::Txn;
+#[cfg(feature = "redis-import")]
+pub use redis::{ImportReport, RedisImportOptions};
+
+/// Key-value store holding typed values. Values are serialized with bincode into the byte
+/// vectors stored by the underlying `Persister`, keys must serialize injectively as described
+/// there
+pub struct EmbedKV<K, V> {
+    persister: Persister<K>,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<K, V> EmbedKV<K, V>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned, V: Serialize + DeserializeOwned {
+    /// Opens the datastore, creating it if it does not exist yet
+    pub fn new(datastore: String, storage_limit: usize) -> Result<Self, KVError> {
+        Persister::new(datastore, storage_limit).map(Self::from_persister)
+    }
+
+    /// Opens the datastore like `new`, compressing the values written to it. See
+    /// `PersisterBuilder::compression` for how compressed stores must be reopened
+    pub fn with_compression(datastore: String, storage_limit: usize, compression: Compression) -> Result<Self, KVError> {
+        PersisterBuilder::new()
+            .path(datastore)
+            .storage_limit(storage_limit)
+            .compression(compression)
+            .open()
+            .map(Self::from_persister)
+    }
+
+    pub fn from_persister(persister: Persister<K>) -> Self {
+        Self { persister, _value: PhantomData }
+    }
+
+    /// Inserts a new key, fails with `KVError::KeyAlreadyExist` if the key is already stored
+    pub fn insert(&mut self, key: &K, value: &V) -> Result<(), KVError> {
+        self.persister.insert_kv(key, &encode_value(value)?)
+    }
+
+    /// Stores the value under the key, inserting the key when it is missing and updating its
+    /// value otherwise, like `Persister::put`
+    pub fn put(&mut self, key: &K, value: &V) -> Result<PutOutcome, KVError> {
+        self.persister.put(key, &encode_value(value)?)
+    }
+
+    pub fn get(&self, key: &K) -> Result<V, KVError> {
+        decode_value(&self.persister.get_value(key)?)
+    }
+
+    /// Replaces the value of an existing key, fails with `KVError::KeyDoesNotExist` otherwise
+    pub fn update(&mut self, key: &K, value: &V) -> Result<(), KVError> {
+        self.persister.update_value(key, &encode_value(value)?)
+    }
+
+    pub fn delete(&mut self, key: &K) -> Result<(), KVError> {
+        self.persister.delete_kv(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.persister.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.persister.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.persister.is_empty()
+    }
+
+    /// Iterates over the stored keys in order without reading or decoding any value
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.persister.keys()
+    }
+
+    pub fn into_persister(self) -> Persister<K> {
+        self.persister
+    }
+}
+
+fn encode_value<V: Serialize>(value: &V) -> Result<Vec<u8>, KVError> {
+    bincode::serialize(value).map_err(|error| KVError::SerializationError(error.to_string()))
+}
+
+fn decode_value<V: DeserializeOwned>(bytes: &[u8]) -> Result<V, KVError> {
+    bincode::deserialize(bytes).map_err(|error| KVError::SerializationError(error.to_string()))
+}
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -10,10 +144,82 @@ pub fn add(left: usize, right: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+        score: Option<f64>,
+    }
+
+    fn new_profile(name: &str, age: u32) -> Profile {
+        Profile {
+            name: name.to_string(),
+            age,
+            tags: vec!["a".to_string(), "b".to_string()],
+            score: None,
+        }
+    }
 
     #[test]
     fn it_works() {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn test_embedkv_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("profiles").to_string_lossy().to_string();
+        let mut store: EmbedKV<String, Profile> = EmbedKV::new(datastore.clone(), 0).unwrap();
+
+        let alice = new_profile("alice", 31);
+        store.insert(&"user:1".to_string(), &alice).unwrap();
+        store.insert(&"user:2".to_string(), &new_profile("bob", 42)).unwrap();
+        assert_eq!(alice, store.get(&"user:1".to_string()).unwrap());
+        assert_eq!(KVError::KeyAlreadyExist, store.insert(&"user:1".to_string(), &alice).unwrap_err());
+
+        // update with a bigger value
+        let mut updated = alice.clone();
+        updated.tags.push("a much longer tag than before".to_string());
+        updated.score = Some(9.5);
+        store.update(&"user:1".to_string(), &updated).unwrap();
+        assert_eq!(updated, store.get(&"user:1".to_string()).unwrap());
+
+        assert_eq!(2, store.len());
+        store.delete(&"user:2".to_string()).unwrap();
+        assert_eq!(KVError::KeyDoesNotExist, store.get(&"user:2".to_string()).unwrap_err());
+        assert_eq!(KVError::KeyDoesNotExist, store.update(&"user:2".to_string(), &alice).unwrap_err());
+        assert!(!store.contains_key(&"user:2".to_string()));
+        assert!(store.contains_key(&"user:1".to_string()));
+
+        // put inserts missing keys and replaces the value of stored ones
+        let bob = new_profile("bob", 43);
+        assert_eq!(Ok(PutOutcome::Inserted), store.put(&"user:2".to_string(), &bob));
+        assert_eq!(Ok(PutOutcome::Updated), store.put(&"user:2".to_string(), &alice));
+        assert_eq!(alice, store.get(&"user:2".to_string()).unwrap());
+        store.delete(&"user:2".to_string()).unwrap();
+        drop(store);
+
+        let store: EmbedKV<String, Profile> = EmbedKV::new(datastore, 0).unwrap();
+        assert_eq!(updated, store.get(&"user:1".to_string()).unwrap());
+        assert_eq!(vec!["user:1"], store.keys().collect::<Vec<&String>>());
+        assert_eq!(1, store.len());
+        assert!(!store.is_empty());
+    }
+
+    #[test]
+    fn test_embedkv_serialization_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("raw").to_string_lossy().to_string();
+
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        persister.insert_kv(&"key".to_string(), &[1]).unwrap();
+
+        // a single byte can't be decoded as a profile
+        let store: EmbedKV<String, Profile> = EmbedKV::from_persister(persister);
+        assert!(matches!(store.get(&"key".to_string()), Err(KVError::SerializationError(_))));
+    }
 }
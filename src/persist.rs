@@ -1,398 +1,6983 @@
-use std::collections::BTreeMap;
-use std::io::{Seek, SeekFrom, Write, Read};
-use std::os::unix::fs::FileExt;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::fs::OpenOptions;
+use std::io::{BufReader, ErrorKind, Seek, SeekFrom, Write, Read};
+use std::sync::{Mutex, MutexGuard};
+use crate::batch::WriteBatch;
+use crate::builder::PersisterBuilder;
+use crate::cache::{CacheConfig, ValueCache};
+use crate::compression::{self, Compression, FrameHeader, MAX_HEADER_LEN};
+use crate::creation::CreationInfo;
+use crate::digest::{self, DigestBuilder, CONTENT_DOMAIN, DIGEST_LEN, ENTRY_DOMAIN};
+use crate::errorlog::{ErrorLog, ErrorRecord, DEFAULT_ERROR_LOG_CAPACITY};
+use crate::expiry::{self, Clock, PurgeReport, SystemClock};
 use crate::fileheader::FileHeader;
-use crate::freelist::FreeList;
+use crate::format::{self, FormatError, FORMAT_VERSION};
+use crate::freelist::{AllocationStrategy, FreeList};
+#[cfg(feature = "mmap")]
+use crate::frozen::FrozenPersister;
+use crate::indexlog::{self, IndexRecord};
+use crate::integrity::{self, IntegrityReport, LayoutCheck, Span, StepOutcome, VerifyState, Violation};
+use crate::keyguard::{KeyCollisionGuard, KEY_COLLISION_CHECK_INSERTS};
+use crate::positional::PositionalFile;
+use crate::prefix::PrefixKey;
+#[cfg(feature = "mmap")]
+use crate::readmap::ReadMap;
+use crate::reservation::Reservation;
 use crate::slot::Slot;
-use std::fs::File;
-use serde::{Serialize, Deserialize};
+use crate::txn::Txn;
+use crate::wal::{self, WalOp, WAL_CHECKPOINT_SIZE};
+use std::fmt::Debug;
+use std::ops::{Bound, Range, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
-#[derive(Debug, PartialEq)]
+/// Error returned by the store. New variants may be added in any release, so matches on it
+/// need a wildcard arm.
+///
+/// Errors compare equal when they are the same variant with the same fields, I/O errors only
+/// compare their `std::io::ErrorKind`. `KVError` implements `std::error::Error`, so it
+/// propagates with `?` into `Box<dyn Error>` or `anyhow::Result`:
+///
+/// ```
+/// use embedkv::Persister;
+///
+/// fn read_back(datastore: String) -> anyhow::Result<Vec<u8>> {
+///     let mut persister: Persister<String> = Persister::new(datastore, 0)?;
+///     persister.insert_kv(&"key".to_string(), &b"value".to_vec())?;
+///     Ok(persister.get_value(&"key".to_string())?)
+/// }
+///
+/// let dir = tempfile::tempdir()?;
+/// let datastore = dir.path().join("store").to_string_lossy().to_string();
+/// assert_eq!(b"value".to_vec(), read_back(datastore)?);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
 pub enum KVError {
     KeyDoesNotExist,
     KeyAlreadyExist,
-    IOError(String),
+    IOError(std::io::Error),
+    /// a key or value that serde couldn't encode or decode
+    SerializationError(String),
+    InvariantViolation(String),
+    SlotBeyondEof { cursor: usize, len: usize, file_len: u64 },
+    KeyQuarantined,
+    FrozenViewInUse,
+    DatastoreAlreadyExists,
+    DatastoreDoesNotExist,
+    BufferTooSmall { needed: usize },
+    /// the reader of a streamed value ended before `expected` bytes, or had more of them, in
+    /// which case `read` is `expected + 1`
+    StreamLengthMismatch { expected: usize, read: usize },
+    KeyEncodingCollision { first: String, second: String },
+    CompressionError(String),
+    /// a write or commit of `len` bytes on a reservation of `reserved` bytes
+    ReservationExceeded { len: usize, reserved: usize },
+    /// a write needing the db file to grow to `needed` bytes, past the `limit` of the store
+    StorageLimitExceeded { limit: usize, needed: usize },
+    /// data read back from the files that can't have been written by the store
+    Corruption(String),
+    /// an argument the operation can't work with, whatever the state of the store
+    InvalidArgument(String),
+    /// a write on a datastore opened with `PersisterBuilder::read_only`
+    ReadOnly,
+    /// a datastore opened with options its files don't allow, like a store created with
+    /// compression opened without it
+    InvalidFormat(String),
+    /// a datastore written in a version of the file format this release can't read
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// the datastore opened at `path` isn't one: a file without the header of the store, an
+    /// empty file, a directory, or the files of two datastores swapped
+    NotADatastore { path: PathBuf, detail: String },
+    /// the datastore opened at `path` was written by the store but fails the checks of `phase`
+    CorruptDatastore { path: PathBuf, phase: OpenPhase, detail: String },
+    /// a sync of the files failed, writes fail until `Persister::acknowledge_sync_failure`
+    SyncFailed,
 }
 
+impl std::fmt::Display for KVError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KVError::KeyDoesNotExist => write!(f, "key does not exist"),
+            KVError::KeyAlreadyExist => write!(f, "key already exists"),
+            KVError::IOError(io_error) => write!(f, "i/o error: {}", io_error),
+            KVError::SerializationError(reason) => write!(f, "serialization error: {}", reason),
+            KVError::InvariantViolation(reason) => write!(f, "invariant violation: {}", reason),
+            KVError::SlotBeyondEof { cursor, len, file_len } => write!(
+                f, "slot {}..{} extends past the end of the db file ({} bytes)", cursor, cursor + len, file_len
+            ),
+            KVError::KeyQuarantined => write!(f, "key is quarantined"),
+            KVError::FrozenViewInUse => write!(f, "a frozen view of the store is still alive"),
+            KVError::DatastoreAlreadyExists => write!(f, "datastore already exists"),
+            KVError::DatastoreDoesNotExist => write!(f, "datastore does not exist"),
+            KVError::BufferTooSmall { needed } => write!(f, "buffer too small, {} bytes needed", needed),
+            KVError::StreamLengthMismatch { expected, read } => write!(
+                f, "stream length mismatch, expected {} bytes and read {}", expected, read
+            ),
+            KVError::KeyEncodingCollision { first, second } => write!(
+                f, "keys {} and {} serialize to the same bytes", first, second
+            ),
+            KVError::CompressionError(reason) => write!(f, "compression error: {}", reason),
+            KVError::ReservationExceeded { len, reserved } => write!(
+                f, "{} bytes don't fit in a reservation of {} bytes", len, reserved
+            ),
+            KVError::StorageLimitExceeded { limit, needed } => write!(
+                f, "storage limit of {} bytes exceeded, {} bytes needed", limit, needed
+            ),
+            KVError::Corruption(reason) => write!(f, "corruption: {}", reason),
+            KVError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            KVError::ReadOnly => write!(f, "datastore is read-only"),
+            KVError::InvalidFormat(reason) => write!(f, "invalid format: {}", reason),
+            KVError::UnsupportedVersion { found, supported } => write!(
+                f, "unsupported format version {}, version {} is supported", found, supported
+            ),
+            KVError::SyncFailed => write!(f, "a sync of the files failed, the writes since the last sync must be acknowledged"),
+            KVError::NotADatastore { path, detail } => write!(f, "{:?} is not a datastore: {}", path, detail),
+            KVError::CorruptDatastore { path, phase, detail } => write!(
+                f, "datastore {:?} is corrupt, {} check failed: {}", path, phase.name(), detail
+            ),
+        }
+    }
+}
+
+/// Part of opening a datastore that found it corrupt, see `KVError::CorruptDatastore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OpenPhase {
+    /// the format header at the start of the db and index files
+    Header,
+    /// the records of the index file
+    Index,
+}
+
+impl OpenPhase {
+    fn name(&self) -> &'static str {
+        match self {
+            OpenPhase::Header => "header",
+            OpenPhase::Index => "index",
+        }
+    }
+}
+
+impl KVError {
+    // an error opening the datastore at `path`, with the failures of the files told apart
+    pub(crate) fn opening(path: &Path, error: KVError) -> KVError {
+        match error {
+            KVError::IOError(io_error) => match io_error.get_ref().and_then(|inner| inner.downcast_ref::<FormatError>()) {
+                Some(FormatError::NotADatastore(detail)) => KVError::NotADatastore { path: path.to_path_buf(), detail: detail.clone() },
+                Some(FormatError::Corrupt(detail)) => KVError::CorruptDatastore { path: path.to_path_buf(), phase: OpenPhase::Header, detail: detail.clone() },
+                _ => KVError::from(io_error),
+            },
+            KVError::Corruption(detail) => KVError::CorruptDatastore { path: path.to_path_buf(), phase: OpenPhase::Index, detail },
+            error => error,
+        }
+    }
+}
+
+impl std::error::Error for KVError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KVError::IOError(io_error) => Some(io_error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for KVError {
+    fn from(io_error: std::io::Error) -> Self {
+        // files that fail the format check when they are opened
+        match io_error.get_ref().and_then(|inner| inner.downcast_ref::<FormatError>()) {
+            Some(FormatError::Invalid(reason)) => KVError::InvalidFormat(reason.clone()),
+            Some(FormatError::UnsupportedVersion(found)) => KVError::UnsupportedVersion { found: *found, supported: FORMAT_VERSION },
+            // the path of the datastore is added by `KVError::opening`
+            Some(FormatError::NotADatastore(_) | FormatError::Corrupt(_)) | None => KVError::IOError(io_error),
+        }
+    }
+}
+
+impl PartialEq for KVError {
+    fn eq(&self, other: &Self) -> bool {
+        use KVError::*;
+        match (self, other) {
+            (IOError(a), IOError(b)) => a.kind() == b.kind(),
+            (SerializationError(a), SerializationError(b))
+            | (InvariantViolation(a), InvariantViolation(b))
+            | (CompressionError(a), CompressionError(b))
+            | (Corruption(a), Corruption(b))
+            | (InvalidArgument(a), InvalidArgument(b))
+            | (InvalidFormat(a), InvalidFormat(b)) => a == b,
+            (SlotBeyondEof { cursor: a, len: b, file_len: c }, SlotBeyondEof { cursor: x, len: y, file_len: z }) => (a, b, c) == (x, y, z),
+            (BufferTooSmall { needed: a }, BufferTooSmall { needed: b }) => a == b,
+            (UnsupportedVersion { found: a, supported: b }, UnsupportedVersion { found: x, supported: y }) => (a, b) == (x, y),
+            (StreamLengthMismatch { expected: a, read: b }, StreamLengthMismatch { expected: x, read: y })
+            | (ReservationExceeded { len: a, reserved: b }, ReservationExceeded { len: x, reserved: y })
+            | (StorageLimitExceeded { limit: a, needed: b }, StorageLimitExceeded { limit: x, needed: y }) => (a, b) == (x, y),
+            (KeyEncodingCollision { first: a, second: b }, KeyEncodingCollision { first: x, second: y }) => (a, b) == (x, y),
+            (NotADatastore { path: a, detail: b }, NotADatastore { path: x, detail: y }) => (a, b) == (x, y),
+            (CorruptDatastore { path: a, phase: b, detail: c }, CorruptDatastore { path: x, phase: y, detail: z }) => (a, b, c) == (x, y, z),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other)
+                && matches!(self, KeyDoesNotExist | KeyAlreadyExist | KeyQuarantined | FrozenViewInUse
+                    | DatastoreAlreadyExists | DatastoreDoesNotExist | ReadOnly | SyncFailed),
+        }
+    }
+}
+
+// size of the chunks values are streamed in by `insert_from_reader` and `read_to_writer`
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// values loaded by `bulk_load` are staged in chunks of this size before being written
+const BULK_LOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+// slots next to each other are read by `get_many` in a single read of up to this size
+const COALESCED_READ_SIZE: usize = 1024 * 1024;
+
+/// What to do with a key whose slot points past the end of the db file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// return `KVError::SlotBeyondEof` on every read of the key
+    Fail,
+    /// return `KVError::SlotBeyondEof` once and mark the key as corrupt, later reads fail with
+    /// `KVError::KeyQuarantined` without touching the file until the key is purged
+    Quarantine,
+}
+
+/// When the files of the store are synced to disk. Whatever the mode, `Persister::flush` syncs
+/// them on demand and dropping the store syncs them on a best-effort basis.
+///
+/// Writes go to the write-ahead log before the db and index files. The log is only synced ahead
+/// of the writes the mode syncs, which then survive a crash of the machine whole or not at all.
+/// The other writes still survive a crash of the process whole, the log is replayed from the
+/// page cache, but a crash of the machine can lose or tear them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// sync after every successful write
+    Always,
+    /// only sync on explicit flush and on drop, the write-ahead log included
+    Never,
+    /// sync once every n successful writes, `EveryNOps(0)` behaves like `Always`
+    EveryNOps(usize),
+}
+
+/// Tells whether `Persister::put` inserted a new key or replaced the value of an existing one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PutOutcome {
+    Inserted,
+    Updated,
+}
+
+/// Outcome of `Persister::snapshot_to`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SnapshotInfo {
+    /// keys copied to the snapshot
+    pub keys: usize,
+    /// size of the db file of the snapshot
+    pub bytes: usize,
+    /// quarantined keys left out of the snapshot
+    pub skipped_quarantined: usize,
+}
+
+/// Outcome of `Persister::split_into`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SplitReport {
+    /// keys and bytes written to each part, in the order of the parts
+    pub parts: Vec<SnapshotInfo>,
+}
+
+/// Outcome of `Persister::compact_datastore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionReport {
+    /// values moved to a lower cursor
+    pub values_moved: usize,
+    /// bytes of the moved values
+    pub bytes_moved: usize,
+    /// bytes the db file shrank by
+    pub bytes_reclaimed: u64,
+}
+
+/// Space usage of a store, see `Persister::stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StoreStats {
+    /// keys stored, as counted by `Persister::len`
+    pub keys: usize,
+    /// bytes of the db file referenced by the keys
+    pub live_bytes: usize,
+    /// length of the data in the db file, which can run past `last_cursor` after deletes. The
+    /// format header at the start of the file is left out
+    pub file_len: u64,
+    /// bytes held by the free list
+    pub free_bytes: usize,
+    /// number of free slots
+    pub free_fragments: usize,
+    /// space of the biggest free slot, the largest value stored without growing the file
+    pub largest_free_fragment: usize,
+    /// end of the data, new values that don't fit in the free list are written there
+    pub last_cursor: usize,
+    /// keys quarantined under `EofPolicy::Quarantine`
+    pub quarantined_keys: usize,
+    /// reads of `Persister::get_value` served by the value cache, see `PersisterBuilder::cache`
+    pub cache_hits: u64,
+    /// reads of `Persister::get_value` that went to the db file while the cache is enabled
+    pub cache_misses: u64,
+}
+
+/// Byte store indexed by keys of type `K`.
+///
+/// Any type that is `Ord + Clone + Debug + Serialize + DeserializeOwned` can be a key: strings,
+/// byte vectors, integers, tuples or structs deriving the serde traits. Keys are ordered by
+/// their `Ord` impl in memory, so their serialized form doesn't need to preserve the order.
+///
+/// Keys are stored in the index file in their serialized form, so the `Serialize` impl of `K`
+/// must be injective: two different keys serializing to the same bytes would be merged into
+/// one when the index is replayed. The first new keys written after opening the store are
+/// checked for this, see `Persister::set_paranoid_key_checks`
 pub struct Persister<K> {
     freelist: FreeList,  
     header: FileHeader,
     index: BTreeMap<K, Slot>, // todo(): unify SlotInstance with a more common name
+    live_slots: BTreeMap<usize, usize>, // cursor -> space of every non-empty slot in the index
+    quarantined: Mutex<BTreeSet<K>>, // behind a lock so reads can quarantine keys through &self
+    cache: Mutex<ValueCache<K>>, // behind a lock so reads can fill it through &self
+    expiries: BTreeMap<K, u64>, // key -> expiration in milliseconds since the unix epoch
+    clock: Box<dyn Clock>,
+    eof_policy: EofPolicy,
+    sync_mode: SyncMode,
+    unsynced_ops: usize, // successful writes since the last sync
+    errors: ErrorLog,
+    key_guard: KeyCollisionGuard<K>,
+    live_digest: Mutex<Option<[u8; DIGEST_LEN]>>, // None until live_digest() is first called
+    compression: Compression,
+    wal_pending: Option<u64>, // length of the write-ahead log before the write in progress was logged
+    sync_failed: bool, // a sync failed, writes wait for acknowledge_sync_failure
+    #[cfg(feature = "mmap")]
+    read_map: Option<ReadMap>, // set while reads are served from a map of the db file
+    secure_delete: bool,
+    read_only: bool, // every write fails with KVError::ReadOnly
+    storage_limit: usize, // bytes the db file can't grow past, 0 for no limit
+    creation: Option<CreationInfo>, // None for stores created before it was recorded
     last_cursor: usize,
 }
 
-impl<K> Persister<K> where K: Ord + Clone {
-    pub fn new(datastore: String, _storage_limit: usize) -> Result<Self, KVError> {
-        FileHeader::new(Some(datastore))
-            .map(|fh| Self { freelist: FreeList::new(), header: fh, index: BTreeMap::new(), last_cursor: 0 })
-            .map_err(|io_error| KVError::IOError(io_error.to_string()))
+// how the value of a key is laid out in its slot
+enum Layout {
+    // the bytes of the value are stored as they are in this part of the slot
+    Plain(Slot),
+    // the value must be decompressed from the whole slot, `len` is its uncompressed length
+    Compressed { slot: Slot, len: usize },
+}
+
+impl<K> Persister<K> where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    /// Opens the datastore, loading its index if it already exists or creating it otherwise.
+    /// Same as opening it with a `PersisterBuilder` left to its defaults but for the storage
+    /// limit, see `PersisterBuilder::storage_limit`
+    pub fn new(datastore: String, storage_limit: usize) -> Result<Self, KVError> {
+        PersisterBuilder::new()
+            .path(datastore)
+            .storage_limit(storage_limit)
+            .open()
+    }
+
+    /// Creates a fresh datastore, fails with `KVError::DatastoreAlreadyExists` instead of
+    /// touching the files of an existing one
+    pub fn create_new(datastore: String, storage_limit: usize) -> Result<Self, KVError> {
+        let mut persister = FileHeader::create_new(&datastore, Compression::None)
+            .map_err(|io_error| match io_error.kind() {
+                ErrorKind::AlreadyExists => KVError::DatastoreAlreadyExists,
+                _ => KVError::from(io_error),
+            })
+            .map(Self::with_header)?;
+        persister.storage_limit = storage_limit;
+
+        Ok(persister)
+    }
+
+    /// Opens an existing datastore, fails with `KVError::DatastoreDoesNotExist` instead of
+    /// creating it
+    pub fn open_existing(datastore: String, storage_limit: usize) -> Result<Self, KVError> {
+        PersisterBuilder::new()
+            .path(datastore)
+            .create_if_missing(false)
+            .storage_limit(storage_limit)
+            .open()
+    }
+
+    /// Creates an empty datastore backed by anonymous temporary files, removed when it is
+    /// dropped. Same as opening it with `PersisterBuilder::temporary`
+    pub fn new_temporary() -> Result<Self, KVError> {
+        PersisterBuilder::new().temporary().open()
+    }
+
+    /// Opens a snapshot written by `Persister::snapshot_to` as a regular datastore. The snapshot
+    /// of a store with compression enabled is opened through `PersisterBuilder::compression`
+    pub fn open_snapshot(path: &Path) -> Result<Self, KVError> {
+        Self::open_existing(path.to_string_lossy().to_string(), 0)
+    }
+
+    /// Adopts a datastore written before the format header was introduced: a db file holding
+    /// the values from its first byte and an index file that is empty or holds index records
+    /// without a header. The keys are taken from the records of the index file and from
+    /// `key_hints`, the range of the db file each key's value was stored in as known by the
+    /// process that wrote it, which wins over the records. Without either, the adopted
+    /// datastore is empty and the values must be inserted again from the original files.
+    ///
+    /// The values are copied to a new datastore named after the db file with an `.adopted`
+    /// suffix, written under a temporary name and renamed once complete, which is returned
+    /// opened. The original files are never changed, removing them is up to the caller once the
+    /// adopted store checks out. Fails with `KVError::InvalidArgument` if the db file already
+    /// has a format header, with `KVError::SlotBeyondEof` if a value reaches past the end of
+    /// the db file and with `KVError::DatastoreAlreadyExists` if the store was already adopted
+    pub fn adopt_legacy(db_path: &Path, index_path: &Path, key_hints: &[(K, Range<usize>)]) -> Result<Self, KVError> {
+        let open = |path: &Path| OpenOptions::new().read(true).open(path).map_err(|io_error| match io_error.kind() {
+            ErrorKind::NotFound => KVError::DatastoreDoesNotExist,
+            _ => KVError::from(io_error),
+        });
+        let db_file = open(db_path)?;
+        if format::has_magic(&db_file)? {
+            return Err(KVError::InvalidArgument(format!("{:?} already has a datastore header", db_path)));
+        }
+
+        // the legacy files are read in place, with the data starting at their first byte
+        let header = FileHeader { db_file, index_file: open(index_path)?, wal_file: None, offset: 0 };
+        let mut legacy = Persister::with_header(header);
+        legacy.read_only = true;
+        legacy.load_legacy_index(key_hints)?;
+
+        let datastore = format!("{}.adopted", db_path.to_string_lossy());
+        let adopting = format!("{}.adopting", db_path.to_string_lossy());
+        if Path::new(&datastore).exists() {
+            return Err(KVError::DatastoreAlreadyExists);
+        }
+        // files left by an adoption that didn't complete
+        FileHeader::remove_files(&adopting)?;
+        legacy.snapshot_range(&adopting, (Bound::Unbounded, Bound::Unbounded))?;
+        FileHeader::rename_files(&adopting, &datastore)?;
+
+        Self::open_existing(datastore, 0)
+    }
+
+    // torn records at the end of a legacy index file are dropped, there is no log to replay
+    // them from
+    fn load_legacy_index(&mut self, key_hints: &[(K, Range<usize>)]) -> Result<(), KVError> {
+        let mut reader = BufReader::new(&self.header.index_file);
+        loop {
+            match IndexRecord::decode(&mut reader) {
+                Ok(Some(record)) => apply_index_record(&mut self.index, &mut self.expiries, record)?,
+                Ok(None) => break,
+                Err(io_error) if io_error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(io_error) => return Err(KVError::Corruption(format!("legacy index file: {}", io_error))),
+            }
+        }
+        for (key, range) in key_hints {
+            self.expiries.remove(key);
+            self.index.insert(key.clone(), Slot { cursor: range.start, space: range.len() });
+        }
+
+        let file_len = self.header.data_len()?;
+        for slot in self.index.values() {
+            if slot.cursor.saturating_add(slot.space) as u64 > file_len {
+                return Err(KVError::SlotBeyondEof { cursor: slot.cursor, len: slot.space, file_len });
+            }
+        }
+
+        Ok(())
+    }
+
+    // the options are in place before the index is loaded, the free list it rebuilds keeps the
+    // allocation strategy
+    pub(crate) fn open_configured(header: FileHeader, options: &PersisterBuilder) -> Result<Self, KVError> {
+        let mut persister = Self::with_header(header);
+        persister.read_only = options.mode.read_only;
+        persister.storage_limit = options.storage_limit;
+        persister.sync_mode = options.sync_mode;
+        persister.compression = options.mode.compression;
+        persister.cache = Mutex::new(ValueCache::new(options.cache));
+        persister.secure_delete = options.secure_delete;
+        persister.eof_policy = options.eof_policy;
+        persister.freelist.set_strategy(options.allocation_strategy);
+        persister.load_index()?;
+        persister.record_creation(options.label.clone())?;
+
+        Ok(persister)
+    }
+
+    // a store without creation info nor any record in its index file has just been created,
+    // stores created before the info was recorded go on without it
+    fn record_creation(&mut self, label: Option<String>) -> Result<(), KVError> {
+        if self.creation.is_some() || self.read_only || self.header.index_file.metadata()?.len() > self.header.offset {
+            return Ok(());
+        }
+
+        let info = CreationInfo::new(self.clock.now_millis(), self.compression.name(), label);
+        self.write_creation_info(info)
+    }
+
+    fn write_creation_info(&mut self, info: CreationInfo) -> Result<(), KVError> {
+        self.append_index_record(&IndexRecord::Config { block: info.encode() })?;
+        self.creation = Some(info);
+
+        Ok(())
+    }
+
+    pub(crate) fn with_header(header: FileHeader) -> Self {
+        Self {
+            freelist: FreeList::new(),
+            header,
+            index: BTreeMap::new(),
+            live_slots: BTreeMap::new(),
+            quarantined: Mutex::new(BTreeSet::new()),
+            cache: Mutex::new(ValueCache::new(CacheConfig::default())),
+            expiries: BTreeMap::new(),
+            clock: Box::new(SystemClock),
+            eof_policy: EofPolicy::Fail,
+            sync_mode: SyncMode::Never,
+            unsynced_ops: 0,
+            errors: ErrorLog::new(DEFAULT_ERROR_LOG_CAPACITY),
+            key_guard: KeyCollisionGuard::new(KEY_COLLISION_CHECK_INSERTS),
+            live_digest: Mutex::new(None),
+            compression: Compression::None,
+            wal_pending: None,
+            sync_failed: false,
+            #[cfg(feature = "mmap")]
+            read_map: None,
+            secure_delete: false,
+            read_only: false,
+            storage_limit: 0,
+            creation: None,
+            last_cursor: 0,
+        }
+    }
+
+    pub fn insert_kv(&mut self, key: &K, value: &[u8]) -> Result<(), KVError> {
+        let result = self.insert_kv_inner(key, value, None);
+        let result = self.sync_after_write(result);
+        self.record_error("insert_kv", result)
+    }
+
+    /// Inserts a new key that expires once `ttl` has elapsed. Expired keys read as missing and
+    /// can be inserted again, their space is reclaimed by the next write to the key or by
+    /// `Persister::purge_expired`
+    pub fn insert_kv_with_ttl(&mut self, key: &K, value: &[u8], ttl: Duration) -> Result<(), KVError> {
+        let expires_at = expiry::deadline(self.clock.now_millis(), ttl);
+        let result = self.insert_kv_inner(key, value, Some(expires_at));
+        let result = self.sync_after_write(result);
+        self.record_error("insert_kv_with_ttl", result)
+    }
+
+    fn insert_kv_inner(&mut self, key: &K, value: &[u8], expires_at: Option<u64>) -> Result<(), KVError> {
+        self.check_writable()?;
+        self.reclaim_if_expired(key)?;
+        if self.index.contains_key(key) {
+            return Err(KVError::KeyAlreadyExist)
+        }
+        self.check_new_key(key)?;
+        let stored = compression::encode(self.compression, value)?;
+
+        // the slot is only referenced by the index once the value and the key are both written,
+        // any failure before that gives the claimed space back
+        let (slot, from_freelist) = self.allocate(stored.len());
+        let result = match from_freelist || slot.space == 0 {
+            true => Ok(()),
+            false => self.check_storage_limit(self.last_cursor),
+        };
+        let result = result
+            .and_then(|_| self.check_no_overlap(slot.cursor, slot.space))
+            .and_then(|_| self.log_put(key, &slot, expires_at, Some(&stored)))
+            .and_then(|_| match stored.is_empty() {
+                true => Ok(()),
+                false => self.persist_value(&stored, slot.cursor),
+            })
+            .and_then(|_| self.persist_key(key, &slot, expires_at));
+        if let Err(error) = result {
+            self.unclaim(&slot, from_freelist);
+            return Err(error);
+        }
+
+        self.index.insert(key.clone(), slot.clone());
+        if slot.space > 0 {
+            self.live_slots.insert(slot.cursor, slot.space);
+        }
+        self.set_expiry(key, expires_at);
+        let added = self.tracked_new_entry_hash(key, value);
+        self.toggle_live_digest(added);
+
+        Ok(())
+    }
+
+    pub fn get_value(&self, key: &K) -> Result<Vec<u8>, KVError> {
+        let result = self.get_value_inner(key);
+        self.record_error("get_value", result)
+    }
+
+    fn get_value_inner(&self, key: &K) -> Result<Vec<u8>, KVError> {
+        self.check_not_expired(key)?;
+        let slot = self.readable_slot(key)?;
+        if let Some(value) = self.cache().get(key) {
+            return Ok(value);
+        }
+
+        let value = self.get_slot_value(key, &slot)?;
+        self.cache().insert(key, &value);
+
+        Ok(value)
+    }
+
+    /// Values of the keys in the order they are given, a key given several times gets its
+    /// value as many times. The slots of all the keys are looked up first, then read sorted by
+    /// cursor, the ones next to each other in a single read. A key that can't be read gets its
+    /// error at its position, like `KVError::KeyDoesNotExist` for a missing key, without
+    /// failing the others
+    pub fn get_many<'k, I>(&self, keys: I) -> Vec<Result<Vec<u8>, KVError>>
+    where I: IntoIterator<Item = &'k K>, K: 'k {
+        let keys: Vec<&K> = keys.into_iter().collect();
+        let mut results: Vec<Option<Result<Vec<u8>, KVError>>> = Vec::with_capacity(keys.len());
+        let mut reads = vec![];
+        for (position, key) in keys.iter().enumerate() {
+            let slot = match self.check_not_expired(key).and_then(|_| self.readable_slot(key)) {
+                Ok(slot) => slot,
+                Err(error) => {
+                    results.push(Some(Err(error)));
+                    continue;
+                },
+            };
+
+            let cached = self.cache().get(key);
+            if cached.is_none() {
+                reads.push((position, slot));
+            }
+            results.push(cached.map(Ok));
+        }
+
+        reads.sort_by_key(|(_, slot)| slot.cursor);
+        let mut reads = reads.into_iter().peekable();
+        while let Some((position, first)) = reads.next() {
+            let mut run = vec![(position, first.clone())];
+            let mut end = first.cursor + first.space;
+            // duplicated keys share their slot, other slots never overlap
+            while let Some((position, slot)) = reads.next_if(|(_, slot)| {
+                slot.cursor <= end && slot.cursor + slot.space - first.cursor <= COALESCED_READ_SIZE
+            }) {
+                end = end.max(slot.cursor + slot.space);
+                run.push((position, slot));
+            }
+
+            for (position, value) in self.read_run(&keys, first.cursor, end, run) {
+                results[position] = Some(value);
+            }
+        }
+
+        results.into_iter()
+            .map(|result| result.unwrap_or(Err(KVError::KeyDoesNotExist)))
+            .map(|result| self.record_error("get_many", result))
+            .collect()
+    }
+
+    // reads the slots of a run covering `start..end` at once, or one by one when the read fails
+    // so that each key gets its own error
+    fn read_run(&self, keys: &[&K], start: usize, end: usize, run: Vec<(usize, Slot)>) -> Vec<(usize, Result<Vec<u8>, KVError>)> {
+        let mut stored = vec![];
+        if run.len() > 1 {
+            stored.resize(end - start, 0);
+            if self.read_at(start, &mut stored).is_err() {
+                stored.clear();
+            }
+        }
+
+        run.into_iter()
+            .map(|(position, slot)| {
+                let value = match stored.is_empty() {
+                    true => self.get_slot_value(keys[position], &slot),
+                    false => {
+                        let bytes = stored[slot.cursor - start..slot.cursor - start + slot.space].to_vec();
+                        compression::decode(self.compression, bytes)
+                    },
+                };
+                if let Ok(value) = &value {
+                    self.cache().insert(keys[position], value);
+                }
+                (position, value)
+            })
+            .collect()
+    }
+
+    /// Serves reads from a memory map of the db file instead of a read on the file for each of
+    /// them, and enables `Persister::get_value_ref`. Writes still go through the file, the map
+    /// is redone after every write that changes the length of the file.
+    ///
+    /// The map relies on the store being the only writer of its files: another process
+    /// truncating the db file while it is mapped makes reads crash the process
+    #[cfg(feature = "mmap")]
+    pub fn set_mmap_reads(&mut self, enabled: bool) -> Result<(), KVError> {
+        self.read_map = match enabled {
+            true => Some(ReadMap::new(&self.header.db_file)?),
+            false => None,
+        };
+
+        Ok(())
+    }
+
+    /// Value of the key borrowed straight from the memory map of the db file, without copying
+    /// it. Fails with `KVError::InvalidArgument` unless mmap reads are on, see
+    /// `Persister::set_mmap_reads`, and with `KVError::CompressionError` for a compressed value.
+    /// The borrow keeps the store from being written, so the bytes can't change under it
+    #[cfg(feature = "mmap")]
+    pub fn get_value_ref(&self, key: &K) -> Result<&[u8], KVError> {
+        let result = self.get_value_ref_inner(key);
+        self.record_error("get_value_ref", result)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn get_value_ref_inner(&self, key: &K) -> Result<&[u8], KVError> {
+        let read_map = self.read_map.as_ref()
+            .ok_or_else(|| KVError::InvalidArgument("mmap reads are disabled".to_string()))?;
+        self.check_not_expired(key)?;
+        let slot = match self.layout(key)? {
+            Layout::Plain(slot) => slot,
+            Layout::Compressed { .. } => {
+                return Err(KVError::CompressionError("compressed values can't be borrowed".to_string()));
+            },
+        };
+
+        if let Some(bytes) = read_map.slice(self.header.offset as usize + slot.cursor, slot.space) {
+            return Ok(bytes);
+        }
+
+        // reading the file tells why the map doesn't cover the slot, quarantining the key if
+        // the slot is past the end of the file
+        self.get_slot_value(key, &slot)?;
+        Err(KVError::InvariantViolation(format!(
+            "slot {}..{} is not covered by the map of the db file", slot.cursor, slot.cursor + slot.space
+        )))
+    }
+
+    fn get_slot_value(&self, key: &K, slot: &Slot) -> Result<Vec<u8>, KVError> {
+        let result = self.retrieve_value(slot.cursor, slot.space);
+        self.quarantine_on_eof(key, &result);
+
+        result
+    }
+
+    /// Reads the value of the key into the start of `buffer`, returning its length. Fails with
+    /// `KVError::BufferTooSmall` before reading anything if the value doesn't fit, see
+    /// `Persister::value_len`
+    pub fn read_value_into(&self, key: &K, buffer: &mut [u8]) -> Result<usize, KVError> {
+        let result = self.read_value_into_inner(key, buffer);
+        self.record_error("read_value_into", result)
+    }
+
+    fn read_value_into_inner(&self, key: &K, buffer: &mut [u8]) -> Result<usize, KVError> {
+        self.check_not_expired(key)?;
+        let slot = match self.layout(key)? {
+            Layout::Plain(slot) => slot,
+            Layout::Compressed { slot, len } => {
+                if buffer.len() < len {
+                    return Err(KVError::BufferTooSmall { needed: len });
+                }
+                let value = self.get_slot_value(key, &slot)?;
+                buffer[..len].copy_from_slice(&value);
+                return Ok(len);
+            },
+        };
+        if buffer.len() < slot.space {
+            return Err(KVError::BufferTooSmall { needed: slot.space });
+        }
+        if slot.space == 0 {
+            return Ok(0);
+        }
+
+        let result = self.read_at(slot.cursor, &mut buffer[..slot.space])
+            .map(|_| slot.space);
+        self.quarantine_on_eof(key, &result);
+
+        result
+    }
+
+    /// Inserts a new key streaming its value from the reader in fixed size chunks, so the value
+    /// never needs to be held in memory. The reader must yield exactly `len` bytes, otherwise
+    /// the insert fails with `KVError::StreamLengthMismatch` and the claimed space is released.
+    /// Streamed values are never compressed
+    pub fn insert_from_reader<R: Read>(&mut self, key: &K, reader: R, len: usize) -> Result<(), KVError> {
+        let result = self.insert_from_reader_inner(key, reader, len);
+        let result = self.sync_after_write(result);
+        self.record_error("insert_from_reader", result)
+    }
+
+    fn insert_from_reader_inner<R: Read>(&mut self, key: &K, mut reader: R, len: usize) -> Result<(), KVError> {
+        self.check_writable()?;
+        self.reclaim_if_expired(key)?;
+        if self.index.contains_key(key) {
+            return Err(KVError::KeyAlreadyExist);
+        }
+        self.check_new_key(key)?;
+
+        // the entry hash is computed while streaming, the value can't be read twice
+        let mut entry_hasher = self.tracked_digest().map(|_| DigestBuilder::new(ENTRY_DOMAIN));
+        if let Some(hasher) = entry_hasher.as_mut() {
+            hasher.entry(&encode_key(key)?, len);
+        }
+
+        // compressing would need the whole value, it is stored raw
+        let header = compression::raw_header(self.compression, len);
+        let (slot, from_freelist) = self.allocate(header.len() + len);
+        let body = Slot { cursor: slot.cursor + header.len(), space: len };
+        let result = self.check_storage_limit(self.last_cursor)
+            .and_then(|_| self.check_no_overlap(slot.cursor, slot.space))
+            .and_then(|_| self.persist_value(header, slot.cursor))
+            .and_then(|_| self.stream_into_slot(&mut reader, &body, entry_hasher.as_mut()))
+            .and_then(|_| self.log_put(key, &slot, None, None))
+            .and_then(|_| self.persist_key(key, &slot, None));
+        if let Err(error) = result {
+            self.unclaim(&slot, from_freelist);
+            return Err(error);
+        }
+
+        self.index.insert(key.clone(), slot.clone());
+        if slot.space > 0 {
+            self.live_slots.insert(slot.cursor, slot.space);
+        }
+        self.set_expiry(key, None);
+        self.toggle_live_digest(entry_hasher.map(DigestBuilder::finish));
+
+        Ok(())
+    }
+
+    /// Claims space for a value of up to `max_len` bytes, written in place through the returned
+    /// reservation. The key is only published when the reservation is committed: nothing is
+    /// written to the index file before, so a crash leaves no trace of the reservation once
+    /// the store is reopened. The store can't be used while the reservation is alive. Reserved
+    /// values are never compressed
+    pub fn reserve(&mut self, key: &K, max_len: usize) -> Result<Reservation<'_, K>, KVError> {
+        let result = self.reserve_inner(key, max_len);
+        let (slot, header_len, from_freelist) = self.record_error("reserve", result)?;
+
+        Ok(Reservation::new(self, key.clone(), slot, header_len, from_freelist))
+    }
+
+    /// Starts a transaction staging puts and deletes in memory until it is committed, see `Txn`.
+    /// The store can't be used while the transaction is alive, so there's a single transaction
+    /// at a time
+    pub fn begin(&mut self) -> Txn<'_, K> {
+        Txn::new(self)
+    }
+
+    fn reserve_inner(&mut self, key: &K, max_len: usize) -> Result<(Slot, usize, bool), KVError> {
+        self.check_writable()?;
+        self.reclaim_if_expired(key)?;
+        if self.index.contains_key(key) {
+            return Err(KVError::KeyAlreadyExist);
+        }
+        self.check_new_key(key)?;
+
+        let header = compression::raw_header(self.compression, max_len);
+        let (slot, from_freelist) = self.allocate(header.len() + max_len);
+        let result = self.check_storage_limit(self.last_cursor)
+            .and_then(|_| self.check_no_overlap(slot.cursor, slot.space))
+            .and_then(|_| self.persist_value(header, slot.cursor));
+        if let Err(error) = result {
+            self.unclaim(&slot, from_freelist);
+            return Err(error);
+        }
+
+        Ok((slot, header.len(), from_freelist))
+    }
+
+    pub(crate) fn write_reserved(&self, slot: &Slot, offset: usize, bytes: &[u8]) -> Result<(), KVError> {
+        let result = self.persist_value(bytes, slot.cursor + offset);
+        self.record_error("write_reserved", result)
+    }
+
+    pub(crate) fn commit_reservation(&mut self, key: &K, slot: &Slot, header_len: usize, from_freelist: bool, final_len: usize) -> Result<(), KVError> {
+        let result = self.commit_reservation_inner(key, slot, header_len, from_freelist, final_len);
+        let result = self.sync_after_write(result);
+        self.record_error("commit_reservation", result)
+    }
+
+    fn commit_reservation_inner(&mut self, key: &K, slot: &Slot, header_len: usize, from_freelist: bool, final_len: usize) -> Result<(), KVError> {
+        let published = match final_len {
+            0 => Slot { cursor: 0, space: 0 },
+            _ => Slot { cursor: slot.cursor, space: header_len + final_len },
+        };
+        let result = self.log_put(key, &published, None, None)
+            .and_then(|_| self.persist_key(key, &published, None));
+        if let Err(error) = result {
+            self.unclaim(slot, from_freelist);
+            return Err(error);
+        }
+
+        // the slack after the value goes back like any unused part of a fresh allocation
+        let used = published.space;
+        self.unclaim(&Slot { cursor: slot.cursor + used, space: slot.space - used }, from_freelist);
+
+        self.index.insert(key.clone(), published.clone());
+        if published.space > 0 {
+            self.live_slots.insert(published.cursor, published.space);
+        }
+        self.set_expiry(key, None);
+        let added = self.tracked_entry_hash(key);
+        self.toggle_live_digest(added);
+
+        Ok(())
+    }
+
+    pub(crate) fn release_reservation(&mut self, slot: &Slot, from_freelist: bool) {
+        self.unclaim(slot, from_freelist);
+    }
+
+    /// Streams the value of the key into the writer in fixed size chunks, returning its length
+    pub fn read_to_writer<W: Write>(&self, key: &K, writer: W) -> Result<usize, KVError> {
+        let result = self.read_to_writer_inner(key, writer);
+        self.record_error("read_to_writer", result)
+    }
+
+    fn read_to_writer_inner<W: Write>(&self, key: &K, mut writer: W) -> Result<usize, KVError> {
+        self.check_not_expired(key)?;
+        self.stream_value(key, |chunk| {
+            writer.write_all(chunk).map_err(KVError::from)
+        })
+    }
+
+    // hands the value of the key to `sink` in fixed size chunks, returning its length
+    fn stream_value<F>(&self, key: &K, mut sink: F) -> Result<usize, KVError>
+    where F: FnMut(&[u8]) -> Result<(), KVError> {
+        let slot = match self.layout(key)? {
+            Layout::Plain(slot) => slot,
+            Layout::Compressed { slot, len } => {
+                sink(&self.get_slot_value(key, &slot)?)?;
+                return Ok(len);
+            },
+        };
+
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(slot.space)];
+        let mut done = 0;
+        while done < slot.space {
+            let len = chunk.len().min(slot.space - done);
+            let result = self.read_at(slot.cursor + done, &mut chunk[..len]);
+            self.quarantine_on_eof(key, &result);
+            result?;
+
+            sink(&chunk[..len])?;
+            done += len;
+        }
+
+        Ok(slot.space)
+    }
+
+    /// Digest of the logical contents of the store: every serialized key and its value, in
+    /// key order. Two stores holding the same entries get the same digest whatever the layout
+    /// of their files. Expired keys are left out. Reads every value, one chunk at a time
+    pub fn content_digest(&self) -> Result<[u8; DIGEST_LEN], KVError> {
+        let result = self.content_digest_inner();
+        self.record_error("content_digest", result)
+    }
+
+    fn content_digest_inner(&self) -> Result<[u8; DIGEST_LEN], KVError> {
+        let mut builder = DigestBuilder::new(CONTENT_DOMAIN);
+        for key in self.keys() {
+            self.hash_entry_into(key, &mut builder)?;
+        }
+
+        Ok(builder.finish())
+    }
+
+    /// Digest of the set of entries of the store, kept up to date on every write so it can be
+    /// compared without scanning the store. The first call scans the store to start tracking it,
+    /// and from then on writes also hash the entries they replace, which costs a read of the
+    /// previous value on updates and deletes.
+    ///
+    /// It is the XOR of the SHA-256 of each entry, which is cheap to update but only guards
+    /// against accidental drift: unlike `content_digest`, entries can be crafted to cancel each
+    /// other out. If a previous value can't be read the digest stops being tracked and the next
+    /// call scans the store again. Expired keys count until their space is reclaimed
+    pub fn live_digest(&self) -> Result<[u8; DIGEST_LEN], KVError> {
+        let result = self.live_digest_inner();
+        self.record_error("live_digest", result)
+    }
+
+    fn live_digest_inner(&self) -> Result<[u8; DIGEST_LEN], KVError> {
+        // a panic while scanning leaves the digest untracked, so poisoning is ignored
+        let mut tracked = self.live_digest.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(live_digest) = *tracked {
+            return Ok(live_digest);
+        }
+
+        let live_digest = self.scan_live_digest()?;
+        *tracked = Some(live_digest);
+
+        Ok(live_digest)
+    }
+
+    fn scan_live_digest(&self) -> Result<[u8; DIGEST_LEN], KVError> {
+        let mut live_digest = [0; DIGEST_LEN];
+        for key in self.index.keys() {
+            digest::toggle(&mut live_digest, &self.entry_hash(key)?);
+        }
+
+        Ok(live_digest)
+    }
+
+    fn hash_entry_into(&self, key: &K, builder: &mut DigestBuilder) -> Result<(), KVError> {
+        builder.entry(&encode_key(key)?, self.stored_value_len(key)?);
+        self.stream_value(key, |chunk| {
+            builder.update(chunk);
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    fn entry_hash(&self, key: &K) -> Result<[u8; DIGEST_LEN], KVError> {
+        let mut builder = DigestBuilder::new(ENTRY_DOMAIN);
+        self.hash_entry_into(key, &mut builder)?;
+
+        Ok(builder.finish())
+    }
+
+    // hash of the stored entry of the key while the live digest is tracked. A failed read
+    // stops the tracking since the digest can no longer be kept exact
+    fn tracked_entry_hash(&mut self, key: &K) -> Option<[u8; DIGEST_LEN]> {
+        (*self.tracked_digest())?;
+
+        match self.entry_hash(key) {
+            Ok(hash) => Some(hash),
+            Err(_) => {
+                *self.tracked_digest() = None;
+                None
+            },
+        }
+    }
+
+    fn tracked_new_entry_hash(&mut self, key: &K, value: &[u8]) -> Option<[u8; DIGEST_LEN]> {
+        (*self.tracked_digest())?;
+
+        match encode_key(key) {
+            Ok(encoded) => Some(digest::entry_hash(&encoded, value)),
+            Err(_) => {
+                *self.tracked_digest() = None;
+                None
+            },
+        }
+    }
+
+    fn toggle_live_digest(&mut self, entry: Option<[u8; DIGEST_LEN]>) {
+        if let (Some(live_digest), Some(entry)) = (self.tracked_digest().as_mut(), entry) {
+            digest::toggle(live_digest, &entry);
+        }
+    }
+
+    // writers have exclusive access, the lock is only needed by live_digest() through &self
+    fn tracked_digest(&mut self) -> &mut Option<[u8; DIGEST_LEN]> {
+        self.live_digest.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Length of the value of the key, taken from the index without reading the db file unless
+    /// compression is enabled, in which case the header of the value is read
+    pub fn value_len(&self, key: &K) -> Result<usize, KVError> {
+        let result = self.value_len_inner(key);
+        self.record_error("value_len", result)
+    }
+
+    fn value_len_inner(&self, key: &K) -> Result<usize, KVError> {
+        self.check_not_expired(key)?;
+        self.stored_value_len(key)
+    }
+
+    fn stored_value_len(&self, key: &K) -> Result<usize, KVError> {
+        let slot = self.index.get(key).ok_or(KVError::KeyDoesNotExist)?;
+        match self.frame_header(key, slot)? {
+            Some(header) => Ok(header.value_len),
+            None => Ok(slot.space),
+        }
+    }
+
+    fn layout(&self, key: &K) -> Result<Layout, KVError> {
+        let slot = self.readable_slot(key)?;
+        match self.frame_header(key, &slot)? {
+            None => Ok(Layout::Plain(slot)),
+            Some(header) if header.is_raw() => Ok(Layout::Plain(Slot {
+                cursor: slot.cursor + header.header_len,
+                space: slot.space - header.header_len,
+            })),
+            Some(header) => Ok(Layout::Compressed { slot, len: header.value_len }),
+        }
+    }
+
+    // header of the value stored in the slot, None when values are stored as given
+    fn frame_header(&self, key: &K, slot: &Slot) -> Result<Option<FrameHeader>, KVError> {
+        if !self.compression.is_enabled() || slot.space == 0 {
+            return Ok(None);
+        }
+
+        let mut prefix = [0; MAX_HEADER_LEN];
+        let prefix = &mut prefix[..MAX_HEADER_LEN.min(slot.space)];
+        let result = self.read_at(slot.cursor, prefix);
+        self.quarantine_on_eof(key, &result);
+        result?;
+
+        compression::parse_header(prefix, slot.space).map(Some)
+    }
+
+    // slot of a key whose value can be read
+    fn readable_slot(&self, key: &K) -> Result<Slot, KVError> {
+        if self.quarantined().contains(key) {
+            return Err(KVError::KeyQuarantined);
+        }
+
+        match self.index.get(key) {
+            Some(slot) => Ok(slot.clone()),
+            None => Err(KVError::KeyDoesNotExist),
+        }
+    }
+
+    fn quarantine_on_eof<T>(&self, key: &K, result: &Result<T, KVError>) {
+        if let Err(KVError::SlotBeyondEof { .. }) = result {
+            if self.eof_policy == EofPolicy::Quarantine {
+                self.quarantined().insert(key.clone());
+            }
+        }
+    }
+
+    /// Tells whether the key is stored, quarantined keys included and expired keys excluded.
+    /// Only the index is looked up
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.index.contains_key(key) && !self.is_expired(key, self.clock.now_millis())
+    }
+
+    /// Number of keys stored, quarantined keys, keys holding an empty value and expired keys
+    /// whose space wasn't reclaimed yet included
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Reports how much of the db file is used by the keys, how much is free and how
+    /// fragmented the free space is
+    pub fn stats(&self) -> Result<StoreStats, KVError> {
+        let result = self.stats_inner();
+        self.record_error("stats", result)
+    }
+
+    fn stats_inner(&self) -> Result<StoreStats, KVError> {
+        let file_len = self.header.data_len()?;
+        let (cache_hits, cache_misses) = {
+            let cache = self.cache();
+            (cache.hits(), cache.misses())
+        };
+
+        Ok(StoreStats {
+            keys: self.index.len(),
+            live_bytes: self.index.values().map(|slot| slot.space).sum(),
+            file_len,
+            free_bytes: self.freelist.total_free_space(),
+            free_fragments: self.freelist.fragment_count(),
+            largest_free_fragment: self.freelist.largest_fragment(),
+            last_cursor: self.last_cursor,
+            quarantined_keys: self.quarantined().len(),
+            cache_hits,
+            cache_misses,
+        })
+    }
+
+    /// Checks that the index, the free list and the db file agree with each other: no two
+    /// slots share bytes, whether they belong to keys or are free, no slot reaches past the end
+    /// of the data or of the file, and the free space counted by the free list adds up. Every
+    /// inconsistency is listed in the report. Nothing is changed, so it can run on a live store
+    /// between writes
+    pub fn verify_integrity(&self) -> Result<IntegrityReport<K>, KVError> {
+        let result = self.verify_integrity_inner(false);
+        self.record_error("verify_integrity", result)
+    }
+
+    /// Checks the store like `verify_integrity` and also reads back the value of every key,
+    /// expired and quarantined keys included, decompressing the compressed ones
+    pub fn verify_integrity_strict(&self) -> Result<IntegrityReport<K>, KVError> {
+        let result = self.verify_integrity_inner(true);
+        self.record_error("verify_integrity_strict", result)
+    }
+
+    fn verify_integrity_inner(&self, strict: bool) -> Result<IntegrityReport<K>, KVError> {
+        let file_len = self.header.data_len()?;
+        let mut report = IntegrityReport::empty();
+        let mut layout = LayoutCheck::new();
+        for span in integrity::sorted_spans(self.index.iter(), self.freelist.fragments().iter().cloned()) {
+            self.verify_span(&span, &mut layout, strict, file_len, &mut report);
+        }
+        integrity::check_free_space(self.freelist.fragments(), self.freelist.total_free_space(), &mut report);
+
+        Ok(report)
+    }
+
+    /// Checks the store like `verify_integrity_strict` a part at a time, so a big store can be
+    /// verified without a single long pass over its db file. Each step resumes from the cursor
+    /// kept in `state` and reads back values until about `budget_bytes` were read, at least one
+    /// value per step. The step that reaches the end of the data returns `StepOutcome::Done`
+    /// with the report of the whole run.
+    ///
+    /// The store can change between steps. Slots past the resume point are checked as they are
+    /// when a step gets to them, so a value moved there by an update can be counted twice, and
+    /// slots written before it are left to the next run. Violations about keys deleted after
+    /// they were found are dropped from the report and counted by `VerifyState::skipped`.
+    /// Without changes between steps, the report is the one of `verify_integrity_strict`
+    pub fn verify_step(&self, budget_bytes: usize, state: &mut VerifyState<K>) -> Result<StepOutcome<K>, KVError> {
+        let result = self.verify_step_inner(budget_bytes, state);
+        self.record_error("verify_step", result)
+    }
+
+    fn verify_step_inner(&self, budget_bytes: usize, state: &mut VerifyState<K>) -> Result<StepOutcome<K>, KVError> {
+        if state.is_done() {
+            return Ok(StepOutcome::Done(state.report.clone()));
+        }
+        let file_len = self.header.data_len()?;
+
+        // the slot checked last by the previous step may have been released or moved since
+        state.layout.retain_furthest(|range, owner| {
+            let slot = Slot { cursor: range.start, space: range.len() };
+            match owner {
+                Some(key) => self.index.get(key) == Some(&slot),
+                None => self.freelist.contains(slot.cursor, slot.space),
+            }
+        });
+
+        // the step ends right before the first value that would go over the budget
+        let mut until = usize::MAX;
+        let mut read = 0;
+        for (cursor, space) in self.live_slots.range(state.cursor..) {
+            if read > 0 && read + space > budget_bytes {
+                until = *cursor;
+                break;
+            }
+            read += space;
+        }
+
+        let slots = self.index.iter().filter(|(_, slot)| (state.cursor..until).contains(&slot.cursor));
+        for span in integrity::sorted_spans(slots, self.freelist.fragments_in(state.cursor..until)) {
+            self.verify_span(&span, &mut state.layout, true, file_len, &mut state.report);
+        }
+        state.cursor = until;
+        if until < usize::MAX {
+            return Ok(StepOutcome::Continue);
+        }
+
+        integrity::check_free_space(self.freelist.fragments(), self.freelist.total_free_space(), &mut state.report);
+        Ok(StepOutcome::Done(state.finish(|key| self.index.contains_key(key))))
+    }
+
+    fn verify_span(&self, span: &Span<K>, layout: &mut LayoutCheck<K>, strict: bool, file_len: u64, report: &mut IntegrityReport<K>) {
+        layout.check(span, self.last_cursor, file_len, report);
+
+        // straight from the file: the cache could hide a broken slot and reads must not
+        // quarantine anything
+        if let (true, Span::Key(key, slot)) = (strict, span) {
+            if let Err(error) = self.retrieve_value(slot.cursor, slot.space) {
+                report.violations.push(Violation::UnreadableValue { key: (*key).clone(), error: error.to_string() });
+            }
+            report.values_read += 1;
+        }
+    }
+
+    /// Options and details recorded when the datastore was created: the crate version, the
+    /// creation time, the compression and the label given by `PersisterBuilder::label`. Snapshots
+    /// keep the info of the store they were taken from. None for stores created before it was
+    /// recorded and for stores built without a datastore
+    pub fn creation_info(&self) -> Option<&CreationInfo> {
+        self.creation.as_ref()
+    }
+
+    /// Iterates over the stored keys in order without reading any value, expired keys are
+    /// skipped
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        let now = self.clock.now_millis();
+        self.index.keys().filter(move |key| !self.is_expired(key, now))
+    }
+
+    /// Removes every expired key from the index in a single pass, releasing their space
+    pub fn purge_expired(&mut self) -> Result<PurgeReport, KVError> {
+        let result = self.purge_expired_inner();
+        let result = self.sync_after_write(result);
+        self.record_error("purge_expired", result)
+    }
+
+    fn purge_expired_inner(&mut self) -> Result<PurgeReport, KVError> {
+        self.check_writable()?;
+        let now = self.clock.now_millis();
+        let expired: Vec<K> = self.expiries.iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut report = PurgeReport::default();
+        for key in expired.iter() {
+            report.freed += self.index.get(key).map_or(0, |slot| slot.space);
+            self.remove_key(key)?;
+            report.removed += 1;
+        }
+
+        Ok(report)
+    }
+
+    /// Replaces the clock keys are expired with, the system clock by default
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn is_expired(&self, key: &K, now: u64) -> bool {
+        is_expired(&self.expiries, key, now)
+    }
+
+    // expired keys read as missing
+    fn check_not_expired(&self, key: &K) -> Result<(), KVError> {
+        match self.is_expired(key, self.clock.now_millis()) {
+            true => Err(KVError::KeyDoesNotExist),
+            false => Ok(()),
+        }
+    }
+
+    // removes the key if it expired, so writes can treat it as missing. Tells whether it did
+    fn reclaim_if_expired(&mut self, key: &K) -> Result<bool, KVError> {
+        if !self.is_expired(key, self.clock.now_millis()) {
+            return Ok(false);
+        }
+
+        self.remove_key(key)?;
+        Ok(true)
+    }
+
+    fn set_expiry(&mut self, key: &K, expires_at: Option<u64>) {
+        match expires_at {
+            Some(expires_at) => self.expiries.insert(key.clone(), expires_at),
+            None => self.expiries.remove(key),
+        };
+    }
+
+    /// Changes the algorithm new values are compressed with. Whether values are compressed at
+    /// all is recorded in the files when the store is created, see
+    /// `PersisterBuilder::compression`, so switching compression on or off fails with
+    /// `KVError::InvalidArgument`. The algorithm can change at any time, every compressed value
+    /// names the one it was compressed with
+    pub fn set_compression(&mut self, compression: Compression) -> Result<(), KVError> {
+        if compression.is_enabled() != self.compression.is_enabled() {
+            let error = KVError::InvalidArgument(format!(
+                "the store was opened with {:?} compression, it can't be switched to {:?}", self.compression, compression
+            ));
+            return self.record_error("set_compression", Err(error));
+        }
+        self.compression = compression;
+
+        Ok(())
+    }
+
+    /// Overwrites with zeros the bytes of every value that is deleted or replaced, as soon as
+    /// its space is released. When zeroing fails the write still took place and its result is
+    /// the `KVError::IOError` of the zeroing. Values logged to the write-ahead log stay in it
+    /// until the next checkpoint, see `Persister::flush`
+    pub fn set_secure_delete(&mut self, enabled: bool) {
+        self.secure_delete = enabled;
+    }
+
+    pub fn set_eof_policy(&mut self, policy: EofPolicy) {
+        self.eof_policy = policy;
+    }
+
+    /// Sets which free slot new values go to when several of them fit,
+    /// `AllocationStrategy::BestFit` by default
+    pub fn set_allocation_strategy(&mut self, strategy: AllocationStrategy) {
+        self.freelist.set_strategy(strategy);
+    }
+
+    /// Sets when writes are synced to disk, `SyncMode::Never` by default. Meant to be called
+    /// right after opening the store
+    pub fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.sync_mode = mode;
+        self.unsynced_ops = 0;
+    }
+
+    /// Keeps checking every new key for serialized collisions with the keys written before it,
+    /// instead of only the first ones written after opening the store. Fails the write with
+    /// `KVError::KeyEncodingCollision` naming both keys when one is found
+    pub fn set_paranoid_key_checks(&mut self, enabled: bool) {
+        self.key_guard.set_paranoid(enabled);
+    }
+
+    /// Flushes the db and index files and syncs their data to disk, which checkpoints the
+    /// write-ahead log
+    pub fn flush(&mut self) -> Result<(), KVError> {
+        let result = self.flush_inner();
+        self.record_error("flush", result)
+    }
+
+    fn flush_inner(&mut self) -> Result<(), KVError> {
+        self.checkpoint()?;
+        self.unsynced_ops = 0;
+
+        Ok(())
+    }
+
+    /// Lets the store be written again after a failed sync, once the writes made since the last
+    /// successful sync are written again and synced. Returns how many of them failed to read
+    /// back as they were written.
+    ///
+    /// A failed `fsync` doesn't mean the data is still waiting to be written: the kernel may
+    /// have dropped the dirty pages, or marked them clean, and a later sync succeeding says
+    /// nothing about them. Retrying the sync or carrying on would lose writes silently, so the
+    /// first failed sync returns its `KVError::IOError` and every later write, flush included,
+    /// fails with `KVError::SyncFailed` until this is called. Reads keep being served.
+    ///
+    /// The writes since the last successful sync are those still in the write-ahead log, which
+    /// checks every entry against its checksum. Each value it holds that is still stored at
+    /// the logged slot is read back and compared with the logged one by checksum, then written
+    /// again whatever the outcome, since pages that read back right may still be the ones a
+    /// failed sync lost. The index file is read back and compared with the index in memory,
+    /// and the records of every logged key and of every key that doesn't match are appended
+    /// again. Values logged without their bytes, by `bulk_load` and reservations, can't be
+    /// written again and are only synced. Everything is then synced, and the store stays
+    /// poisoned if that fails too. Dropping a poisoned store leaves its write-ahead log behind,
+    /// so the next open replays it instead
+    pub fn acknowledge_sync_failure(&mut self) -> Result<usize, KVError> {
+        let result = self.acknowledge_sync_failure_inner();
+        self.record_error("acknowledge_sync_failure", result)
+    }
+
+    fn acknowledge_sync_failure_inner(&mut self) -> Result<usize, KVError> {
+        if !self.sync_failed {
+            return Ok(0);
+        }
+        let mut failed = 0;
+
+        // the last value logged for a key is the one to check, as long as the key still holds
+        // the logged slot. The slots of older values may hold other values by now
+        let mut logged: BTreeMap<K, (Slot, Vec<u8>)> = BTreeMap::new();
+        let mut logged_keys = BTreeSet::new();
+        for op in self.read_wal()?.into_iter().flatten() {
+            match op {
+                WalOp::Put { key, slot, value: Some(value), .. } => {
+                    let key: K = decode_key(&key)?;
+                    logged_keys.insert(key.clone());
+                    logged.insert(key, (slot, value));
+                },
+                WalOp::Put { key, .. } | WalOp::Delete { key } => {
+                    let key: K = decode_key(&key)?;
+                    logged.remove(&key);
+                    logged_keys.insert(key);
+                },
+            }
+        }
+        for (key, (slot, value)) in logged.iter() {
+            if self.index.get(key) != Some(slot) {
+                continue;
+            }
+
+            let mut stored = vec![0; slot.space];
+            let read = self.header.read_data_at(&mut stored, slot.cursor);
+            if read.is_err() || wal::crc32(&stored) != wal::crc32(value) {
+                failed += 1;
+            }
+            self.persist_value(value, slot.cursor)?;
+        }
+
+        // a record cut short or garbled ends what can be trusted of the index file, the
+        // records after it are appended again like the ones that don't match
+        let mut on_disk: BTreeMap<K, Slot> = BTreeMap::new();
+        let mut expiries_on_disk: BTreeMap<K, u64> = BTreeMap::new();
+        self.header.index_file.seek(SeekFrom::Start(self.header.offset))?;
+        let mut reader = BufReader::new(&self.header.index_file);
+        let mut valid_len = self.header.offset;
+        while let Ok(Some(record)) = IndexRecord::decode(&mut reader) {
+            valid_len += record.encode().len() as u64;
+            apply_index_record(&mut on_disk, &mut expiries_on_disk, record)?;
+        }
+        self.header.index_file.set_len(valid_len)?;
+
+        let mut records = vec![];
+        for (key, slot) in self.index.iter() {
+            let expires_at = self.expiries.get(key).copied();
+            let matches = on_disk.get(key) == Some(slot) && expiries_on_disk.get(key).copied() == expires_at;
+            if !matches {
+                failed += 1;
+            }
+            if !matches || logged_keys.contains(key) {
+                records.extend(IndexRecord::Put { key: encode_key(key)?, slot: slot.clone(), expires_at }.encode());
+            }
+        }
+        for key in on_disk.keys().filter(|key| !self.index.contains_key(key)) {
+            failed += 1;
+            records.extend(IndexRecord::Delete { key: encode_key(key)? }.encode());
+        }
+        for key in logged_keys.iter().filter(|key| !self.index.contains_key(key) && !on_disk.contains_key(key)) {
+            records.extend(IndexRecord::Delete { key: encode_key(key)? }.encode());
+        }
+        self.header.append_index(&records)?;
+
+        // a failing sync poisons the store again
+        self.sync_failed = false;
+        self.refresh_read_map();
+        self.flush_inner()?;
+
+        Ok(failed)
+    }
+
+    // syncs the db and index files, after which the write-ahead log holds nothing that isn't
+    // durable and is emptied. Not while a failed sync poisons the store, the log is all that
+    // is left of the writes it may have lost
+    fn checkpoint(&mut self) -> Result<(), KVError> {
+        // nothing was written
+        if self.read_only {
+            return Ok(());
+        }
+        if self.sync_failed {
+            return Err(KVError::SyncFailed);
+        }
+
+        self.sync_files(FileHeader::sync_data)?;
+        self.header.truncate_wal()?;
+        self.sync_files(FileHeader::sync_wal)
+    }
+
+    // a failed sync poisons the store, see `Persister::acknowledge_sync_failure`
+    fn sync_files(&mut self, sync: fn(&FileHeader) -> Result<(), std::io::Error>) -> Result<(), KVError> {
+        sync(&self.header).map_err(|io_error| {
+            self.sync_failed = true;
+            KVError::from(io_error)
+        })
+    }
+
+    // logs a key about to be published at the slot. Values left out of the log must already
+    // be in the db file, which is synced along with the log so the replay can rely on them
+    fn log_put(&mut self, key: &K, slot: &Slot, expires_at: Option<u64>, value: Option<&[u8]>) -> Result<(), KVError> {
+        if self.header.wal_file.is_none() {
+            return Ok(());
+        }
+        if value.is_none() && self.write_is_synced() {
+            self.sync_files(FileHeader::sync_db)?;
+        }
+
+        let op = WalOp::Put { key: encode_key(key)?, slot: slot.clone(), expires_at, value: value.map(<[u8]>::to_vec) };
+        self.log_write(&[op])
+    }
+
+    fn log_delete(&mut self, key: &K) -> Result<(), KVError> {
+        if self.header.wal_file.is_none() {
+            return Ok(());
+        }
+
+        let op = WalOp::Delete { key: encode_key(key)? };
+        self.log_write(&[op])
+    }
+
+    // appends the operations of a write to the write-ahead log before the write touches the db
+    // or index file, and syncs it when the write is synced. A log grown past
+    // `WAL_CHECKPOINT_SIZE` is checkpointed first
+    fn log_write(&mut self, ops: &[WalOp]) -> Result<(), KVError> {
+        let mut len = match self.header.wal_file.as_ref() {
+            Some(wal_file) => wal_file.metadata()?.len(),
+            None => return Ok(()),
+        };
+        if len >= WAL_CHECKPOINT_SIZE {
+            self.checkpoint()?;
+            len = 0;
+        }
+
+        self.wal_pending.get_or_insert(len);
+        if let Some(wal_file) = self.header.wal_file.as_ref() {
+            wal_file.write_all_at(&wal::encode_entry(ops), len)?;
+        }
+        if self.write_is_synced() {
+            self.sync_files(FileHeader::sync_wal)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keys whose slot was found to extend past the end of the db file under
+    /// `EofPolicy::Quarantine`
+    pub fn quarantined_keys(&self) -> Vec<K> {
+        self.quarantined().iter().cloned().collect()
+    }
+
+    /// Removes every quarantined key from the index, returning how many were purged
+    pub fn purge_quarantined(&mut self) -> Result<usize, KVError> {
+        let result = self.purge_quarantined_inner();
+        let result = self.sync_after_write(result);
+        self.record_error("purge_quarantined", result)
+    }
+
+    fn purge_quarantined_inner(&mut self) -> Result<usize, KVError> {
+        self.check_writable()?;
+        let keys: Vec<K> = std::mem::take(self.quarantined.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner())).into_iter().collect();
+        for key in keys.iter() {
+            self.remove_key(key)?;
+        }
+
+        Ok(keys.len())
+    }
+
+    /// Replaces the value of an existing key, which becomes persistent if it had a time to live.
+    /// The new value is written to free space and the previous one is only released once the
+    /// index points at it, so a failed write leaves the key as it was
+    pub fn update_value(&mut self, key: &K, value: &[u8]) -> Result<(), KVError> {
+        let result = self.update_value_inner(key, value, None);
+        let result = self.sync_after_write(result);
+        self.record_error("update_value", result)
+    }
+
+    /// Replaces the value of an existing key, which expires once `ttl` has elapsed
+    pub fn update_value_with_ttl(&mut self, key: &K, value: &[u8], ttl: Duration) -> Result<(), KVError> {
+        let expires_at = expiry::deadline(self.clock.now_millis(), ttl);
+        let result = self.update_value_inner(key, value, Some(expires_at));
+        let result = self.sync_after_write(result);
+        self.record_error("update_value_with_ttl", result)
+    }
+
+    fn update_value_inner(&mut self, key: &K, value: &[u8], expires_at: Option<u64>) -> Result<(), KVError> {
+        self.check_writable()?;
+        if self.reclaim_if_expired(key)? {
+            return Err(KVError::KeyDoesNotExist);
+        }
+
+        let previous_slot = match self.index.get(key) {
+            Some(slot) => slot.clone(),
+            None => return Err(KVError::KeyDoesNotExist),
+        };
+        let stored = compression::encode(self.compression, value)?;
+        let removed = self.tracked_entry_hash(key);
+        self.invalidate_cached(key);
+
+        // the value gets a new slot, from the free list or at the end of the data, while the
+        // previous one is still held, so that a failed or torn write leaves the previous value
+        // and its index entry as they were
+        let last_cursor = self.last_cursor;
+        let mut from_freelist = false;
+        let mut slot = Slot { cursor: 0, space: stored.len() };
+        if slot.space > 0 {
+            match self.freelist.retrieve_free_space(slot.space) {
+                Some(cursor) => {
+                    slot.cursor = cursor;
+                    from_freelist = true;
+                },
+                None => {
+                    slot.cursor = self.last_cursor;
+                    self.check_storage_limit(slot.cursor + slot.space)?;
+                    self.last_cursor = slot.cursor + slot.space;
+                },
+            }
+        }
+
+        let result = self.check_no_overlap(slot.cursor, slot.space)
+            .and_then(|_| self.log_put(key, &slot, expires_at, Some(&stored)))
+            .and_then(|_| self.persist_value(&stored, slot.cursor))
+            .and_then(|_| self.persist_key(key, &slot, expires_at));
+        if let Err(error) = result {
+            if from_freelist {
+                self.freelist.insert_and_merge_free_space(slot.cursor, slot.space);
+            }
+            self.last_cursor = last_cursor;
+            return Err(error);
+        }
+
+        self.release_space(previous_slot.cursor, previous_slot.space);
+
+        // update the index
+        self.index.insert(key.clone(), Slot{cursor: slot.cursor, space: slot.space});
+        if previous_slot.space > 0 {
+            self.live_slots.remove(&previous_slot.cursor);
+        }
+        if slot.space > 0 {
+            self.live_slots.insert(slot.cursor, slot.space);
+        }
+        self.set_expiry(key, expires_at);
+        let added = self.tracked_new_entry_hash(key, value);
+        self.toggle_live_digest(removed);
+        self.toggle_live_digest(added);
+
+        self.scrub_uncovered(&previous_slot, &slot)
+    }
+
+    /// Stores the value under the key, inserting the key when it is missing and updating its
+    /// value otherwise
+    pub fn put(&mut self, key: &K, value: &[u8]) -> Result<PutOutcome, KVError> {
+        let result = self.put_inner(key, value, None);
+        let result = self.sync_after_write(result);
+        self.record_error("put", result)
+    }
+
+    /// Stores the value under the key like `put`, the key expires once `ttl` has elapsed. An
+    /// expired key is inserted again
+    pub fn put_with_ttl(&mut self, key: &K, value: &[u8], ttl: Duration) -> Result<PutOutcome, KVError> {
+        let expires_at = expiry::deadline(self.clock.now_millis(), ttl);
+        let result = self.put_inner(key, value, Some(expires_at));
+        let result = self.sync_after_write(result);
+        self.record_error("put_with_ttl", result)
+    }
+
+    fn put_inner(&mut self, key: &K, value: &[u8], expires_at: Option<u64>) -> Result<PutOutcome, KVError> {
+        self.check_writable()?;
+        self.reclaim_if_expired(key)?;
+        if self.index.contains_key(key) {
+            self.update_value_inner(key, value, expires_at).map(|_| PutOutcome::Updated)
+        } else {
+            self.insert_kv_inner(key, value, expires_at).map(|_| PutOutcome::Inserted)
+        }
+    }
+
+    /// Replaces the value of an existing key with the one returned by `f` given the current
+    /// value, fails with `KVError::KeyDoesNotExist` without calling `f` otherwise. `f` gets a copy
+    /// of the whole value read before anything is written, and the new value is stored like by
+    /// `update_value` but keeps the time to live of the key
+    pub fn modify_value<F>(&mut self, key: &K, f: F) -> Result<(), KVError>
+    where F: FnOnce(&[u8]) -> Vec<u8> {
+        let result = self.modify_value_inner(key, f);
+        let result = self.sync_after_write(result);
+        self.record_error("modify_value", result)
+    }
+
+    /// Modifies the value of the key like `modify_value` when it is stored, or inserts `default`
+    /// without calling `f` otherwise
+    pub fn modify_or_insert<F>(&mut self, key: &K, default: Vec<u8>, f: F) -> Result<PutOutcome, KVError>
+    where F: FnOnce(&[u8]) -> Vec<u8> {
+        let result = self.modify_or_insert_inner(key, default, f);
+        let result = self.sync_after_write(result);
+        self.record_error("modify_or_insert", result)
+    }
+
+    fn modify_value_inner<F>(&mut self, key: &K, f: F) -> Result<(), KVError>
+    where F: FnOnce(&[u8]) -> Vec<u8> {
+        self.check_writable()?;
+        if self.reclaim_if_expired(key)? {
+            return Err(KVError::KeyDoesNotExist);
+        }
+
+        let current = self.get_value_inner(key)?;
+        let modified = f(&current);
+        let expires_at = self.expiries.get(key).copied();
+        self.update_value_inner(key, &modified, expires_at)
+    }
+
+    fn modify_or_insert_inner<F>(&mut self, key: &K, default: Vec<u8>, f: F) -> Result<PutOutcome, KVError>
+    where F: FnOnce(&[u8]) -> Vec<u8> {
+        self.check_writable()?;
+        self.reclaim_if_expired(key)?;
+        if self.index.contains_key(key) {
+            self.modify_value_inner(key, f).map(|_| PutOutcome::Updated)
+        } else {
+            self.insert_kv_inner(key, &default, None).map(|_| PutOutcome::Inserted)
+        }
+    }
+
+    pub fn delete_kv(&mut self, key: &K) -> Result<(), KVError> {
+        let result = self.delete_kv_inner(key);
+        let result = self.sync_after_write(result);
+        self.record_error("delete_kv", result)
+    }
+
+    fn delete_kv_inner(&mut self, key: &K) -> Result<(), KVError> {
+        self.check_writable()?;
+        if self.reclaim_if_expired(key)? {
+            return Err(KVError::KeyDoesNotExist);
+        }
+
+        self.remove_key(key)
+    }
+
+    // drops the key from the index file and the index, releasing its space
+    fn remove_key(&mut self, key: &K) -> Result<(), KVError> {
+        let val = match self.index.get(key) {
+            Some(val) => val.clone(),
+            None => return Err(KVError::KeyDoesNotExist),
+        };
+        let removed = self.tracked_entry_hash(key);
+        self.invalidate_cached(key);
+
+        // tombstone the key in the index file before releasing anything
+        self.log_delete(key)?;
+        self.delete_key(key)?;
+        let released = self.release_slot(&val);
+        self.expiries.remove(key);
+        self.toggle_live_digest(removed);
+
+        // remove key from index
+        match self.index.remove(key) {
+            Some(_) => released,
+            None => Err(KVError::KeyDoesNotExist), // should never happen
+        }
+    }
+
+    /// Removes every key within the range and returns how many were removed, expired keys
+    /// included. All the keys are tombstoned by a single write to the index file, then their
+    /// slots are released and merged with the free slots around them. A range matching no key
+    /// returns `Ok(0)` without touching the files
+    pub fn delete_range<R: RangeBounds<K>>(&mut self, range: R) -> Result<usize, KVError> {
+        let result = self.delete_range_inner(range);
+        let result = self.sync_after_write(result);
+        self.record_error("delete_range", result)
+    }
+
+    fn delete_range_inner<R: RangeBounds<K>>(&mut self, range: R) -> Result<usize, KVError> {
+        self.check_writable()?;
+        if !is_valid_range(&range) {
+            return Ok(0);
+        }
+
+        let mut removed: Vec<(K, Slot)> = self.index.range(range)
+            .map(|(key, slot)| (key.clone(), slot.clone()))
+            .collect();
+        if removed.is_empty() {
+            return Ok(0);
+        }
+
+        let mut ops = Vec::with_capacity(removed.len());
+        let mut records = vec![];
+        for (key, _) in removed.iter() {
+            self.invalidate_cached(key);
+            let key = encode_key(key)?;
+            records.extend(IndexRecord::Delete { key: key.clone() }.encode());
+            ops.push(WalOp::Delete { key });
+        }
+        if self.header.wal_file.is_some() {
+            self.log_write(&ops)?;
+        }
+        self.header.append_index(&records)?;
+
+        // released from the highest cursor down, so a run of slots ending at the tail of the
+        // data walks `last_cursor` back all the way
+        removed.sort_by_key(|(_, slot)| std::cmp::Reverse(slot.cursor));
+        let mut released = Ok(());
+        for (key, slot) in removed.iter() {
+            let removed_hash = self.tracked_entry_hash(key);
+            self.index.remove(key);
+            self.expiries.remove(key);
+            self.toggle_live_digest(removed_hash);
+            released = released.and(self.release_slot(slot));
+        }
+        self.freelist.compact();
+
+        released.map(|_| removed.len())
+    }
+
+    /// Applies all the operations of the batch as a unit. Space for every value is claimed up
+    /// front and the values are written sorted by cursor, coalescing the ones that end up next
+    /// to each other into a single write. The index records of the whole batch are then
+    /// appended at once. If a value or the records can't be written every claimed slot is
+    /// released and the index is left untouched, in memory and on disk.
+    ///
+    /// Puts insert or replace the key, and the last operation on a key wins when the batch
+    /// contains several of them. Deleting a key that is not stored fails the whole batch with
+    /// `KVError::KeyDoesNotExist` before anything is written
+    pub fn write_batch(&mut self, batch: WriteBatch<K>) -> Result<(), KVError> {
+        let result = self.write_batch_inner(batch);
+        let result = self.sync_after_write(result);
+        self.record_error("write_batch", result)
+    }
+
+    fn write_batch_inner(&mut self, batch: WriteBatch<K>) -> Result<(), KVError> {
+        self.check_writable()?;
+        let mut ops: BTreeMap<K, Option<Vec<u8>>> = BTreeMap::new();
+        for (key, op) in batch.into_ops() {
+            ops.insert(key, op);
+        }
+        for key in ops.keys() {
+            self.reclaim_if_expired(key)?;
+        }
+
+        if ops.iter().any(|(key, op)| op.is_none() && !self.index.contains_key(key)) {
+            return Err(KVError::KeyDoesNotExist);
+        }
+        for (key, op) in ops.iter() {
+            if op.is_some() && !self.index.contains_key(key) {
+                self.check_new_key(key)?;
+            }
+        }
+
+        // entries replaced or removed by the batch and entries it adds, for the live digest
+        let mut digest_changes = vec![];
+        for (key, op) in ops.iter() {
+            if self.index.contains_key(key) {
+                digest_changes.push(self.tracked_entry_hash(key));
+                self.invalidate_cached(key);
+            }
+            if let Some(value) = op {
+                digest_changes.push(self.tracked_new_entry_hash(key, value));
+            }
+        }
+
+        let compression = self.compression;
+        let stored = ops.values()
+            .map(|op| op.as_ref().map(|value| compression::encode(compression, value)).transpose())
+            .collect::<Result<Vec<_>, KVError>>()?;
+
+        // claim the space of every value, remembering where it came from in case of rollback
+        let previous_last_cursor = self.last_cursor;
+        let mut allocations: Vec<Option<(Slot, bool)>> = Vec::with_capacity(ops.len());
+        for value in stored.iter() {
+            allocations.push(value.as_ref().map(|value| self.allocate(value.len())));
+        }
+
+        let mut writes: Vec<(usize, &[u8])> = vec![];
+        for (value, allocation) in stored.iter().zip(allocations.iter()) {
+            if let (Some(value), Some((slot, _))) = (value, allocation) {
+                if slot.space > 0 {
+                    writes.push((slot.cursor, value.as_ref()));
+                }
+            }
+        }
+
+        let logged = match self.header.wal_file.is_some() {
+            true => ops.iter().zip(stored.iter()).zip(allocations.iter())
+                .map(|(((key, _), value), allocation)| Ok(match (value, allocation) {
+                    (Some(value), Some((slot, _))) => WalOp::Put {
+                        key: encode_key(key)?, slot: slot.clone(), expires_at: None, value: Some(value.to_vec()),
+                    },
+                    _ => WalOp::Delete { key: encode_key(key)? },
+                }))
+                .collect::<Result<Vec<_>, KVError>>(),
+            false => Ok(vec![]),
+        };
+
+        // the records of the whole batch go to the index file in a single append, which is cut
+        // back if it fails, so the batch is recorded entirely or not at all
+        let records = ops.iter().zip(allocations.iter())
+            .map(|((key, _), allocation)| Ok(match allocation {
+                Some((slot, _)) => IndexRecord::Put { key: encode_key(key)?, slot: slot.clone(), expires_at: None },
+                None => IndexRecord::Delete { key: encode_key(key)? },
+            }.encode()))
+            .collect::<Result<Vec<_>, KVError>>()
+            .map(|records| records.concat());
+
+        let written = self.check_storage_limit(self.last_cursor)
+            .and_then(|_| writes.iter().try_for_each(|(cursor, value)| self.check_no_overlap(*cursor, value.len())))
+            .and(logged)
+            .and_then(|logged| self.log_write(&logged))
+            .and_then(|_| self.write_sorted(writes))
+            .and(records)
+            .and_then(|records| self.header.append_index(&records).map_err(KVError::from));
+        if let Err(error) = written {
+            // give the space back in reverse order so pieces split from the same free slot
+            // are merged back together
+            for (slot, from_freelist) in allocations.iter().flatten().rev() {
+                if *from_freelist {
+                    self.freelist.insert_and_merge_free_space(slot.cursor, slot.space);
+                }
+            }
+            self.last_cursor = previous_last_cursor;
+            return Err(error);
+        }
+
+        // the batch is recorded, publish the keys and free the slots they replaced
+        let mut released = vec![];
+        for ((key, _), allocation) in ops.into_iter().zip(allocations) {
+            self.expiries.remove(&key);
+            let previous = match allocation {
+                Some((slot, _)) => {
+                    if slot.space > 0 {
+                        self.live_slots.insert(slot.cursor, slot.space);
+                    }
+                    self.index.insert(key, slot)
+                },
+                None => self.index.remove(&key),
+            };
+            released.extend(previous);
+        }
+        for change in digest_changes {
+            self.toggle_live_digest(change);
+        }
+
+        // from the highest cursor down, so slots ending at the tail of the data walk
+        // `last_cursor` back all the way
+        released.sort_by_key(|slot| std::cmp::Reverse(slot.cursor));
+        let mut scrubbed = Ok(());
+        for slot in released.iter() {
+            scrubbed = scrubbed.and(self.release_slot(slot));
+        }
+
+        scrubbed
+    }
+
+    /// Inserts all the pairs, laying their values out one after the other at the end of the
+    /// data and writing them in large sequential chunks. The free list is not looked at, which
+    /// makes it the fastest way to fill a fresh store, ideally with pairs sorted by key.
+    ///
+    /// Every key is checked before anything is written: a key given twice or already stored
+    /// fails the whole load with `KVError::KeyAlreadyExist` and leaves the store untouched
+    pub fn bulk_load<I: IntoIterator<Item = (K, Vec<u8>)>>(&mut self, pairs: I) -> Result<(), KVError> {
+        let result = self.bulk_load_inner(pairs.into_iter().collect());
+        let result = self.sync_after_write(result);
+        self.record_error("bulk_load", result)
+    }
+
+    fn bulk_load_inner(&mut self, pairs: Vec<(K, Vec<u8>)>) -> Result<(), KVError> {
+        self.check_writable()?;
+        let now = self.clock.now_millis();
+        let mut seen = BTreeSet::new();
+        for (key, _) in pairs.iter() {
+            if !seen.insert(key) || (self.index.contains_key(key) && !self.is_expired(key, now)) {
+                return Err(KVError::KeyAlreadyExist);
+            }
+        }
+        for (key, _) in pairs.iter() {
+            self.check_new_key(key)?;
+        }
+        for (key, _) in pairs.iter() {
+            self.reclaim_if_expired(key)?;
+        }
+
+        // no live slot may reach past the end of the data, where the values are laid out
+        let start = self.last_cursor;
+        if let Some((live_cursor, live_space)) = self.live_slots.iter().next_back() {
+            if live_cursor + live_space > start {
+                return Err(KVError::InvariantViolation(format!(
+                    "live slot {}..{} reaches past the end of the data at {}", live_cursor, live_cursor + live_space, start
+                )));
+            }
+        }
+
+        // values are staged until the chunk is full, the staged bytes always end at `cursor`
+        let mut cursor = start;
+        let mut staged: Vec<u8> = vec![];
+        let mut slots = Vec::with_capacity(pairs.len());
+        let mut digest_changes = Vec::with_capacity(pairs.len());
+        for (key, value) in pairs.iter() {
+            let stored = compression::encode(self.compression, value)?;
+            self.check_storage_limit(cursor + stored.len())?;
+            if !staged.is_empty() && staged.len() + stored.len() > BULK_LOAD_CHUNK_SIZE {
+                self.persist_value(&staged, cursor - staged.len())?;
+                staged.clear();
+            }
+            if stored.len() > BULK_LOAD_CHUNK_SIZE {
+                self.persist_value(&stored, cursor)?;
+            } else {
+                staged.extend_from_slice(&stored);
+            }
+
+            slots.push(match stored.len() {
+                0 => Slot { cursor: 0, space: 0 },
+                space => Slot { cursor, space },
+            });
+            cursor += stored.len();
+            digest_changes.push(self.tracked_new_entry_hash(key, value));
+        }
+        if !staged.is_empty() {
+            self.persist_value(&staged, cursor - staged.len())?;
+        }
+
+        // the values are durable before the keys are logged, the log only holds the slots
+        if self.header.wal_file.is_some() {
+            if self.write_is_synced() {
+                self.sync_files(FileHeader::sync_db)?;
+            }
+            let ops = pairs.iter().zip(slots.iter())
+                .map(|((key, _), slot)| Ok(WalOp::Put { key: encode_key(key)?, slot: slot.clone(), expires_at: None, value: None }))
+                .collect::<Result<Vec<_>, KVError>>()?;
+            self.log_write(&ops)?;
+        }
+
+        let mut records = vec![];
+        for ((key, _), slot) in pairs.iter().zip(slots.iter()) {
+            records.extend(IndexRecord::Put { key: encode_key(key)?, slot: slot.clone(), expires_at: None }.encode());
+        }
+        self.header.append_index(&records)?;
+
+        self.last_cursor = cursor;
+        for ((key, _), slot) in pairs.into_iter().zip(slots) {
+            if slot.space > 0 {
+                self.live_slots.insert(slot.cursor, slot.space);
+            }
+            self.index.insert(key, slot);
+        }
+        for change in digest_changes {
+            self.toggle_live_digest(change);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the values down the db file so they are packed from its start without holes, then
+    /// truncates the file at the end of the data. The moves are done in chunks, each one logged
+    /// and recorded in the index file before the next one, so a failure leaves some values moved
+    /// and the others where they were, all of them readable
+    pub fn compact_datastore(&mut self) -> Result<CompactionReport, KVError> {
+        let result = self.compact_datastore_inner();
+        let result = self.sync_after_write(result);
+        self.record_error("compact_datastore", result)
+    }
+
+    fn compact_datastore_inner(&mut self) -> Result<CompactionReport, KVError> {
+        self.check_writable()?;
+        let file_len = self.header.data_len()?;
+
+        // new slot of every value, taken in cursor order so a value never moves up
+        let mut by_cursor: Vec<(&K, &Slot)> = self.index.iter().filter(|(_, slot)| slot.space > 0).collect();
+        by_cursor.sort_by_key(|(_, slot)| slot.cursor);
+        let mut moves: Vec<(K, Slot, Slot)> = vec![];
+        let mut end = 0;
+        for (key, slot) in by_cursor {
+            if slot.cursor != end {
+                moves.push((key.clone(), slot.clone(), Slot { cursor: end, space: slot.space }));
+            }
+            end += slot.space;
+        }
+
+        let mut report = CompactionReport::default();
+        let mut moves = moves.into_iter().peekable();
+        while moves.peek().is_some() {
+            let mut chunk = vec![];
+            let mut chunk_len = 0;
+            while let Some(next) = moves.next_if(|_| chunk.is_empty() || chunk_len < BULK_LOAD_CHUNK_SIZE) {
+                chunk_len += next.1.space;
+                chunk.push(next);
+            }
+
+            if let Err(error) = self.move_values(&chunk) {
+                // the index holds the values moved so far
+                self.rebuild_freelist();
+                return Err(error);
+            }
+            report.values_moved += chunk.len();
+            report.bytes_moved += chunk_len;
+        }
+
+        // the new slots must be durable before the file is cut under the old ones
+        self.rebuild_freelist();
+        self.checkpoint()?;
+        self.header.set_data_len(self.last_cursor as u64)?;
+        report.bytes_reclaimed = file_len.saturating_sub(self.last_cursor as u64);
+
+        Ok(report)
+    }
+
+    // copies the values to their new slots and records them, the moves are packed one after
+    // the other below the slots they come from
+    fn move_values(&mut self, moves: &[(K, Slot, Slot)]) -> Result<(), KVError> {
+        let mut values = Vec::with_capacity(moves.len());
+        for (_, from, _) in moves {
+            let mut stored = vec![0; from.space];
+            self.read_at(from.cursor, &mut stored)?;
+            values.push(stored);
+        }
+
+        let mut ops = vec![];
+        let mut records = vec![];
+        for ((key, _, to), stored) in moves.iter().zip(values.iter()) {
+            let expires_at = self.expiries.get(key).copied();
+            let key = encode_key(key)?;
+            records.extend(IndexRecord::Put { key: key.clone(), slot: to.clone(), expires_at }.encode());
+            ops.push(WalOp::Put { key, slot: to.clone(), expires_at, value: Some(stored.clone()) });
+        }
+        if self.header.wal_file.is_some() {
+            self.log_write(&ops)?;
+        }
+
+        let writes = moves.iter().zip(values.iter())
+            .map(|((_, _, to), stored)| (to.cursor, stored.as_slice()))
+            .collect();
+        self.write_sorted(writes)?;
+        self.header.append_index(&records)?;
+
+        for (key, from, to) in moves {
+            self.live_slots.remove(&from.cursor);
+            self.live_slots.insert(to.cursor, to.space);
+            self.index.insert(key.clone(), to.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Most recent errors returned by the methods of the store, from the oldest to the newest.
+    /// Only the last few errors are kept, see `set_recent_errors_capacity`
+    pub fn recent_errors(&self) -> Vec<ErrorRecord> {
+        self.errors.records()
+    }
+
+    pub fn clear_recent_errors(&self) {
+        self.errors.clear();
+    }
+
+    /// Changes how many errors are kept, dropping the oldest ones if needed. 0 disables the log
+    pub fn set_recent_errors_capacity(&mut self, capacity: usize) {
+        self.errors.set_capacity(capacity);
+    }
+
+    /// Iterates over all the key/value pairs in key order
+    pub fn iter(&self) -> Iter<'_, K> {
+        self.range(..)
+    }
+
+    /// Iterates in key order over the key/value pairs whose key falls in `range`. Values are
+    /// read lazily, a failed read is yielded as an error item and iteration can go on. Keys
+    /// expired when the iterator is created are skipped
+    pub fn range<R: RangeBounds<K>>(&self, range: R) -> Iter<'_, K> {
+        Iter {
+            range: is_valid_range(&range).then(|| self.index.range(range)),
+            quarantined: &self.quarantined,
+            header: &self.header,
+            compression: self.compression,
+            expiries: &self.expiries,
+            now: self.clock.now_millis(),
+        }
+    }
+
+    /// Iterates in key order over the key/value pairs whose key starts with `prefix`, an empty
+    /// prefix matches every key
+    pub fn scan_prefix(&self, prefix: &K) -> Iter<'_, K> where K: PrefixKey {
+        let end = match prefix.prefix_end() {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+
+        self.range((Bound::Included(prefix.clone()), end))
+    }
+
+    /// Writes a copy of the store to a new datastore at `path` with its values packed in key
+    /// order, leaving no free space behind. Writes need `&mut self`, so they can't happen while
+    /// the copy is made and the snapshot reflects a single point in time; through a
+    /// `SharedPersister`, hold its read guard for the duration.
+    ///
+    /// Expired keys are left out and the others keep their expiration. Quarantined keys are
+    /// skipped and counted in the returned info. Values are copied as stored, so the snapshot of
+    /// a store with compression enabled must be opened with compression enabled too. Fails with
+    /// `KVError::DatastoreAlreadyExists` instead of touching existing files, and removes the
+    /// files of the snapshot if it can't be completed
+    pub fn snapshot_to(&self, path: &Path) -> Result<SnapshotInfo, KVError> {
+        // the index file and the write-ahead log are named after the file name of the path
+        if path.file_name().is_none() {
+            let error = KVError::InvalidArgument(format!("snapshot path {:?} has no file name", path));
+            return self.record_error("snapshot_to", Err(error));
+        }
+
+        let result = self.snapshot_range(&path.to_string_lossy(), (Bound::Unbounded, Bound::Unbounded));
+        self.record_error("snapshot_to", result)
+    }
+
+    fn snapshot_range(&self, datastore: &str, range: (Bound<&K>, Bound<&K>)) -> Result<SnapshotInfo, KVError> {
+        let header = FileHeader::create_new(datastore, self.compression)
+            .map_err(|io_error| match io_error.kind() {
+                ErrorKind::AlreadyExists => KVError::DatastoreAlreadyExists,
+                _ => KVError::from(io_error),
+            })?;
+
+        let mut snapshot = Persister::with_header(header);
+        snapshot.compression = self.compression;
+        let result = self.write_snapshot(snapshot, range);
+        if result.is_err() {
+            let _ = FileHeader::remove_files(datastore);
+        }
+
+        result
+    }
+
+    // copies the keys in the range, the caller removes the files if it fails
+    fn write_snapshot(&self, mut snapshot: Persister<K>, range: (Bound<&K>, Bound<&K>)) -> Result<SnapshotInfo, KVError> {
+        let mut info = SnapshotInfo::default();
+        let now = self.clock.now_millis();
+
+        // a copy of the store keeps the info of its creation, fields of later releases included
+        let creation = self.creation.clone()
+            .unwrap_or_else(|| CreationInfo::new(now, self.compression.name(), None));
+        snapshot.write_creation_info(creation)?;
+        let quarantined = self.quarantined().clone();
+
+        let mut chunk = vec![];
+        for (key, slot) in self.index.range::<K, _>(range) {
+            if self.is_expired(key, now) {
+                continue;
+            }
+            if quarantined.contains(key) {
+                info.skipped_quarantined += 1;
+                continue;
+            }
+
+            // copy the stored bytes chunk by chunk right after the previous value
+            let copy = Slot { cursor: if slot.space > 0 { snapshot.last_cursor } else { 0 }, space: slot.space };
+            let mut done = 0;
+            while done < slot.space {
+                let len = STREAM_CHUNK_SIZE.min(slot.space - done);
+                chunk.resize(len, 0);
+                let result = self.read_at(slot.cursor + done, &mut chunk);
+                self.quarantine_on_eof(key, &result);
+                result?;
+
+                snapshot.persist_value(&chunk, copy.cursor + done)?;
+                done += len;
+            }
+
+            snapshot.persist_key(key, &copy, self.expiries.get(key).copied())?;
+            snapshot.last_cursor += copy.space;
+            info.keys += 1;
+        }
+        info.bytes = snapshot.last_cursor;
+        snapshot.flush()?;
+
+        Ok(info)
+    }
+
+    /// Splits the store into `boundaries.len() + 1` datastores in `dest_dir`, each written like
+    /// a snapshot by `Persister::snapshot_to`. The datastore `part<i>` holds the keys from
+    /// `boundaries[i - 1]` included up to `boundaries[i]` excluded, the first and last parts
+    /// being open ended, and parts without keys are still created. Values are streamed chunk by
+    /// chunk and the store itself is left untouched.
+    ///
+    /// The boundaries must be sorted and unique, otherwise it fails with
+    /// `KVError::InvalidArgument` before anything is written. Fails with
+    /// `KVError::DatastoreAlreadyExists` if the files of a part are already present, and removes
+    /// the parts already written if it can't be completed
+    pub fn split_into(&self, boundaries: &[K], dest_dir: &Path) -> Result<SplitReport, KVError> {
+        if let Some(pair) = boundaries.windows(2).find(|pair| pair[0] >= pair[1]) {
+            let error = KVError::InvalidArgument(format!("split boundaries {:?} and {:?} are not sorted and unique", pair[0], pair[1]));
+            return self.record_error("split_into", Err(error));
+        }
+
+        let result = self.split_into_inner(boundaries, dest_dir);
+        self.record_error("split_into", result)
+    }
+
+    fn split_into_inner(&self, boundaries: &[K], dest_dir: &Path) -> Result<SplitReport, KVError> {
+        let mut report = SplitReport::default();
+        let mut lower = Bound::Unbounded;
+        for part in 0..=boundaries.len() {
+            let upper = boundaries.get(part).map_or(Bound::Unbounded, Bound::Excluded);
+            let datastore = dest_dir.join(format!("part{}", part)).to_string_lossy().to_string();
+            match self.snapshot_range(&datastore, (lower, upper)) {
+                Ok(info) => report.parts.push(info),
+                Err(error) => {
+                    // the files of the part that failed are already gone
+                    for written in 0..part {
+                        let _ = FileHeader::remove_files(&dest_dir.join(format!("part{}", written)).to_string_lossy());
+                    }
+                    return Err(error);
+                },
+            }
+            lower = boundaries.get(part).map_or(Bound::Unbounded, Bound::Included);
+        }
+
+        Ok(report)
+    }
+
+    /// Turns the store into an immutable read view backed by a memory map of the db file.
+    /// Quarantined and expired keys are left out of the view. The view hands out the stored bytes as they
+    /// are, so it can't be taken from a store with compression enabled
+    #[cfg(feature = "mmap")]
+    pub fn freeze(mut self) -> Result<FrozenPersister<K>, KVError> {
+        if self.compression.is_enabled() {
+            return Err(KVError::CompressionError("frozen views can't decompress values".to_string()));
+        }
+        self.flush()?;
+
+        // the store still syncs its own handles when dropped, the view gets its own
+        let header = self.header.try_clone()?;
+        let quarantined = std::mem::take(&mut *self.quarantined());
+        let expiries = std::mem::take(&mut self.expiries);
+        let now = self.clock.now_millis();
+
+        let entries = std::mem::take(&mut self.index).into_iter()
+            .filter(|(key, _)| !quarantined.contains(key) && !is_expired(&expiries, key, now))
+            .map(|(key, slot)| (key, slot.cursor, slot.space))
+            .collect();
+
+        FrozenPersister::new(header, entries)
+    }
+
+    // a panic while holding the lock can't leave the set in a broken state, so poisoning is
+    // ignored
+    fn quarantined(&self) -> MutexGuard<'_, BTreeSet<K>> {
+        self.quarantined.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // same as for the quarantined keys, a panic can't leave the cache half updated
+    fn cache(&self) -> MutexGuard<'_, ValueCache<K>> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // drops the cached value of a key whose value is about to change
+    fn invalidate_cached(&mut self, key: &K) {
+        self.cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(key);
+    }
+
+    // only keys that are not in the index yet can collide with another key
+    fn check_new_key(&mut self, key: &K) -> Result<(), KVError> {
+        // encoding also rejects a key too long for the index file before anything is written
+        let encoded = encode_key(key)?;
+        if !self.key_guard.is_active() {
+            return Ok(());
+        }
+
+        self.key_guard.check(key, &encoded)
+    }
+
+    // counts a successful write and syncs the files when the sync mode asks for it, a failed
+    // sync is reported as the result of the write
+    fn sync_after_write<T>(&mut self, result: Result<T, KVError>) -> Result<T, KVError> {
+        // a write that failed after being logged must not be replayed, the files as they are
+        // become the checkpoint. While a failed sync keeps the log from being emptied, the
+        // entry of the write is cut off it instead
+        if let (Some(logged_from), Err(_)) = (self.wal_pending.take(), &result) {
+            match (self.sync_failed, self.header.wal_file.as_ref()) {
+                (true, Some(wal_file)) => { let _ = wal_file.set_len(logged_from); },
+                _ => { let _ = self.checkpoint(); },
+            }
+        }
+        self.refresh_read_map();
+        let value = result?;
+
+        let due = self.write_is_synced();
+        self.unsynced_ops += 1;
+        if due {
+            self.flush_inner()?;
+        }
+
+        Ok(value)
+    }
+
+    // whether the sync mode syncs the files once the write in progress succeeds
+    fn write_is_synced(&self) -> bool {
+        match self.sync_mode {
+            SyncMode::Always => true,
+            SyncMode::Never => false,
+            SyncMode::EveryNOps(n) => self.unsynced_ops + 1 >= n,
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), KVError> {
+        if self.read_only {
+            return Err(KVError::ReadOnly);
+        }
+
+        match self.sync_failed {
+            true => Err(KVError::SyncFailed),
+            false => Ok(()),
+        }
+    }
+
+    // `needed` is the end of the data once the write is done
+    fn check_storage_limit(&self, needed: usize) -> Result<(), KVError> {
+        if self.storage_limit > 0 && needed > self.storage_limit {
+            return Err(KVError::StorageLimitExceeded { limit: self.storage_limit, needed });
+        }
+
+        Ok(())
+    }
+
+    fn record_error<T>(&self, operation: &'static str, result: Result<T, KVError>) -> Result<T, KVError> {
+        if let Err(error) = &result {
+            self.errors.push(operation, error);
+        }
+
+        result
+    }
+
+    // claims space for a value from the free list or from the end of the file, the returned flag
+    // tells whether the space came from the free list
+    fn allocate(&mut self, space: usize) -> (Slot, bool) {
+        if space == 0 {
+            return (Slot { cursor: 0, space: 0 }, false);
+        }
+
+        match self.freelist.retrieve_free_space(space) {
+            Some(cursor) => (Slot { cursor, space }, true),
+            None => {
+                let cursor = self.last_cursor;
+                self.last_cursor += space;
+                (Slot { cursor, space }, false)
+            },
+        }
+    }
+
+    // rebuilds the free list and the end of the data from the slots of the index
+    fn rebuild_freelist(&mut self) {
+        let strategy = self.freelist.strategy();
+        (self.freelist, self.last_cursor) = FreeList::new_from_index(self.index.values().collect());
+        self.freelist.set_strategy(strategy);
+    }
+
+    // gives back a slot that was just allocated and never got referenced by the index
+    fn unclaim(&mut self, slot: &Slot, from_freelist: bool) {
+        if slot.space == 0 {
+            return;
+        }
+
+        if from_freelist {
+            self.freelist.insert_and_merge_free_space(slot.cursor, slot.space);
+        } else if slot.cursor + slot.space == self.last_cursor {
+            self.last_cursor = slot.cursor;
+        }
+    }
+
+    // copies exactly `slot.space` bytes from the reader into the slot, chunk by chunk
+    fn stream_into_slot<R: Read>(&self, reader: &mut R, slot: &Slot, mut hasher: Option<&mut DigestBuilder>) -> Result<(), KVError> {
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(slot.space)];
+        let mut done = 0;
+        while done < slot.space {
+            let len = chunk.len().min(slot.space - done);
+            let read = read_up_to(reader, &mut chunk[..len])?;
+            if read < len {
+                return Err(KVError::StreamLengthMismatch { expected: slot.space, read: done + read });
+            }
+
+            self.persist_value(&chunk[..len], slot.cursor + done)?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk[..len]);
+            }
+            done += len;
+        }
+
+        if read_up_to(reader, &mut [0])? > 0 {
+            return Err(KVError::StreamLengthMismatch { expected: slot.space, read: slot.space + 1 });
+        }
+
+        Ok(())
+    }
+
+    // hands the space of a slot that is no longer referenced back to the free list, or gives it
+    // back to the end of the file when it was the last slot. The space is released even if it
+    // can't be zeroed under secure delete
+    fn release_slot(&mut self, slot: &Slot) -> Result<(), KVError> {
+        if slot.space == 0 {
+            return Ok(());
+        }
+
+        self.live_slots.remove(&slot.cursor);
+        self.release_space(slot.cursor, slot.space);
+
+        self.scrub(slot.cursor, slot.space)
+    }
+
+    // gives back space no live slot uses anymore: space at the end of the data moves the end
+    // back, any other space goes to the free list
+    fn release_space(&mut self, cursor: usize, space: usize) {
+        if space == 0 {
+            return;
+        }
+
+        if cursor + space != self.last_cursor {
+            self.freelist.insert_and_merge_free_space(cursor, space);
+            return;
+        }
+
+        // the free slots left at the new end of the data go back to the tail as well, so
+        // `last_cursor` keeps ending at the highest live slot
+        self.last_cursor = cursor;
+        while let Some(free) = self.freelist.retrieve_ending_at(self.last_cursor) {
+            self.last_cursor = free.cursor;
+        }
+    }
+
+    // zeroes the bytes of `previous` outside of `slot`, the slot that replaced it
+    fn scrub_uncovered(&self, previous: &Slot, slot: &Slot) -> Result<(), KVError> {
+        let previous_end = previous.cursor + previous.space;
+        if slot.space == 0 {
+            return self.scrub(previous.cursor, previous.space);
+        }
+
+        let before_end = previous_end.min(slot.cursor);
+        let after_start = previous.cursor.max(slot.cursor + slot.space);
+        self.scrub(previous.cursor, before_end.saturating_sub(previous.cursor))?;
+        self.scrub(after_start, previous_end.saturating_sub(after_start))
+    }
+
+    // overwrites released space with zeros under secure delete
+    fn scrub(&self, cursor: usize, space: usize) -> Result<(), KVError> {
+        if !self.secure_delete || space == 0 {
+            return Ok(());
+        }
+
+        let zeros = vec![0; STREAM_CHUNK_SIZE.min(space)];
+        let mut done = 0;
+        while done < space {
+            let len = zeros.len().min(space - done);
+            self.persist_value(&zeros[..len], cursor + done)?;
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    // writes the values sorted by cursor, values that are contiguous on disk go in a single write
+    fn write_sorted(&mut self, mut writes: Vec<(usize, &[u8])>) -> Result<(), KVError> {
+        writes.sort_by_key(|(cursor, _)| *cursor);
+
+        let mut run_cursor = 0;
+        let mut run: Vec<u8> = vec![];
+        for (cursor, value) in writes {
+            if !run.is_empty() && run_cursor + run.len() != cursor {
+                self.persist_value(&run, run_cursor)?;
+                run.clear();
+            }
+            if run.is_empty() {
+                run_cursor = cursor;
+            }
+            run.extend_from_slice(value);
+        }
+
+        if !run.is_empty() {
+            self.persist_value(&run, run_cursor)?;
+        }
+
+        Ok(())
+    }
+
+    // refuses any write to `cursor..cursor+space` that would step over the data of a live slot.
+    // Live slots never overlap, so only the closest slot starting before the end of the write
+    // needs to be checked
+    fn check_no_overlap(&self, cursor: usize, space: usize) -> Result<(), KVError> {
+        if space == 0 {
+            return Ok(());
+        }
+
+        if let Some((live_cursor, live_space)) = self.live_slots.range(..cursor + space).next_back() {
+            if live_cursor + live_space > cursor {
+                return Err(KVError::InvariantViolation(format!(
+                    "write to {}..{} overlaps live slot {}..{}",
+                    cursor, cursor + space, live_cursor, live_cursor + live_space
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    // values are written and read at their offset, the position of the db file is never used
+    fn persist_value(&self, data: &[u8], cursor: usize) -> Result<(), KVError> {
+        self.header.write_data_at(data, cursor)
+            .map_err(KVError::from)
+    }
+
+    fn retrieve_value(&self, cursor: usize, space: usize) -> Result<Vec<u8>, KVError> {
+        let mut stored = vec![0; space];
+        self.read_at(cursor, &mut stored)?;
+        compression::decode(self.compression, stored)
+    }
+
+    // reads from the map of the db file when mmap reads are on and the map covers the range
+    fn read_at(&self, cursor: usize, buffer: &mut [u8]) -> Result<(), KVError> {
+        #[cfg(feature = "mmap")]
+        {
+            let offset = self.header.offset as usize;
+            if let Some(bytes) = self.read_map.as_ref().and_then(|read_map| read_map.slice(offset + cursor, buffer.len())) {
+                buffer.copy_from_slice(bytes);
+                return Ok(());
+            }
+        }
+
+        read_slot_into(&self.header, cursor, buffer)
+    }
+
+    // redoes the map after the db file changed length, reads past the end of a map that
+    // couldn't be redone fall back to the file
+    fn refresh_read_map(&mut self) {
+        #[cfg(feature = "mmap")]
+        if let Some(read_map) = self.read_map.as_mut() {
+            let _ = read_map.refresh(&self.header.db_file);
+        }
+    }
+
+    /// Rebuilds the in-memory index by replaying the records stored in the index file, the
+    /// last record of each key wins. The free list is reconstructed from the holes left
+    /// between the slots of the index.
+    ///
+    /// The writes left in the write-ahead log by a crash are then applied again and the log is
+    /// checkpointed. A record torn at the end of the index file is dropped, since the write it
+    /// belonged to is replayed from the log, and replay stops at the first torn or corrupt
+    /// entry of the log
+    pub fn load_index(&mut self) -> Result<(), KVError> {
+        let result = self.load_index_inner();
+        self.record_error("load_index", result)
+    }
+
+    fn load_index_inner(&mut self) -> Result<(), KVError> {
+        self.cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+        let mut index: BTreeMap<K, Slot> = BTreeMap::new();
+        let mut expiries: BTreeMap<K, u64> = BTreeMap::new();
+        let mut creation = None;
+
+        self.header.index_file.seek(SeekFrom::Start(self.header.offset))?;
+        let mut reader = BufReader::new(&self.header.index_file);
+        let mut valid_len = self.header.offset;
+
+        loop {
+            let record = match IndexRecord::decode(&mut reader) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                // without a write-ahead log nothing can stand in for the torn record
+                Err(io_error) if io_error.kind() == ErrorKind::UnexpectedEof && self.header.wal_file.is_some() => {
+                    if !self.read_only {
+                        self.header.index_file.set_len(valid_len)?;
+                    }
+                    break;
+                },
+                Err(io_error) if io_error.kind() == ErrorKind::InvalidData => {
+                    return Err(KVError::Corruption(format!("index file: {}", io_error)));
+                },
+                Err(io_error) => return Err(KVError::IOError(io_error)),
+            };
+            valid_len += record.encode().len() as u64;
+            if let IndexRecord::Config { block } = &record {
+                creation = Some(CreationInfo::decode(block)?);
+            }
+            apply_index_record(&mut index, &mut expiries, record)?;
+        }
+
+        let wal_entries = self.read_wal()?;
+        // replaying the log writes to both files
+        if self.read_only && !wal_entries.is_empty() {
+            return Err(KVError::ReadOnly);
+        }
+        for ops in wal_entries {
+            for op in ops {
+                let record = match op {
+                    WalOp::Put { key, slot, expires_at, value } => {
+                        if let Some(value) = value {
+                            self.persist_value(&value, slot.cursor)?;
+                        }
+                        IndexRecord::Put { key, slot, expires_at }
+                    },
+                    WalOp::Delete { key } => IndexRecord::Delete { key },
+                };
+                self.append_index_record(&record)?;
+                apply_index_record(&mut index, &mut expiries, record)?;
+            }
+        }
+
+        self.live_slots = index.values()
+            .filter(|slot| slot.space > 0)
+            .map(|slot| (slot.cursor, slot.space))
+            .collect();
+        self.index = index;
+        self.rebuild_freelist();
+        self.expiries = expiries;
+        self.creation = creation;
+
+        if self.header.wal_file.is_some() {
+            self.checkpoint()?;
+        }
+        self.refresh_read_map();
+
+        Ok(())
+    }
+
+    // entries of the write-ahead log up to the first torn or corrupt one, which a crash left
+    // behind before the write it logged touched anything
+    fn read_wal(&self) -> Result<Vec<Vec<WalOp>>, KVError> {
+        let mut wal_file = match self.header.wal_file.as_ref() {
+            Some(wal_file) => wal_file,
+            None => return Ok(vec![]),
+        };
+
+        let mut bytes = vec![];
+        wal_file.seek(SeekFrom::Start(0))
+            .and_then(|_| wal_file.read_to_end(&mut bytes))?;
+
+        let mut reader = bytes.as_slice();
+        let mut entries = vec![];
+        while let Ok(Some(ops)) = wal::decode_entry(&mut reader) {
+            entries.push(ops);
+        }
+
+        Ok(entries)
+    }
+
+    fn persist_key(&mut self, key: &K, slot: &Slot, expires_at: Option<u64>) -> Result<(), KVError> {
+        let record = IndexRecord::Put { key: encode_key(key)?, slot: slot.clone(), expires_at };
+        self.append_index_record(&record)
+    }
+
+    fn delete_key(&mut self, key: &K) -> Result<(), KVError> {
+        let record = IndexRecord::Delete { key: encode_key(key)? };
+        self.append_index_record(&record)
+    }
+
+    fn append_index_record(&mut self, record: &IndexRecord) -> Result<(), KVError> {
+        self.header.append_index(&record.encode()).map_err(KVError::from)
+    }
+}
+
+impl<K> Drop for Persister<K> {
+    fn drop(&mut self) {
+        // best effort, there is no one left to report a failure to. A poisoned store keeps its
+        // log for the next open to replay
+        if !self.read_only && !self.sync_failed && self.header.sync_data().is_ok() {
+            let _ = self.header.truncate_wal().and_then(|_| self.header.sync_wal());
+        }
+    }
+}
+
+/// Iterator over the key/value pairs of a `Persister` in key order, values are read from the db
+/// file as the iterator advances. Quarantined and expired keys are skipped
+pub struct Iter<'a, K> {
+    range: Option<btree_map::Range<'a, K, Slot>>, // None for ranges that can't contain any key
+    quarantined: &'a Mutex<BTreeSet<K>>,
+    header: &'a FileHeader,
+    compression: Compression,
+    expiries: &'a BTreeMap<K, u64>,
+    now: u64,
+}
+
+impl<K: Ord + Clone> Iterator for Iter<'_, K> {
+    type Item = Result<(K, Vec<u8>), KVError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the lock is only held while skipping, reads done by the caller between two items can
+        // still quarantine keys
+        let quarantined = self.quarantined.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (key, slot) = self.range.as_mut()?
+            .find(|(key, _)| !quarantined.contains(key) && !is_expired(self.expiries, key, self.now))?;
+        drop(quarantined);
+
+        let value = read_slot(self.header, slot.cursor, slot.space)
+            .and_then(|stored| compression::decode(self.compression, stored));
+        Some(value.map(|value| (key.clone(), value)))
+    }
+}
+
+fn is_expired<K: Ord>(expiries: &BTreeMap<K, u64>, key: &K, now: u64) -> bool {
+    expiries.get(key).is_some_and(|expires_at| *expires_at <= now)
+}
+
+// BTreeMap::range panics when the start of the range is after its end, or when both bounds
+// exclude the same key
+fn is_valid_range<K: Ord, R: RangeBounds<K>>(range: &R) -> bool {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end) | Bound::Excluded(end)) => start <= end,
+        _ => true,
+    }
+}
+
+fn read_slot(header: &FileHeader, cursor: usize, space: usize) -> Result<Vec<u8>, KVError> {
+    let mut buffer = vec![0; space];
+    read_slot_into(header, cursor, &mut buffer)?;
+
+    Ok(buffer)
+}
+
+// fills as much of the buffer as the reader can give, stopping early only at its end
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, KVError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(io_error) if io_error.kind() == ErrorKind::Interrupted => {},
+            Err(io_error) => return Err(KVError::IOError(io_error)),
+        }
+    }
+
+    Ok(filled)
+}
+
+fn read_slot_into(header: &FileHeader, cursor: usize, buffer: &mut [u8]) -> Result<(), KVError> {
+    header.read_data_at(buffer, cursor)
+        .map_err(|io_error| classify_read_error(header, io_error, cursor, buffer.len()))
+}
+
+// tells apart a slot that points past the end of the file (truncated db file) from any other
+// failure while reading it
+fn classify_read_error(header: &FileHeader, io_error: std::io::Error, cursor: usize, space: usize) -> KVError {
+    if io_error.kind() == ErrorKind::UnexpectedEof {
+        if let Ok(file_len) = header.data_len() {
+            if (cursor + space) as u64 > file_len {
+                return KVError::SlotBeyondEof { cursor, len: space, file_len };
+            }
+        }
+    }
+
+    KVError::IOError(io_error)
+}
+
+fn encode_key<K: Serialize>(key: &K) -> Result<Vec<u8>, KVError> {
+    let encoded = bincode::serialize(key).map_err(|error| KVError::SerializationError(error.to_string()))?;
+    // the index file reads a longer key as corruption
+    if encoded.len() > indexlog::MAX_KEY_LEN {
+        return Err(KVError::InvalidArgument(format!("key of {} bytes is over {} bytes", encoded.len(), indexlog::MAX_KEY_LEN)));
+    }
+    Ok(encoded)
+}
+
+fn decode_key<K: DeserializeOwned>(bytes: &[u8]) -> Result<K, KVError> {
+    bincode::deserialize(bytes).map_err(|error| KVError::SerializationError(error.to_string()))
+}
+
+// applies a record of the index file to the index being rebuilt by `load_index`
+fn apply_index_record<K: Ord + Clone + DeserializeOwned>(index: &mut BTreeMap<K, Slot>, expiries: &mut BTreeMap<K, u64>, record: IndexRecord) -> Result<(), KVError> {
+    match record {
+        IndexRecord::Put { key, slot, expires_at } => {
+            let key: K = decode_key(&key)?;
+            match expires_at {
+                Some(expires_at) => expiries.insert(key.clone(), expires_at),
+                None => expiries.remove(&key),
+            };
+            index.insert(key, slot);
+        },
+        IndexRecord::Delete { key } => {
+            let key = decode_key(&key)?;
+            expiries.remove(&key);
+            index.remove(&key);
+        },
+        // read by `load_index`, it holds no key
+        IndexRecord::Config { .. } => {},
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+    use std::fs::{File, OpenOptions};
+    use crate::fileheader::faults;
+    use crate::format::{FileKind, FORMAT_HEADER_LEN};
+    use std::path::Path;
+    use super::*;
+
+    fn new_mock_persister() -> Persister<String> {
+        Persister::with_header(FileHeader {
+            db_file: tempfile::tempfile().unwrap(),
+            index_file: tempfile::tempfile().unwrap(),
+            wal_file: None,
+            offset: 0,
+        })
+    }
+
+    // opens (without truncating) a datastore living in `dir`, so tests can drop a persister
+    // and open the same files again
+    fn open_mock_persister(dir: &Path) -> Persister<String> {
+        let open = |name: &str| OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(name)).unwrap();
+
+        let mut persister = new_mock_persister();
+        persister.header = FileHeader { db_file: open("db"), index_file: open("index_db"), wal_file: None, offset: 0 };
+        persister
+    }
+
+    #[test]
+    fn test_insert_kv_empty_values() {
+        for_each_backend(|mut persister| {
+            assert_eq!(Ok(()), persister.insert_kv(&"empty_value".to_string(), &[]));
+            assert_eq!(
+                Slot{cursor: 0, space: 0},
+                persister.index.get("empty_value").unwrap().clone()
+            );
+            assert_eq!(0, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn test_insert_kv_two_times_same_key() {
+        for_each_backend(|mut persister| {
+            assert_eq!(Ok(()), persister.insert_kv(&"key_duplicated".to_string(), &[]));
+            assert_eq!(KVError::KeyAlreadyExist, persister.insert_kv(&"key_duplicated".to_string(), &[]).unwrap_err());
+            assert_eq!(0, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn test_insert_kv_multiple_kvs() {
+        for_each_backend(|mut persister| {
+            let keys: Vec<String> = vec![
+                "key_1".to_string(),
+                "key_2".to_string(),
+                "key_3".to_string(),
+                "key_4".to_string(),
+                "key_5".to_string(),
+            ];
+
+            let values: Vec<Vec<u8>> = vec![
+                vec![b'a', b'b', b'c'],
+                vec![b'd', b'e', b'f', b'g'],
+                vec![b'h', b'i', b'j', b'k', b'l'],
+                vec![b'm', b'n', b'o', b'p'],
+                vec![b'q', b'r', b's', b't', b'u', b'v'],
+            ];
+
+            let slots: Vec<Slot> = vec![
+                Slot { space: 3, cursor: 0 },
+                Slot { space: 4, cursor: 3 },
+                Slot { space: 5, cursor: 7 },
+                Slot { space: 4, cursor: 12 },
+                Slot { space: 6, cursor: 16 },
+            ];
+
+            // insert multiple non empty values and make sure that cursor is incremented
+            let mut expected_cursor = 0;
+            for kv in keys.iter().zip(values.iter()) {
+                assert_eq!(expected_cursor, persister.last_cursor);
+                persister.insert_kv(kv.0, kv.1).unwrap();
+
+                expected_cursor += kv.1.len();
+            }
+
+            // make sure that all keys can be retrieved with the corresponding slot
+            for (iteration, kv) in keys.iter().zip(values.iter()).enumerate() {
+                assert_eq!(
+                    slots[iteration],
+                    persister.index.get(kv.0).unwrap().clone()
+                );
+            }
+
+            // check that the resulting file is the same
+            persister.header.db_file.flush().unwrap();
+            assert_slots_eq(
+                  open_file("tests/data/insert_kv-01.dat"),
+                  &persister.header,
+                  &slots
+            )
+        });
+    }
+
+    #[test]
+    fn test_insert_kv_check_free_spots() {
+        for_each_backend(|mut persister| {
+            // create a free spot in the middle of two keys with size 2 and test whether we
+            // make use of the free space generated
+            let _ = persister.insert_kv(&"key_1".to_string(), b"abc");
+            let _ = persister.insert_kv(&"key_2".to_string(), b"de");
+            let _ = persister.insert_kv(&"key_3".to_string(), b"fgh");
+
+            // delete the middle kv
+            persister.delete_kv(&"key_2".to_string()).unwrap();
+
+            let _ = persister.insert_kv(&"key_4".to_string(), b"ijk");
+            assert_eq!(8, persister.index.get("key_4").unwrap().cursor);
+            assert_eq!(3, persister.index.get("key_4").unwrap().space);
+
+            let _ = persister.insert_kv(&"key_5".to_string(), b"l");
+            assert_eq!(3, persister.index.get("key_5").unwrap().cursor);
+            assert_eq!(1, persister.index.get("key_5").unwrap().space);
+
+            // check that the resulting file is the same
+            persister.header.db_file.flush().unwrap();
+            assert_slots_eq(
+                open_file("tests/data/insert_kv-02.dat"),
+                &persister.header,
+                &[Slot{space: 3, cursor: 0},
+                    Slot{space: 3, cursor: 5},
+                    Slot{space: 3, cursor: 8},
+                    Slot{space: 1, cursor: 3}]
+            )
+        });
+    }
+
+    #[test]
+    fn test_get_value() {
+        for_each_backend(|mut persister| {
+            persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+            assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+
+            assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"non_existent_key".to_string()).unwrap_err())
+        });
+    }
+
+    #[test]
+    fn test_update_value() {
+        for_each_backend(|mut persister| {
+            let _ = persister.insert_kv(&"key1".to_string(), b"acd");
+            let _ = persister.update_value(&"key1".to_string(), b"efg");
+            assert_eq!(6, persister.last_cursor);
+
+            assert_eq!(vec![b'e', b'f', b'g'], persister.get_value(&"key1".to_string()).unwrap());
+
+            // delete the kv and try to update again
+            let _ = persister.delete_kv(&"key1".to_string());
+            assert_eq!(
+                KVError::KeyDoesNotExist,
+                persister.update_value(&"key1".to_string(), b"efg").unwrap_err()
+            );
+            assert_eq!(0, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn test_update_value_with_more_space() {
+        for_each_backend(|mut persister| {
+            let _ = persister.insert_kv(&"key1".to_string(), b"acd");
+            let _ = persister.update_value(&"key1".to_string(), b"efgh");
+            assert_eq!(7, persister.last_cursor);
+
+            assert_eq!(vec![b'e', b'f', b'g', b'h'], persister.get_value(&"key1".to_string()).unwrap());
+
+            // delete the kv and try to update again
+            let _ = persister.delete_kv(&"key1".to_string());
+            assert_eq!(0, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn test_update_value_with_middle_space_not_enough() {
+        for_each_backend(|mut persister| {
+            let _ = persister.insert_kv(&"key1".to_string(), b"acd");
+            let _ = persister.insert_kv(&"key2".to_string(), b"efg");
+            let _ = persister.insert_kv(&"key3".to_string(), b"hij");
+
+            // try to update middle kv with a bigger value
+            let _ = persister.update_value(&"key2".to_string(), b"klmn");
+            assert_eq!(13, persister.last_cursor);
+
+            assert_eq!(vec![b'k', b'l', b'm', b'n'], persister.get_value(&"key2".to_string()).unwrap());
+
+            // delete the kv and try to update again
+            let _ = persister.delete_kv(&"key2".to_string());
+            assert_eq!(9, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn delete_kv() {
+        for_each_backend(|mut persister| {
+            let _ = persister.insert_kv(&"key1".to_string(), b"acd");
+            let _ = persister.delete_kv(&"key1".to_string());
+            assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key1".to_string()).unwrap_err());
+
+            assert_eq!(0, persister.last_cursor);
+        });
+    }
+
+    #[test]
+    fn test_write_overlapping_live_slot_is_refused() {
+        let mut persister = new_mock_persister();
+
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"def").unwrap();
+
+        // corrupt the freelist so it hands out the space owned by key1
+        persister.freelist.insert_free_space(0, 3);
+
+        assert!(matches!(
+            persister.insert_kv(&"key3".to_string(), b"xyz").unwrap_err(),
+            KVError::InvariantViolation(_)
+        ));
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key3".to_string()).unwrap_err());
+
+        // same for an update of key2 growing into the corrupted free space
+        persister.freelist.insert_free_space(0, 4);
+        assert!(matches!(
+            persister.update_value(&"key2".to_string(), b"ghij").unwrap_err(),
+            KVError::InvariantViolation(_)
+        ));
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'd', b'e', b'f'], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_update_value_never_overwrites_its_own_slot() {
+        let mut persister = new_mock_persister();
+
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"def").unwrap();
+        persister.update_value(&"key1".to_string(), b"gh").unwrap();
+        persister.update_value(&"key2".to_string(), b"ijkl").unwrap();
+
+        assert_eq!(vec![b'g', b'h'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'i', b'j', b'k', b'l'], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(vec![(&6, &2), (&8, &4)], persister.live_slots.iter().collect::<Vec<_>>());
+        assert_eq!(6, persister.freelist.total_free_space());
+    }
+
+    #[test]
+    fn test_get_value_slot_beyond_eof() {
+        let mut persister = new_mock_persister();
+
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"def").unwrap();
+
+        // truncate the db file behind the store
+        persister.header.db_file.set_len(4).unwrap();
+
+        let expected = KVError::SlotBeyondEof { cursor: 3, len: 3, file_len: 4 };
+        assert_eq!(expected, persister.get_value(&"key2".to_string()).unwrap_err());
+        assert_eq!(expected, persister.get_value(&"key2".to_string()).unwrap_err());
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert!(persister.quarantined_keys().is_empty());
+    }
+
+    #[test]
+    fn test_get_value_slot_beyond_eof_quarantine() {
+        let mut persister = new_mock_persister();
+        persister.set_eof_policy(EofPolicy::Quarantine);
+
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"def").unwrap();
+        persister.insert_kv(&"key3".to_string(), b"ghi").unwrap();
+        persister.header.db_file.set_len(3).unwrap();
+
+        // the first read reports the problem, later ones fail fast
+        assert_eq!(
+            KVError::SlotBeyondEof { cursor: 3, len: 3, file_len: 3 },
+            persister.get_value(&"key2".to_string()).unwrap_err()
+        );
+        assert_eq!(KVError::KeyQuarantined, persister.get_value(&"key2".to_string()).unwrap_err());
+        let _ = persister.get_value(&"key3".to_string()).unwrap_err();
+        assert_eq!(
+            vec!["key2".to_string(), "key3".to_string()],
+            persister.quarantined_keys()
+        );
+
+        // purging reclaims the index entries and their space
+        assert_eq!(Ok(2), persister.purge_quarantined());
+        assert!(persister.quarantined_keys().is_empty());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key2".to_string()).unwrap_err());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key3".to_string()).unwrap_err());
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(3, persister.last_cursor);
+    }
+
+    #[test]
+    fn test_load_index_round_trip() {
+        let keys: Vec<String> = vec![
+            "k".to_string(),
+            "a_much_longer_key_than_the_others".to_string(),
+            "key\0with\nodd\u{ff}bytes".to_string(),
+            "empty".to_string(),
+            "updated".to_string(),
+            "deleted".to_string(),
+        ];
+        let values: Vec<Vec<u8>> = vec![
+            vec![b'a'],
+            vec![b'b', b'c', b'd', b'e'],
+            vec![0, 255, 10, 13],
+            vec![],
+            vec![b'f', b'g'],
+            vec![b'h', b'i', b'j'],
+        ];
+
+        // the index is loaded again from the files of the store, whatever backs them
+        for_each_backend(|mut persister| {
+            for (key, value) in keys.iter().zip(values.iter()) {
+                persister.insert_kv(key, value).unwrap();
+            }
+            persister.update_value(&"updated".to_string(), b"klm").unwrap();
+            persister.delete_kv(&"deleted".to_string()).unwrap();
+            persister.load_index().unwrap();
+
+            for (key, value) in keys.iter().zip(values.iter()).take(4) {
+                assert_eq!(value.clone(), persister.get_value(key).unwrap());
+            }
+            assert_eq!(vec![b'k', b'l', b'm'], persister.get_value(&"updated".to_string()).unwrap());
+            assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"deleted".to_string()).unwrap_err());
+            assert_eq!(5, persister.index.len());
+            assert_eq!(17, persister.last_cursor);
+
+            // the reloaded store keeps working and keeps persisting its index
+            persister.insert_kv(&"deleted".to_string(), b"n").unwrap();
+            persister.load_index().unwrap();
+            assert_eq!(vec![b'n'], persister.get_value(&"deleted".to_string()).unwrap());
+            assert_eq!(6, persister.index.len());
+        });
+
+        // and from files opened again
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let mut persister = open_mock_persister(dir.path());
+            for (key, value) in keys.iter().zip(values.iter()) {
+                persister.insert_kv(key, value).unwrap();
+            }
+            persister.update_value(&"updated".to_string(), b"klm").unwrap();
+            persister.delete_kv(&"deleted".to_string()).unwrap();
+        }
+
+        let mut persister = open_mock_persister(dir.path());
+        persister.load_index().unwrap();
+        assert_eq!(vec![b'k', b'l', b'm'], persister.get_value(&"updated".to_string()).unwrap());
+        assert_eq!(5, persister.index.len());
+    }
+
+    #[test]
+    fn test_reopen_reuses_holes() {
+        for_each_backend(|mut persister| {
+            persister.insert_kv(&"key1".to_string(), &[b'a'; 3]).unwrap();
+            persister.insert_kv(&"key2".to_string(), &[b'b'; 7]).unwrap();
+            persister.insert_kv(&"key3".to_string(), &[b'c'; 5]).unwrap();
+            persister.insert_kv(&"key4".to_string(), &[b'd'; 4]).unwrap();
+            persister.delete_kv(&"key2".to_string()).unwrap();
+            persister.delete_kv(&"key4".to_string()).unwrap();
+
+            // the hole left by key2 is free again, the space of key4 is past the end of the data
+            persister.load_index().unwrap();
+            assert_eq!(15, persister.last_cursor);
+            assert_eq!((7, 1), (persister.freelist.total_free_space(), persister.freelist.fragment_count()));
+
+            persister.insert_kv(&"key5".to_string(), &[b'e'; 6]).unwrap();
+            persister.insert_kv(&"key6".to_string(), &[b'f'; 2]).unwrap();
+            assert_eq!(Slot {cursor: 3, space: 6}, persister.index[&"key5".to_string()]);
+            assert_eq!(Slot {cursor: 15, space: 2}, persister.index[&"key6".to_string()]);
+            assert_eq!(vec![b'c'; 5], persister.get_value(&"key3".to_string()).unwrap());
+        });
+    }
+
+    #[test]
+    fn test_load_index_truncated_record() {
+        let dir = tempfile::tempdir().unwrap();
+
+        {
+            let mut persister = open_mock_persister(dir.path());
+            persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+            let len = persister.header.index_file.metadata().unwrap().len();
+            persister.header.index_file.set_len(len - 1).unwrap();
+        }
+
+        let mut persister = open_mock_persister(dir.path());
+        assert!(matches!(persister.load_index().unwrap_err(), KVError::IOError(_)));
+    }
+
+    #[test]
+    fn test_reopen_existing_datastore() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+            persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+            persister.insert_kv(&"key3".to_string(), b"fghi").unwrap();
+            persister.insert_kv(&"key4".to_string(), b"j").unwrap();
+            persister.insert_kv(&"key5".to_string(), b"kl").unwrap();
+            persister.insert_kv(&"empty".to_string(), &[]).unwrap();
+
+            // leave holes at 3..5 and 9..10 and drop the tail key
+            persister.delete_kv(&"key2".to_string()).unwrap();
+            persister.delete_kv(&"key4".to_string()).unwrap();
+            persister.delete_kv(&"key5".to_string()).unwrap();
+        }
+
+        // reopening must not wipe the existing data
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'f', b'g', b'h', b'i'], persister.get_value(&"key3".to_string()).unwrap());
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key2".to_string()).unwrap_err());
+        assert_eq!(9, persister.last_cursor);
+
+        // new inserts land in the recovered holes before growing the file
+        persister.insert_kv(&"key6".to_string(), b"mn").unwrap();
+        assert_eq!(Slot {cursor: 3, space: 2}, persister.index.get("key6").unwrap().clone());
+        persister.insert_kv(&"key7".to_string(), b"o").unwrap();
+        assert_eq!(Slot {cursor: 9, space: 1}, persister.index.get("key7").unwrap().clone());
+        assert_eq!(10, persister.last_cursor);
+        assert_eq!(vec![b'f', b'g', b'h', b'i'], persister.get_value(&"key3".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_create_new_and_open_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        assert!(matches!(
+            Persister::<String>::open_existing(datastore.clone(), 0),
+            Err(KVError::DatastoreDoesNotExist)
+        ));
+
+        {
+            let mut persister: Persister<String> = Persister::create_new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        }
+
+        assert!(matches!(
+            Persister::<String>::create_new(datastore.clone(), 0),
+            Err(KVError::DatastoreAlreadyExists)
+        ));
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![b'a'], persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut persister = new_mock_persister();
+        assert_eq!(0, persister.iter().count());
+
+        // insert out of order, iteration must come back sorted by key
+        let entries: Vec<(String, Vec<u8>)> = vec![
+            ("c".to_string(), vec![b'3', b'3']),
+            ("a".to_string(), vec![b'1']),
+            ("e".to_string(), vec![]),
+            ("b".to_string(), vec![]),
+            ("d".to_string(), vec![b'4', b'4', b'4']),
+        ];
+        for (key, value) in entries.iter() {
+            persister.insert_kv(key, value).unwrap();
+        }
+
+        let mut expected = entries.clone();
+        expected.sort();
+        assert_eq!(expected, persister.iter().collect::<Result<Vec<(String, Vec<u8>)>, KVError>>().unwrap());
+    }
+
+    #[test]
+    fn test_range() {
+        let mut persister = new_mock_persister();
+        for (i, key) in ["b", "d", "f", "h"].iter().enumerate() {
+            persister.insert_kv(&key.to_string(), &vec![i as u8; i]).unwrap();
+        }
+
+        let keys = |iter: Iter<'_, String>| -> Vec<String> {
+            iter.map(|item| item.unwrap().0).collect()
+        };
+
+        assert_eq!(vec!["d", "f"], keys(persister.range("c".to_string().."g".to_string())));
+        assert_eq!(vec!["d", "f", "h"], keys(persister.range("d".to_string()..="h".to_string())));
+        assert_eq!(vec!["b", "d"], keys(persister.range(.."f".to_string())));
+        assert_eq!(vec!["h"], keys(persister.range("g".to_string()..)));
+        assert_eq!(
+            vec![("f".to_string(), vec![2, 2])],
+            persister.range("e".to_string().."g".to_string()).map(|item| item.unwrap()).collect::<Vec<_>>()
+        );
+
+        // empty ranges, or ranges entirely before or after the stored keys
+        assert!(keys(persister.range("a".to_string().."b".to_string())).is_empty());
+        assert!(keys(persister.range("i".to_string()..)).is_empty());
+        assert!(keys(persister.range("d".to_string().."d".to_string())).is_empty());
+        assert!(keys(persister.range("g".to_string().."c".to_string())).is_empty());
+        assert!(keys(persister.range((Bound::Excluded("d".to_string()), Bound::Excluded("d".to_string())))).is_empty());
+    }
+
+    #[test]
+    fn test_iter_failed_read_and_quarantine() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"a".to_string(), b"a").unwrap();
+        persister.insert_kv(&"b".to_string(), b"b").unwrap();
+        persister.insert_kv(&"c".to_string(), b"c").unwrap();
+        persister.header.db_file.set_len(1).unwrap();
+
+        let items: Vec<Result<(String, Vec<u8>), KVError>> = persister.iter().collect();
+        assert_eq!(Ok(("a".to_string(), vec![b'a'])), items[0]);
+        assert_eq!(Err(KVError::SlotBeyondEof { cursor: 1, len: 1, file_len: 1 }), items[1]);
+        assert_eq!(Err(KVError::SlotBeyondEof { cursor: 2, len: 1, file_len: 1 }), items[2]);
+
+        // quarantined keys are left out
+        persister.set_eof_policy(EofPolicy::Quarantine);
+        let _ = persister.get_value(&"b".to_string());
+        assert_eq!(2, persister.iter().count());
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let mut persister = new_mock_persister();
+        for key in ["b", "abc", "a", "ab", "abd", "ac", "aa"] {
+            persister.insert_kv(&key.to_string(), key.as_bytes()).unwrap();
+        }
+
+        let scan = |prefix: &str| -> Vec<String> {
+            persister.scan_prefix(&prefix.to_string())
+                .map(|item| {
+                    let (key, value) = item.unwrap();
+                    assert_eq!(key.as_bytes(), value.as_slice());
+                    key
+                })
+                .collect()
+        };
+
+        assert_eq!(vec!["a", "aa", "ab", "abc", "abd", "ac"], scan("a"));
+        assert_eq!(vec!["ab", "abc", "abd"], scan("ab"));
+        assert_eq!(vec!["abc"], scan("abc"));
+        assert_eq!(vec!["b"], scan("b"));
+        assert!(scan("abe").is_empty());
+        assert!(scan("c").is_empty());
+        assert_eq!(vec!["a", "aa", "ab", "abc", "abd", "ac", "b"], scan(""));
+    }
+
+    #[test]
+    fn test_scan_prefix_bytes_overflow() {
+        let mut persister: Persister<Vec<u8>> = Persister::with_header(FileHeader {
+            db_file: tempfile::tempfile().unwrap(),
+            index_file: tempfile::tempfile().unwrap(),
+            wal_file: None,
+            offset: 0,
+        });
+        for key in [vec![0x01, 0xff], vec![0x01, 0xff, 0x00], vec![0x01, 0xff, 0xff], vec![0x02], vec![0xff, 0xff, 0x01]] {
+            persister.insert_kv(&key, &[]).unwrap();
+        }
+
+        let scan = |prefix: Vec<u8>| -> Vec<Vec<u8>> {
+            persister.scan_prefix(&prefix).map(|item| item.unwrap().0).collect()
+        };
+
+        assert_eq!(vec![vec![0x01, 0xff], vec![0x01, 0xff, 0x00], vec![0x01, 0xff, 0xff]], scan(vec![0x01, 0xff]));
+        assert_eq!(vec![vec![0x01, 0xff, 0xff]], scan(vec![0x01, 0xff, 0xff]));
+        assert_eq!(vec![vec![0xff, 0xff, 0x01]], scan(vec![0xff, 0xff]));
+        assert_eq!(5, scan(vec![]).len());
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+        persister.insert_kv(&"key3".to_string(), b"f").unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put("key4".to_string(), vec![b'g', b'h'])
+            .put("key5".to_string(), vec![b'i', b'j', b'k'])
+            .put("key1".to_string(), vec![b'l'])
+            .delete("key3".to_string())
+            .put("empty".to_string(), vec![])
+            .put("dup".to_string(), vec![b'x'])
+            .put("dup".to_string(), vec![b'y', b'z']);
+        assert_eq!(7, batch.len());
+        persister.write_batch(batch).unwrap();
+
+        // operations are applied in key order, dup reuses the hole left by key2 and the rest
+        // is appended
+        assert_eq!(Slot {cursor: 3, space: 2}, persister.index.get("dup").unwrap().clone());
+        assert_eq!(vec![b'g', b'h'], persister.get_value(&"key4".to_string()).unwrap());
+        assert_eq!(vec![b'i', b'j', b'k'], persister.get_value(&"key5".to_string()).unwrap());
+        assert_eq!(vec![b'l'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'y', b'z'], persister.get_value(&"dup".to_string()).unwrap());
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key3".to_string()).unwrap_err());
+
+        // a put followed by a delete of the same key ends up deleted
+        let mut batch = WriteBatch::new();
+        batch.put("key6".to_string(), vec![b'm']).delete("key6".to_string());
+        assert_eq!(KVError::KeyDoesNotExist, persister.write_batch(batch).unwrap_err());
+        let mut batch = WriteBatch::new();
+        batch.put("key4".to_string(), vec![b'm']).delete("key4".to_string());
+        persister.write_batch(batch).unwrap();
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key4".to_string()).unwrap_err());
+    }
+
+    #[test]
+    fn test_write_batch_delete_missing_key() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put("key2".to_string(), vec![b'b']).delete("missing".to_string());
+        assert_eq!(KVError::KeyDoesNotExist, persister.write_batch(batch).unwrap_err());
+
+        assert_eq!(1, persister.index.len());
+        assert_eq!(1, persister.last_cursor);
+    }
+
+    #[test]
+    fn test_write_batch_rollback_on_write_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_mock_persister(dir.path());
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+        persister.insert_kv(&"key3".to_string(), b"f").unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
+
+        let freelist = persister.freelist.clone();
+        let index = persister.index.clone();
+        let live_slots = persister.live_slots.clone();
+
+        // swap the db file for a read-only handle so every value write fails
+        let writable = std::mem::replace(
+            &mut persister.header.db_file,
+            OpenOptions::new().read(true).open(dir.path().join("db")).unwrap(),
+        );
+
+        let new_batch = || {
+            let mut batch = WriteBatch::new();
+            batch.put("key4".to_string(), vec![b'g', b'h'])
+                .put("key5".to_string(), vec![b'i', b'j', b'k'])
+                .put("key1".to_string(), vec![b'l'])
+                .delete("key3".to_string());
+            batch
+        };
+        assert!(matches!(persister.write_batch(new_batch()), Err(KVError::IOError(_))));
+
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(index, persister.index);
+        assert_eq!(live_slots, persister.live_slots);
+        assert_eq!(6, persister.last_cursor);
+
+        // the same batch goes through once the file is writable again
+        persister.header.db_file = writable;
+        persister.write_batch(new_batch()).unwrap();
+        assert_eq!(vec![b'l'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'g', b'h'], persister.get_value(&"key4".to_string()).unwrap());
+        assert_eq!(vec![b'i', b'j', b'k'], persister.get_value(&"key5".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_write_batch_torn_index_append() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+        persister.insert_kv(&"key3".to_string(), b"f").unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
+
+        let freelist = persister.freelist.clone();
+        let index = persister.index.clone();
+        let live_slots = persister.live_slots.clone();
+        let index_len = persister.header.index_file.metadata().unwrap().len();
+
+        // the records of the first operations reach the index file, the rest don't
+        let mut batch = WriteBatch::new();
+        batch.put("key4".to_string(), b"gh".to_vec())
+            .put("key5".to_string(), b"ijk".to_vec())
+            .put("key1".to_string(), b"l".to_vec())
+            .delete("key3".to_string());
+        faults::tear_next_write(FileKind::Index, 40);
+        assert!(matches!(persister.write_batch(batch), Err(KVError::IOError(_))));
+
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(index, persister.index);
+        assert_eq!(live_slots, persister.live_slots);
+        assert_eq!(6, persister.last_cursor);
+        assert_eq!(index_len, persister.header.index_file.metadata().unwrap().len());
+        assert_eq!(b"abc".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(b"f".to_vec(), persister.get_value(&"key3".to_string()).unwrap());
+
+        persister.load_index().unwrap();
+        assert_eq!(index, persister.index);
+    }
+
+    #[test]
+    fn test_write_batch_compared_to_insert_kv() {
+        let entries: Vec<(String, Vec<u8>)> = (0..1_000)
+            .map(|i: u32| (format!("key_{:05}", i), i.to_le_bytes().to_vec()))
+            .collect();
+
+        let mut individual = new_mock_persister();
+        for (key, value) in entries.iter() {
+            individual.insert_kv(key, value).unwrap();
+        }
+
+        let mut batched = new_mock_persister();
+        let mut batch = WriteBatch::new();
+        for (key, value) in entries.iter() {
+            batch.put(key.clone(), value.clone());
+        }
+        batched.write_batch(batch).unwrap();
+
+        assert_eq!(individual.last_cursor, batched.last_cursor);
+        assert_eq!(individual.index, batched.index);
+        for (key, value) in entries.iter().step_by(97) {
+            assert_eq!(value.clone(), batched.get_value(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_recent_errors() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        assert!(persister.recent_errors().is_empty());
+
+        let _ = persister.insert_kv(&"key1".to_string(), b"b");
+        let _ = persister.get_value(&"missing".to_string());
+        let _ = persister.update_value(&"missing".to_string(), b"c");
+        let _ = persister.delete_kv(&"missing".to_string());
+
+        let records = persister.recent_errors();
+        assert_eq!(
+            vec!["insert_kv", "get_value", "update_value", "delete_kv"],
+            records.iter().map(|record| record.operation).collect::<Vec<&str>>()
+        );
+        assert_eq!(
+            vec!["KeyAlreadyExist", "KeyDoesNotExist", "KeyDoesNotExist", "KeyDoesNotExist"],
+            records.iter().map(|record| record.error.as_str()).collect::<Vec<&str>>()
+        );
+        assert!(records.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+        persister.clear_recent_errors();
+        assert!(persister.recent_errors().is_empty());
+    }
+
+    #[test]
+    fn test_recent_errors_capacity() {
+        let mut persister = new_mock_persister();
+        persister.set_recent_errors_capacity(2);
+
+        let mut batch = WriteBatch::new();
+        batch.delete("missing".to_string());
+        let _ = persister.write_batch(batch);
+        let _ = persister.get_value(&"missing".to_string());
+        let _ = persister.delete_kv(&"missing".to_string());
+
+        // the oldest error was dropped to make room for the newest one
+        assert_eq!(
+            vec!["get_value", "delete_kv"],
+            persister.recent_errors().iter().map(|record| record.operation).collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn test_sync_mode_always() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.set_sync_mode(SyncMode::Always);
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        assert_eq!(0, persister.unsynced_ops);
+
+        // the value and its index record are visible from handles opened on the side
+        let offset = FORMAT_HEADER_LEN as usize;
+        assert_eq!(b"abc".to_vec(), std::fs::read(&datastore).unwrap()[offset..]);
+        let index = std::fs::read(dir.path().join("index_store")).unwrap()[offset..].to_vec();
+        let mut records = index.as_slice();
+        assert!(matches!(IndexRecord::decode(&mut records), Ok(Some(IndexRecord::Config { .. }))));
+        assert_eq!(Some(IndexRecord::Put {
+            key: encode_key(&"key1".to_string()).unwrap(),
+            slot: Slot {cursor: 0, space: 3},
+            expires_at: None,
+        }), IndexRecord::decode(&mut records).unwrap());
+    }
+
+    #[test]
+    fn test_sync_mode_every_n_ops() {
+        let mut persister = new_mock_persister();
+        persister.set_sync_mode(SyncMode::EveryNOps(3));
+
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.put(&"key1".to_string(), b"b").unwrap();
+        assert_eq!(2, persister.unsynced_ops);
+
+        // failed writes are not counted
+        let _ = persister.delete_kv(&"missing".to_string());
+        assert_eq!(2, persister.unsynced_ops);
+
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        assert_eq!(0, persister.unsynced_ops);
+
+        persister.set_sync_mode(SyncMode::Never);
+        persister.insert_kv(&"key2".to_string(), b"c").unwrap();
+        persister.insert_kv(&"key3".to_string(), b"d").unwrap();
+        persister.insert_kv(&"key4".to_string(), b"e").unwrap();
+        assert_eq!(3, persister.unsynced_ops);
+        assert_eq!(Ok(()), persister.flush());
+        assert_eq!(0, persister.unsynced_ops);
+    }
+
+    #[test]
+    fn test_sync_mode_never_leaves_syncs_to_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+
+        // the writes are logged, but neither the log nor the other files are synced
+        let syncs = faults::syncs();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.update_value(&"key1".to_string(), b"abcdef").unwrap();
+        persister.put(&"key2".to_string(), b"gh").unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("key3".to_string(), b"ij".to_vec());
+        batch.delete("key2".to_string());
+        persister.write_batch(batch).unwrap();
+        persister.bulk_load(vec![("key4".to_string(), b"kl".to_vec())]).unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        assert_eq!(syncs, faults::syncs());
+        assert!(persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len() > 0);
+
+        persister.flush().unwrap();
+        assert!(faults::syncs() > syncs);
+        assert_eq!(0, persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len());
+
+        // the log is synced ahead of a synced write
+        persister.set_sync_mode(SyncMode::Always);
+        let syncs = faults::syncs();
+        persister.insert_kv(&"key5".to_string(), b"mn").unwrap();
+        assert_eq!(syncs + 4, faults::syncs());
+    }
+
+    #[test]
+    fn test_sync_failure_poisons_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.set_sync_mode(SyncMode::Always);
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+
+        // the write whose log couldn't be synced fails with the error of the sync, the writes
+        // after it wait for the failure to be acknowledged
+        faults::fail_next_syncs(1);
+        assert!(matches!(persister.insert_kv(&"key2".to_string(), b"b"), Err(KVError::IOError(_))));
+        assert_eq!(Err(KVError::SyncFailed), persister.insert_kv(&"key3".to_string(), b"c"));
+        assert_eq!(Err(KVError::SyncFailed), persister.delete_kv(&"key1".to_string()));
+        assert_eq!(Err(KVError::SyncFailed), persister.flush());
+        assert_eq!(vec![b'a'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value(&"key2".to_string()));
+        assert_eq!(0, persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len());
+
+        assert_eq!(Ok(0), persister.acknowledge_sync_failure());
+        assert_eq!(Ok(0), persister.acknowledge_sync_failure());
+        persister.insert_kv(&"key3".to_string(), b"c").unwrap();
+        drop(persister);
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![&"key1".to_string(), &"key3".to_string()], persister.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_acknowledge_sync_failure_rewrites_lost_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.insert_kv(&"key0".to_string(), b"synced").unwrap();
+        persister.flush().unwrap();
+
+        persister.insert_kv(&"key1".to_string(), b"abcd").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"ef").unwrap();
+        persister.update_value(&"key1".to_string(), b"ghijkl").unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        faults::fail_next_syncs(1);
+        assert!(matches!(persister.flush(), Err(KVError::IOError(_))));
+
+        // the pages of the last value and of the last index record were lost with the sync
+        let slot = persister.index[&"key1".to_string()].clone();
+        persister.header.write_data_at(&[0; 6], slot.cursor).unwrap();
+        let index_len = persister.header.index_file.metadata().unwrap().len();
+        persister.header.index_file.set_len(index_len - 1).unwrap();
+        assert_eq!(Err(KVError::SyncFailed), persister.put(&"key3".to_string(), b"m"));
+
+        // the value read back wrong and key2 is still in the index file
+        assert_eq!(Ok(2), persister.acknowledge_sync_failure());
+        assert_eq!(b"ghijkl".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(0, persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len());
+        persister.put(&"key3".to_string(), b"m").unwrap();
+        drop(persister);
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(
+            vec![&"key0".to_string(), &"key1".to_string(), &"key3".to_string()],
+            persister.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(b"synced".to_vec(), persister.get_value(&"key0".to_string()).unwrap());
+        assert_eq!(b"ghijkl".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_acknowledge_sync_failure_that_fails_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        faults::fail_next_syncs(2);
+        assert!(matches!(persister.flush(), Err(KVError::IOError(_))));
+        assert!(matches!(persister.acknowledge_sync_failure(), Err(KVError::IOError(_))));
+        assert_eq!(Err(KVError::SyncFailed), persister.insert_kv(&"key2".to_string(), b"d"));
+
+        // dropped poisoned, the log is left for the next open to replay
+        let db_len = persister.header.data_len().unwrap();
+        persister.header.write_data_at(&[0; 3], 0).unwrap();
+        drop(persister);
+        let mut persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(db_len, persister.header.data_len().unwrap());
+        assert_eq!(b"abc".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+        persister.insert_kv(&"key2".to_string(), b"d").unwrap();
+    }
+
+    // the tenant is not serialized, so keys that only differ by it collide
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+    struct BrokenKey {
+        id: u32,
+        #[serde(skip)]
+        tenant: u32,
+    }
+
+    fn new_broken_key_persister() -> Persister<BrokenKey> {
+        Persister::with_header(FileHeader {
+            db_file: tempfile::tempfile().unwrap(),
+            index_file: tempfile::tempfile().unwrap(),
+            wal_file: None,
+            offset: 0,
+        })
+    }
+
+    #[test]
+    fn test_key_encoding_collision() {
+        let mut persister = new_broken_key_persister();
+        persister.insert_kv(&BrokenKey { id: 1, tenant: 1 }, b"a").unwrap();
+        persister.update_value(&BrokenKey { id: 1, tenant: 1 }, b"b").unwrap();
+
+        assert_eq!(
+            Err(KVError::KeyEncodingCollision {
+                first: "BrokenKey { id: 1, tenant: 1 }".to_string(),
+                second: "BrokenKey { id: 1, tenant: 2 }".to_string(),
+            }),
+            persister.insert_kv(&BrokenKey { id: 1, tenant: 2 }, b"c")
+        );
+        assert!(!persister.index.contains_key(&BrokenKey { id: 1, tenant: 2 }));
+
+        // collisions inside a batch fail the whole batch
+        let mut batch = WriteBatch::new();
+        batch.put(BrokenKey { id: 2, tenant: 1 }, vec![b'd']).put(BrokenKey { id: 2, tenant: 2 }, vec![b'e']);
+        assert!(matches!(persister.write_batch(batch), Err(KVError::KeyEncodingCollision { .. })));
+        assert_eq!(1, persister.index.len());
+        assert_eq!(vec![b'b'], persister.get_value(&BrokenKey { id: 1, tenant: 1 }).unwrap());
+    }
+
+    #[test]
+    fn test_key_encoding_collision_paranoid() {
+        let mut persister = new_broken_key_persister();
+        for id in 0..KEY_COLLISION_CHECK_INSERTS as u32 {
+            persister.insert_kv(&BrokenKey { id, tenant: 0 }, &[]).unwrap();
+        }
+
+        // past the first inserts the check is off unless the paranoid flag is set
+        let id = KEY_COLLISION_CHECK_INSERTS as u32;
+        persister.insert_kv(&BrokenKey { id, tenant: 0 }, &[]).unwrap();
+        persister.insert_kv(&BrokenKey { id, tenant: 1 }, &[]).unwrap();
+
+        persister.set_paranoid_key_checks(true);
+        persister.insert_kv(&BrokenKey { id: id + 1, tenant: 0 }, &[]).unwrap();
+        assert!(matches!(
+            persister.insert_kv(&BrokenKey { id: id + 1, tenant: 1 }, &[]),
+            Err(KVError::KeyEncodingCollision { .. })
+        ));
+    }
+
+    #[test]
+    fn test_get_value_interleaved_with_writes() {
+        let mut persister = new_mock_persister();
+
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key2".to_string()).unwrap());
+
+        // reads leave the position of the db file wherever they like, writes must not follow it
+        persister.update_value(&"key1".to_string(), b"fghi").unwrap();
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key2".to_string()).unwrap());
+        persister.insert_kv(&"key3".to_string(), b"j").unwrap();
+        assert_eq!(vec![b'f', b'g', b'h', b'i'], persister.get_value(&"key1".to_string()).unwrap());
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        assert_eq!(vec![b'j'], persister.get_value(&"key3".to_string()).unwrap());
+        persister.insert_kv(&"key4".to_string(), b"kl").unwrap();
+
+        assert_eq!(vec![b'f', b'g', b'h', b'i'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'j'], persister.get_value(&"key3".to_string()).unwrap());
+        assert_eq!(vec![b'k', b'l'], persister.get_value(&"key4".to_string()).unwrap());
+        assert_eq!(Slot {cursor: 0, space: 1}, persister.index.get("key3").unwrap().clone());
+        assert_eq!(Slot {cursor: 5, space: 4}, persister.index.get("key1").unwrap().clone());
+
+        // nothing got written past the last slot
+        let mut content = vec![];
+        persister.header.db_file.seek(SeekFrom::Start(0)).unwrap();
+        persister.header.db_file.read_to_end(&mut content).unwrap();
+        assert_eq!(9, content.len());
+        assert_eq!(b"fghi", &content[5..]);
+    }
+
+    #[test]
+    fn test_get_value_through_shared_reference() {
+        let mut persister = new_mock_persister();
+        for (key, value) in [("a", b"1"), ("b", b"2"), ("c", b"3")] {
+            persister.insert_kv(&key.to_string(), value).unwrap();
+        }
+
+        // point lookups can be served while iterating, both only borrow the store
+        let reader = &persister;
+        for item in reader.iter() {
+            let (key, value) = item.unwrap();
+            assert_eq!(value, reader.get_value(&key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_reads_through_shared_references() {
+        let mut persister = new_mock_persister();
+        for i in 0..100 {
+            persister.insert_kv(&format!("key_{}", i), &format!("value_{}", i).into_bytes()).unwrap();
+        }
+        let _ = persister.get_value(&"missing".to_string());
+        let digest = persister.content_digest().unwrap();
+
+        // every reader only borrows the store, so plain references can be handed to threads
+        let persister = &persister;
+        std::thread::scope(|scope| {
+            for reader in 0..4 {
+                scope.spawn(move || {
+                    for round in 0..500 {
+                        let i = (round * 7 + reader) % 100;
+                        let key = format!("key_{}", i);
+                        let value = format!("value_{}", i).into_bytes();
+
+                        assert_eq!(value, persister.get_value(&key).unwrap());
+                        assert!(persister.contains_key(&key));
+                        assert_eq!(value.len(), persister.value_len(&key).unwrap());
+                        let mut buffer = [0; 16];
+                        assert_eq!(Ok(value.len()), persister.read_value_into(&key, &mut buffer));
+                        assert_eq!(value, buffer[..value.len()]);
+                    }
+                    assert_eq!(100, persister.keys().count());
+                    assert_eq!(100, persister.iter().filter(|item| item.is_ok()).count());
+                    assert_eq!(digest, persister.content_digest().unwrap());
+                    let _ = persister.live_digest().unwrap();
+                    assert!(persister.quarantined_keys().is_empty());
+                    assert_eq!(1, persister.recent_errors().len());
+                });
+            }
+        });
+
+        assert_eq!(persister.scan_live_digest().unwrap(), persister.live_digest().unwrap());
+    }
+
+    #[test]
+    fn test_read_value_into() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+        persister.insert_kv(&"empty".to_string(), &[]).unwrap();
+
+        assert_eq!(Ok(3), persister.value_len(&"key1".to_string()));
+        assert_eq!(Ok(0), persister.value_len(&"empty".to_string()));
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.value_len(&"missing".to_string()));
+
+        // exact size
+        let mut buffer = [0; 3];
+        assert_eq!(Ok(3), persister.read_value_into(&"key1".to_string(), &mut buffer));
+        assert_eq!([b'a', b'b', b'c'], buffer);
+
+        // oversized buffers only get the start written
+        let mut buffer = [b'x'; 5];
+        assert_eq!(Ok(2), persister.read_value_into(&"key2".to_string(), &mut buffer));
+        assert_eq!([b'd', b'e', b'x', b'x', b'x'], buffer);
+
+        // too small buffers are left untouched
+        let mut buffer = [b'x'; 2];
+        assert_eq!(
+            Err(KVError::BufferTooSmall { needed: 3 }),
+            persister.read_value_into(&"key1".to_string(), &mut buffer)
+        );
+        assert_eq!([b'x', b'x'], buffer);
+
+        assert_eq!(Ok(0), persister.read_value_into(&"empty".to_string(), &mut []));
+        assert_eq!(
+            Err(KVError::KeyDoesNotExist),
+            persister.read_value_into(&"missing".to_string(), &mut buffer)
+        );
+    }
+
+    #[test]
+    fn test_read_value_into_reused_buffer() {
+        let mut persister = new_mock_persister();
+        for i in 0..100 {
+            persister.insert_kv(&format!("key_{:03}", i), &vec![i as u8; i % 16]).unwrap();
+        }
+
+        // a single buffer serves every read
+        let mut buffer = [0; 16];
+        for _ in 0..10 {
+            for i in 0..100 {
+                let key = format!("key_{:03}", i);
+                let len = persister.read_value_into(&key, &mut buffer).unwrap();
+                assert_eq!(i % 16, len);
+                assert!(buffer[..len].iter().all(|byte| *byte == i as u8));
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_value_into_quarantine() {
+        let mut persister = new_mock_persister();
+        persister.set_eof_policy(EofPolicy::Quarantine);
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.header.db_file.set_len(1).unwrap();
+
+        let mut buffer = [0; 3];
+        assert_eq!(
+            Err(KVError::SlotBeyondEof { cursor: 0, len: 3, file_len: 1 }),
+            persister.read_value_into(&"key1".to_string(), &mut buffer)
+        );
+        assert_eq!(Err(KVError::KeyQuarantined), persister.read_value_into(&"key1".to_string(), &mut buffer));
+    }
+
+    // hands out at most 1000 bytes per read, like a socket would
+    struct TrickleReader<'a>(&'a [u8]);
+
+    impl Read for TrickleReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(1000);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_stream_large_value() {
+        let mut persister = new_mock_persister();
+        let value: Vec<u8> = (0..4 * 1024 * 1024).map(|i: usize| (i * 31 % 251) as u8).collect();
+        assert!(value.len() > STREAM_CHUNK_SIZE);
+
+        persister.insert_kv(&"small".to_string(), b"ab").unwrap();
+        persister.insert_from_reader(&"big".to_string(), TrickleReader(&value), value.len()).unwrap();
+        assert_eq!(Slot {cursor: 2, space: value.len()}, persister.index.get("big").unwrap().clone());
+        assert_eq!(2 + value.len(), persister.last_cursor);
+
+        let mut streamed = vec![];
+        assert_eq!(Ok(value.len()), persister.read_to_writer(&"big".to_string(), &mut streamed));
+        assert!(streamed == value);
+        assert!(persister.get_value(&"big".to_string()).unwrap() == value);
+
+        let mut streamed = vec![];
+        assert_eq!(Ok(2), persister.read_to_writer(&"small".to_string(), &mut streamed));
+        assert_eq!(vec![b'a', b'b'], streamed);
+
+        // empty values and missing keys
+        persister.insert_from_reader(&"empty".to_string(), std::io::empty(), 0).unwrap();
+        assert_eq!(Ok(0), persister.read_to_writer(&"empty".to_string(), &mut streamed));
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.read_to_writer(&"missing".to_string(), &mut streamed));
+        assert_eq!(
+            Err(KVError::KeyAlreadyExist),
+            persister.insert_from_reader(&"big".to_string(), &b"x"[..], 1)
+        );
+    }
+
+    #[test]
+    fn test_stream_length_mismatch_releases_space() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 10]).unwrap();
+        persister.insert_kv(&"key2".to_string(), b"b").unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let freelist = persister.freelist.clone();
+
+        // short reader taking space from the free list
+        assert_eq!(
+            Err(KVError::StreamLengthMismatch { expected: 8, read: 6 }),
+            persister.insert_from_reader(&"key3".to_string(), &[b'c'; 6][..], 8)
+        );
+        assert_eq!(freelist, persister.freelist);
+        assert!(!persister.contains_key(&"key3".to_string()));
+
+        // long reader taking space at the end of the file
+        assert_eq!(
+            Err(KVError::StreamLengthMismatch { expected: 12, read: 13 }),
+            persister.insert_from_reader(&"key3".to_string(), &[b'c'; 20][..], 12)
+        );
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(11, persister.last_cursor);
+        assert!(!persister.contains_key(&"key3".to_string()));
+
+        // the released space is reused by the next insert
+        persister.insert_from_reader(&"key3".to_string(), &[b'c'; 8][..], 8).unwrap();
+        assert_eq!(Slot {cursor: 0, space: 8}, persister.index.get("key3").unwrap().clone());
+        assert_eq!(vec![b'c'; 8], persister.get_value(&"key3".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_introspection() {
+        let mut persister = new_mock_persister();
+        assert!(persister.is_empty());
+        assert_eq!(0, persister.len());
+        assert!(!persister.contains_key(&"key1".to_string()));
+
+        persister.insert_kv(&"key2".to_string(), b"a").unwrap();
+        persister.insert_kv(&"key1".to_string(), b"bc").unwrap();
+        persister.insert_kv(&"empty".to_string(), &[]).unwrap();
+        persister.insert_kv(&"key3".to_string(), b"d").unwrap();
+        assert_eq!(4, persister.len());
+        assert!(!persister.is_empty());
+        assert!(persister.contains_key(&"empty".to_string()));
+        assert_eq!(vec!["empty", "key1", "key2", "key3"], persister.keys().collect::<Vec<&String>>());
+
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        persister.update_value(&"key1".to_string(), b"efg").unwrap();
+        assert_eq!(3, persister.len());
+        assert!(!persister.contains_key(&"key2".to_string()));
+        assert!(persister.contains_key(&"key1".to_string()));
+        assert!(persister.contains_key(&"key3".to_string()));
+        assert_eq!(vec!["empty", "key1", "key3"], persister.keys().collect::<Vec<&String>>());
+
+        // nothing is read from the db file
+        persister.header.db_file.set_len(0).unwrap();
+        assert!(persister.contains_key(&"key1".to_string()));
+        assert_eq!(3, persister.keys().count());
+
+        for key in ["empty", "key1", "key3"] {
+            persister.delete_kv(&key.to_string()).unwrap();
+        }
+        assert!(persister.is_empty());
+    }
+
+    #[test]
+    fn test_allocation_strategy() {
+        let fragmented = |strategy: AllocationStrategy| -> Persister<String> {
+            let mut persister = new_mock_persister();
+            persister.set_allocation_strategy(strategy);
+
+            // holes of 4 at 0, 8 at 5, 6 at 14 and 12 at 21, kept apart by one byte values
+            for (key, len) in [("a", 4), ("s1", 1), ("b", 8), ("s2", 1), ("c", 6), ("s3", 1), ("d", 12), ("s4", 1)] {
+                persister.insert_kv(&key.to_string(), &vec![b'x'; len]).unwrap();
+            }
+            for key in ["a", "b", "c", "d"] {
+                persister.delete_kv(&key.to_string()).unwrap();
+            }
+            persister
+        };
+
+        for (strategy, cursor) in [
+            (AllocationStrategy::BestFit, 14),
+            (AllocationStrategy::FirstFit, 5),
+            (AllocationStrategy::WorstFit, 21),
+        ] {
+            let mut persister = fragmented(strategy);
+            persister.insert_kv(&"new".to_string(), &[b'n'; 5]).unwrap();
+            assert_eq!(Slot {cursor, space: 5}, persister.index.get("new").unwrap().clone());
+            assert_eq!(34, persister.last_cursor);
+        }
+    }
+
+    #[test]
+    fn test_put() {
+        let mut persister = new_mock_persister();
+
+        // put on a missing key inserts it
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&"key1".to_string(), b"ab"));
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&"key2".to_string(), b"cdef"));
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&"key3".to_string(), b"gh"));
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+
+        // the new value is written next to the previous one, which is freed afterwards
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key1".to_string(), b"ij"));
+        assert_eq!(Slot {cursor: 8, space: 2}, persister.index.get("key1").unwrap().clone());
+        assert_eq!(vec![b'i', b'j'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(10, persister.last_cursor);
+
+        // a larger value reuses the hole left by key1 and key2, the end of the data goes back to
+        // key3
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key1".to_string(), b"klm"));
+        assert_eq!(Slot {cursor: 0, space: 3}, persister.index.get("key1").unwrap().clone());
+        assert_eq!(vec![b'k', b'l', b'm'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(8, persister.last_cursor);
+
+        // a smaller value at the end of the data moves into the hole and gives its slot and
+        // what is left of the hole back to the end
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key3".to_string(), b"n"));
+        assert_eq!(Slot {cursor: 3, space: 1}, persister.index.get("key3").unwrap().clone());
+        assert_eq!(4, persister.last_cursor);
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_eq!(vec![b'n'], persister.get_value(&"key3".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_put_empty_values() {
+        let mut persister = new_mock_persister();
+
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&"key1".to_string(), &[]));
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key1".to_string(), &[]));
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(0, persister.last_cursor);
+
+        // grow from and shrink to an empty value
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key1".to_string(), b"ab"));
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(2, persister.last_cursor);
+        assert_eq!(Ok(PutOutcome::Updated), persister.put(&"key1".to_string(), &[]));
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"key1".to_string()).unwrap());
+
+        // the released space can be reused by other keys
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&"key2".to_string(), b"cd"));
+        assert_eq!(Slot {cursor: 0, space: 2}, persister.index.get("key2").unwrap().clone());
+    }
+
+    #[test]
+    fn test_content_digest_ignores_layout() {
+        let mut a = new_mock_persister();
+        let mut b = new_mock_persister();
+        a.insert_kv(&"a".to_string(), &[1, 2, 3]).unwrap();
+        a.insert_kv(&"b".to_string(), &[]).unwrap();
+        a.insert_kv(&"c".to_string(), &[4]).unwrap();
+
+        // same entries reached through other writes, at other offsets
+        b.insert_kv(&"tmp".to_string(), &[9; 10]).unwrap();
+        b.insert_kv(&"c".to_string(), &[4, 4]).unwrap();
+        b.insert_kv(&"b".to_string(), &[5]).unwrap();
+        b.delete_kv(&"tmp".to_string()).unwrap();
+        b.insert_kv(&"a".to_string(), &[1, 2, 3]).unwrap();
+        b.update_value(&"c".to_string(), &[4]).unwrap();
+        b.update_value(&"b".to_string(), &[]).unwrap();
+
+        assert_eq!(a.content_digest().unwrap(), b.content_digest().unwrap());
+        assert_eq!(a.live_digest().unwrap(), b.live_digest().unwrap());
+        assert_ne!(new_mock_persister().content_digest().unwrap(), a.content_digest().unwrap());
+    }
+
+    #[test]
+    fn test_digests_detect_single_entry_difference() {
+        let mut base = new_mock_persister();
+        base.insert_kv(&"a".to_string(), &[1, 2]).unwrap();
+        base.insert_kv(&"b".to_string(), &[3]).unwrap();
+        let content = base.content_digest().unwrap();
+        let live = base.live_digest().unwrap();
+
+        type Variant<'a> = &'a dyn Fn(&mut Persister<String>);
+        let variants: [Variant; 5] = [
+            &|p| { p.update_value(&"a".to_string(), &[1, 3]).unwrap(); },
+            &|p| { p.update_value(&"b".to_string(), &[3, 0]).unwrap(); },
+            &|p| { p.delete_kv(&"b".to_string()).unwrap(); },
+            &|p| { p.insert_kv(&"c".to_string(), &[]).unwrap(); },
+            // moving a byte from one value to the other keeps the total contents
+            &|p| {
+                p.update_value(&"a".to_string(), &[1]).unwrap();
+                p.update_value(&"b".to_string(), &[2, 3]).unwrap();
+            },
+        ];
+        for variant in variants.iter() {
+            let mut persister = new_mock_persister();
+            persister.insert_kv(&"a".to_string(), &[1, 2]).unwrap();
+            persister.insert_kv(&"b".to_string(), &[3]).unwrap();
+            let _ = persister.live_digest().unwrap();
+            variant(&mut persister);
+
+            assert_ne!(content, persister.content_digest().unwrap());
+            assert_ne!(live, persister.live_digest().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_live_digest_follows_random_writes() {
+        // small xorshift generator so the sequence is reproducible without extra dependencies
+        let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut next = move |bound: usize| -> usize {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+
+        let mut persister = new_mock_persister();
+        let _ = persister.live_digest().unwrap();
+        for round in 0..500 {
+            let key = format!("key_{}", next(30));
+            let value = vec![round as u8; next(20)];
+            match next(6) {
+                0 => { let _ = persister.insert_kv(&key, &value); },
+                1 => { let _ = persister.update_value(&key, &value); },
+                2 => { let _ = persister.delete_kv(&key); },
+                3 => { persister.put(&key, &value).unwrap(); },
+                4 => { let _ = persister.insert_from_reader(&key, TrickleReader(&value), value.len()); },
+                _ => {
+                    let mut batch = WriteBatch::new();
+                    batch.put(key, value);
+                    batch.delete(format!("key_{}", next(30)));
+                    let _ = persister.write_batch(batch);
+                },
+            }
+
+            assert_eq!(persister.scan_live_digest().unwrap(), persister.live_digest().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_live_digest_stops_on_failed_read() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"a".to_string(), b"a").unwrap();
+        persister.insert_kv(&"b".to_string(), b"b").unwrap();
+        let _ = persister.live_digest().unwrap();
+
+        // the previous value of b can't be hashed, so tracking is dropped
+        persister.header.db_file.set_len(1).unwrap();
+        persister.update_value(&"b".to_string(), b"c").unwrap();
+        assert_eq!(None, *persister.tracked_digest());
+
+        // and started again from a scan on the next call
+        assert_eq!(persister.scan_live_digest().unwrap(), persister.live_digest().unwrap());
+    }
+
+    #[cfg(feature = "compression")]
+    fn incompressible(len: usize) -> Vec<u8> {
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..len).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u8
+        }).collect()
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_round_trip() {
+        for compression in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+            let mut persister = new_mock_persister();
+            persister.compression = compression;
+            let text = b"the quick brown fox jumps over the lazy dog ".repeat(50);
+            let noise = incompressible(300);
+
+            persister.insert_kv(&"text".to_string(), &text).unwrap();
+            persister.insert_kv(&"noise".to_string(), &noise).unwrap();
+            persister.insert_kv(&"empty".to_string(), &[]).unwrap();
+
+            // slots hold the stored bytes, callers only see the values
+            let text_slot = persister.index.get("text").unwrap().clone();
+            assert!(text_slot.space < text.len() / 4);
+            assert_eq!(noise.len() + 1, persister.index.get("noise").unwrap().space);
+            assert_eq!(0, persister.index.get("empty").unwrap().space);
+            assert_eq!(text_slot.space + noise.len() + 1, persister.last_cursor);
+
+            assert_eq!(text, persister.get_value(&"text".to_string()).unwrap());
+            assert_eq!(noise, persister.get_value(&"noise".to_string()).unwrap());
+            assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
+            assert_eq!(text.len(), persister.value_len(&"text".to_string()).unwrap());
+            assert_eq!(noise.len(), persister.value_len(&"noise".to_string()).unwrap());
+
+            let mut buffer = vec![0; text.len()];
+            assert_eq!(Err(KVError::BufferTooSmall { needed: text.len() }), persister.read_value_into(&"text".to_string(), &mut buffer[..10]));
+            assert_eq!(Ok(text.len()), persister.read_value_into(&"text".to_string(), &mut buffer));
+            assert_eq!(text, buffer);
+            let mut written = vec![];
+            assert_eq!(Ok(noise.len()), persister.read_to_writer(&"noise".to_string(), &mut written));
+            assert_eq!(noise, written);
+
+            let items: Vec<(String, Vec<u8>)> = persister.iter().map(|item| item.unwrap()).collect();
+            assert_eq!(vec![("empty".to_string(), vec![]), ("noise".to_string(), noise.clone()), ("text".to_string(), text.clone())], items);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_update_resizes_slots() {
+        let mut persister = new_mock_persister();
+        persister.compression = Compression::Lz4;
+        let key = "key".to_string();
+
+        // from raw to compressed, the value grows while its slot shrinks
+        persister.insert_kv(&key, &incompressible(100)).unwrap();
+        persister.insert_kv(&"after".to_string(), &[1]).unwrap();
+        let value = vec![b'x'; 1000];
+        persister.update_value(&key, &value).unwrap();
+        let slot = persister.index.get("key").unwrap().clone();
+        assert_eq!(103, slot.cursor);
+        assert!(slot.space < 100);
+        assert_eq!(101, persister.freelist.total_free_space());
+        assert_eq!(value, persister.get_value(&key).unwrap());
+
+        // from compressed to raw, the slot grows past the free 0..101
+        let value = incompressible(200);
+        persister.update_value(&key, &value).unwrap();
+        assert_eq!(Slot { cursor: 103 + slot.space, space: 201 }, persister.index.get("key").unwrap().clone());
+        assert_eq!(value, persister.get_value(&key).unwrap());
+        assert_eq!(vec![1], persister.get_value(&"after".to_string()).unwrap());
+
+        // batches and streamed values go through the same framing
+        let mut batch = WriteBatch::new();
+        batch.put(key.clone(), vec![b'y'; 500]);
+        batch.put("batch".to_string(), incompressible(50));
+        persister.write_batch(batch).unwrap();
+        assert_eq!(vec![b'y'; 500], persister.get_value(&key).unwrap());
+        assert_eq!(incompressible(50), persister.get_value(&"batch".to_string()).unwrap());
+
+        let streamed = vec![b'z'; 300];
+        persister.insert_from_reader(&"streamed".to_string(), TrickleReader(&streamed), streamed.len()).unwrap();
+        assert_eq!(301, persister.index.get("streamed").unwrap().space);
+        assert_eq!(streamed, persister.get_value(&"streamed".to_string()).unwrap());
+        assert_eq!(streamed.len(), persister.value_len(&"streamed".to_string()).unwrap());
+
+        // the frozen view hands out stored bytes
+        #[cfg(feature = "mmap")]
+        assert!(matches!(persister.freeze(), Err(KVError::CompressionError(_))));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let text = b"abcabcabd".repeat(200);
+
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new()
+                .path(&datastore)
+                .compression(Compression::Zstd { level: 0 })
+                .open()
+                .unwrap();
+            persister.insert_kv(&"text".to_string(), &text).unwrap();
+            persister.insert_kv(&"noise".to_string(), &incompressible(64)).unwrap();
+        }
+
+        // the algorithm can change between opens, each value tells how it was stored
+        let mut persister: Persister<String> = PersisterBuilder::new()
+            .path(&datastore)
+            .compression(Compression::Lz4)
+            .open()
+            .unwrap();
+        assert_eq!(text, persister.get_value(&"text".to_string()).unwrap());
+        assert_eq!(incompressible(64), persister.get_value(&"noise".to_string()).unwrap());
+
+        persister.put(&"text".to_string(), &b"xyz".repeat(300)).unwrap();
+        assert_eq!(b"xyz".repeat(300), persister.get_value(&"text".to_string()).unwrap());
+
+        // and after opening, as long as the values stay compressed
+        assert_eq!(Ok(()), persister.set_compression(Compression::Zstd { level: 1 }));
+        assert!(matches!(persister.set_compression(Compression::None), Err(KVError::InvalidArgument(_))));
+        assert_eq!(Compression::Zstd { level: 1 }, persister.compression);
+        persister.put(&"noise".to_string(), &b"xyz".repeat(300)).unwrap();
+        assert_eq!(b"xyz".repeat(300), persister.get_value(&"noise".to_string()).unwrap());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression_mismatched_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let compressed = dir.path().join("compressed").to_string_lossy().to_string();
+        let plain = dir.path().join("plain").to_string_lossy().to_string();
+        let open = |path: &str, compression| PersisterBuilder::new().path(path).compression(compression).open::<String>();
+
+        let mut persister = open(&compressed, Compression::Lz4).unwrap();
+        persister.insert_kv(&"key".to_string(), &[b'a'; 100]).unwrap();
+        drop(persister);
+        let mut persister = open(&plain, Compression::None).unwrap();
+        persister.insert_kv(&"key".to_string(), &[0, 1, 2]).unwrap();
+        drop(persister);
+
+        // the framed bytes aren't handed out as the value, nor is the first byte of a plain
+        // value taken for a frame tag
+        assert_eq!(
+            Err(KVError::InvalidFormat("db file was created with compression lz4, opened with none".to_string())),
+            Persister::<String>::open_existing(compressed.clone(), 0).map(|_| ())
+        );
+        assert_eq!(
+            Err(KVError::InvalidFormat("db file was created with compression none, opened with zstd".to_string())),
+            open(&plain, Compression::Zstd { level: 0 }).map(|_| ())
+        );
+        assert!(matches!(
+            PersisterBuilder::new().path(&compressed).read_only(true).open::<String>(),
+            Err(KVError::InvalidFormat(_))
+        ));
+
+        assert_eq!(vec![b'a'; 100], open(&compressed, Compression::Lz4).unwrap().get_value(&"key".to_string()).unwrap());
+        assert_eq!(vec![0, 1, 2], open(&plain, Compression::None).unwrap().get_value(&"key".to_string()).unwrap());
+
+        // snapshots keep the compression of their store
+        let persister = open(&compressed, Compression::Lz4).unwrap();
+        let snapshot = dir.path().join("snapshot");
+        persister.snapshot_to(&snapshot).unwrap();
+        assert!(matches!(Persister::<String>::open_snapshot(&snapshot), Err(KVError::InvalidFormat(_))));
+        let snapshot = open(&snapshot.to_string_lossy(), Compression::Lz4).unwrap();
+        assert_eq!(vec![b'a'; 100], snapshot.get_value(&"key".to_string()).unwrap());
+    }
+
+    // clock that only moves when told to
+    #[derive(Clone)]
+    struct ManualClock(std::sync::Arc<std::sync::atomic::AtomicU64>);
+
+    impl ManualClock {
+        fn new(now: u64) -> Self {
+            Self(std::sync::Arc::new(std::sync::atomic::AtomicU64::new(now)))
+        }
+
+        fn set(&self, now: u64) {
+            self.0.store(now, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now_millis(&self) -> u64 {
+            self.0.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_ttl_expiry() {
+        let mut persister = new_mock_persister();
+        let clock = ManualClock::new(1_000);
+        persister.set_clock(Box::new(clock.clone()));
+        let key = "key".to_string();
+
+        persister.insert_kv_with_ttl(&key, &[1, 2, 3], Duration::from_millis(100)).unwrap();
+        persister.insert_kv(&"other".to_string(), &[4]).unwrap();
+        clock.set(1_099);
+        assert_eq!(vec![1, 2, 3], persister.get_value(&key).unwrap());
+        assert!(persister.contains_key(&key));
+
+        // expired keys read as missing but keep their space until a write reclaims it
+        clock.set(1_100);
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value(&key));
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.value_len(&key));
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.read_value_into(&key, &mut [0; 3]));
+        assert!(!persister.contains_key(&key));
+        assert_eq!(vec!["other"], persister.keys().collect::<Vec<&String>>());
+        assert_eq!(vec![Ok(("other".to_string(), vec![4]))], persister.iter().collect::<Vec<_>>());
+        assert_eq!(2, persister.len());
+
+        // updating or deleting an expired key fails like for a missing key, and reclaims it
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.update_value(&key, &[5]));
+        assert_eq!(1, persister.len());
+        assert_eq!((None, Some(&Slot { cursor: 0, space: 3 })), persister.freelist.neighbors_of(0, 0));
+
+        // a key inserted again over an expired one takes its place
+        persister.insert_kv_with_ttl(&key, &[6, 7], Duration::from_millis(50)).unwrap();
+        clock.set(1_150);
+        assert_eq!(Ok(PutOutcome::Inserted), persister.put(&key, &[8]));
+        assert_eq!(vec![8], persister.get_value(&key).unwrap());
+
+        // writes without a time to live make the key persistent
+        persister.update_value_with_ttl(&key, &[9], Duration::from_millis(10)).unwrap();
+        persister.update_value(&key, &[10]).unwrap();
+        clock.set(u64::MAX);
+        assert_eq!(vec![10], persister.get_value(&key).unwrap());
+        assert_eq!(Ok(PutOutcome::Updated), persister.put_with_ttl(&key, &[11], Duration::ZERO));
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.delete_kv(&key));
+        assert_eq!(1, persister.len());
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let mut persister = new_mock_persister();
+        let clock = ManualClock::new(0);
+        persister.set_clock(Box::new(clock.clone()));
+
+        persister.insert_kv_with_ttl(&"a".to_string(), &[1; 4], Duration::from_secs(1)).unwrap();
+        persister.insert_kv_with_ttl(&"b".to_string(), &[2; 2], Duration::from_secs(3)).unwrap();
+        persister.insert_kv_with_ttl(&"c".to_string(), &[], Duration::from_secs(1)).unwrap();
+        persister.insert_kv(&"d".to_string(), &[3; 5]).unwrap();
+        persister.put_with_ttl(&"e".to_string(), &[4; 3], Duration::from_secs(2)).unwrap();
+
+        assert_eq!(Ok(PurgeReport { removed: 0, freed: 0 }), persister.purge_expired());
+
+        clock.set(2_000);
+        assert_eq!(Ok(PurgeReport { removed: 3, freed: 7 }), persister.purge_expired());
+        assert_eq!(vec!["b", "d"], persister.keys().collect::<Vec<&String>>());
+        assert_eq!(2, persister.len());
+        // the space of e was at the end of the file, the rest went to the free list
+        assert_eq!(11, persister.last_cursor);
+        assert_eq!((None, Some(&Slot { cursor: 0, space: 4 })), persister.freelist.neighbors_of(0, 0));
+
+        clock.set(3_000);
+        assert_eq!(Ok(PurgeReport { removed: 1, freed: 2 }), persister.purge_expired());
+        assert_eq!(vec![3; 5], persister.get_value(&"d".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_ttl_in_batch() {
+        let mut persister = new_mock_persister();
+        let clock = ManualClock::new(0);
+        persister.set_clock(Box::new(clock.clone()));
+        persister.insert_kv_with_ttl(&"a".to_string(), &[1], Duration::from_millis(10)).unwrap();
+        persister.insert_kv_with_ttl(&"b".to_string(), &[2], Duration::from_millis(10)).unwrap();
+        clock.set(10);
+
+        let mut batch = WriteBatch::new();
+        batch.delete("a".to_string());
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.write_batch(batch));
+
+        // puts in a batch store persistent keys
+        let mut batch = WriteBatch::new();
+        batch.put("b".to_string(), vec![3]);
+        persister.write_batch(batch).unwrap();
+        clock.set(u64::MAX);
+        assert_eq!(vec![3], persister.get_value(&"b".to_string()).unwrap());
+        assert_eq!(1, persister.len());
+    }
+
+    #[test]
+    fn test_ttl_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let clock = ManualClock::new(5_000);
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.set_clock(Box::new(clock.clone()));
+            persister.insert_kv_with_ttl(&"short".to_string(), &[1], Duration::from_secs(1)).unwrap();
+            persister.insert_kv_with_ttl(&"long".to_string(), &[2], Duration::from_secs(10)).unwrap();
+            persister.insert_kv_with_ttl(&"cleared".to_string(), &[3], Duration::from_secs(1)).unwrap();
+            persister.update_value(&"cleared".to_string(), &[4]).unwrap();
+        }
+
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        persister.set_clock(Box::new(clock.clone()));
+        clock.set(7_000);
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value(&"short".to_string()));
+        assert_eq!(vec![2], persister.get_value(&"long".to_string()).unwrap());
+        assert_eq!(vec![4], persister.get_value(&"cleared".to_string()).unwrap());
+
+        clock.set(20_000);
+        assert_eq!(vec!["cleared"], persister.keys().collect::<Vec<&String>>());
+        assert_eq!(Ok(PurgeReport { removed: 2, freed: 2 }), persister.purge_expired());
+    }
+
+    #[test]
+    fn test_reserve_fill_commit() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"a".to_string(), &[1; 4]).unwrap();
+
+        let reservation = persister.reserve(&"big".to_string(), 100).unwrap();
+        assert_eq!(100, reservation.max_len());
+        for (i, chunk) in b"streamed in pieces".chunks(5).enumerate() {
+            reservation.write_at(i * 5, chunk).unwrap();
+        }
+        assert_eq!(
+            Err(KVError::ReservationExceeded { len: 101, reserved: 100 }),
+            reservation.write_at(98, &[0; 3])
+        );
+        reservation.commit(18).unwrap();
+
+        // the slack went back to the end of the file
+        assert_eq!(Slot { cursor: 4, space: 18 }, persister.index.get("big").unwrap().clone());
+        assert_eq!(22, persister.last_cursor);
+        assert_eq!(b"streamed in pieces".to_vec(), persister.get_value(&"big".to_string()).unwrap());
+
+        // a reservation carved from a free slot gives its slack back to the free list
+        persister.delete_kv(&"a".to_string()).unwrap();
+        let reservation = persister.reserve(&"small".to_string(), 3).unwrap();
+        reservation.write_at(0, b"x").unwrap();
+        reservation.commit(1).unwrap();
+        assert_eq!(Slot { cursor: 0, space: 1 }, persister.index.get("small").unwrap().clone());
+        assert_eq!((Some(&Slot { cursor: 1, space: 3 }), None), persister.freelist.neighbors_of(4, 0));
+
+        let reservation = persister.reserve(&"empty".to_string(), 10).unwrap();
+        reservation.commit(0).unwrap();
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
+        assert_eq!(22, persister.last_cursor);
+
+        assert!(matches!(persister.reserve(&"big".to_string(), 1), Err(KVError::KeyAlreadyExist)));
+        let reservation = persister.reserve(&"over".to_string(), 2).unwrap();
+        assert_eq!(Err(KVError::ReservationExceeded { len: 3, reserved: 2 }), reservation.commit(3));
+        assert!(!persister.contains_key(&"over".to_string()));
+        assert_eq!(22, persister.last_cursor);
+    }
+
+    #[test]
+    fn test_reserve_abort_and_drop() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"a".to_string(), &[1; 4]).unwrap();
+        persister.insert_kv(&"b".to_string(), &[2; 4]).unwrap();
+        persister.delete_kv(&"a".to_string()).unwrap();
+
+        let reservation = persister.reserve(&"c".to_string(), 50).unwrap();
+        reservation.write_at(0, &[3; 50]).unwrap();
+        reservation.abort();
+        assert_eq!(8, persister.last_cursor);
+
+        {
+            let reservation = persister.reserve(&"c".to_string(), 2).unwrap();
+            reservation.write_at(0, &[3; 2]).unwrap();
+        }
+        // the piece split from the free slot was merged back with the rest of it
+        assert_eq!((Some(&Slot { cursor: 0, space: 4 }), None), persister.freelist.neighbors_of(4, 0));
+
+        assert!(!persister.contains_key(&"c".to_string()));
+        assert_eq!(1, persister.len());
+        assert_eq!(vec![2; 4], persister.get_value(&"b".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_reserve_uncommitted_is_discarded_on_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"a".to_string(), &[1; 4]).unwrap();
+            let reservation = persister.reserve(&"pending".to_string(), 8).unwrap();
+            reservation.write_at(0, &[9; 8]).unwrap();
+            // crash before commit, without running any cleanup
+            std::mem::forget(reservation);
+        }
+
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        assert!(!persister.contains_key(&"pending".to_string()));
+        assert_eq!(4, persister.last_cursor);
+        persister.insert_kv(&"b".to_string(), &[2; 8]).unwrap();
+        assert_eq!(Slot { cursor: 4, space: 8 }, persister.index.get("b").unwrap().clone());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_reserve_with_compression() {
+        let mut persister = new_mock_persister();
+        persister.compression = Compression::Lz4;
+        let _ = persister.live_digest().unwrap();
+
+        let reservation = persister.reserve(&"key".to_string(), 10).unwrap();
+        reservation.write_at(0, b"abc").unwrap();
+        reservation.commit(3).unwrap();
+        assert_eq!(4, persister.index.get("key").unwrap().space);
+        assert_eq!(b"abc".to_vec(), persister.get_value(&"key".to_string()).unwrap());
+        assert_eq!(persister.scan_live_digest().unwrap(), persister.live_digest().unwrap());
+    }
+
+    #[test]
+    fn test_snapshot_point_in_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot_path = dir.path().join("snapshot");
+        let mut persister = new_mock_persister();
+        for i in 0..20 {
+            persister.insert_kv(&format!("key_{:02}", i), &vec![i as u8; i % 5]).unwrap();
+        }
+        for i in (0..20).step_by(3) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        persister.update_value(&"key_01".to_string(), &[7; 9]).unwrap();
+        let expected: Vec<(String, Vec<u8>)> = persister.iter().map(|item| item.unwrap()).collect();
+        let digest = persister.content_digest().unwrap();
+
+        let info = persister.snapshot_to(&snapshot_path).unwrap();
+        let bytes: usize = expected.iter().map(|(_, value)| value.len()).sum();
+        assert_eq!(SnapshotInfo { keys: expected.len(), bytes, skipped_quarantined: 0 }, info);
+
+        persister.insert_kv(&"after".to_string(), &[1]).unwrap();
+        persister.delete_kv(&"key_02".to_string()).unwrap();
+        persister.update_value(&"key_04".to_string(), &[]).unwrap();
+
+        // the snapshot holds the state at snapshot time, packed without holes
+        let mut snapshot: Persister<String> = Persister::open_snapshot(&snapshot_path).unwrap();
+        assert_eq!(expected, snapshot.iter().map(|item| item.unwrap()).collect::<Vec<_>>());
+        assert_eq!(digest, snapshot.content_digest().unwrap());
+        assert_eq!(bytes, snapshot.last_cursor);
+        assert_eq!(None, snapshot.freelist.retrieve_free_space(1));
+        assert_eq!(FORMAT_HEADER_LEN + bytes as u64, std::fs::metadata(&snapshot_path).unwrap().len());
+    }
+
+    #[test]
+    fn test_snapshot_edge_cases() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = new_mock_persister();
+
+        // an empty store gives an empty snapshot
+        let empty_path = dir.path().join("empty");
+        assert_eq!(SnapshotInfo::default(), persister.snapshot_to(&empty_path).unwrap());
+        assert!(Persister::<String>::open_snapshot(&empty_path).unwrap().is_empty());
+
+        // existing files are never clobbered
+        persister.insert_kv(&"a".to_string(), &[]).unwrap();
+        persister.insert_kv(&"b".to_string(), &[1, 2]).unwrap();
+        assert_eq!(Err(KVError::DatastoreAlreadyExists), persister.snapshot_to(&empty_path));
+        assert!(Persister::<String>::open_snapshot(&empty_path).unwrap().is_empty());
+        assert!(matches!(persister.snapshot_to(Path::new("/")), Err(KVError::InvalidArgument(_))));
+
+        // empty values, quarantined keys and expiring keys
+        let clock = ManualClock::new(0);
+        persister.set_clock(Box::new(clock.clone()));
+        persister.insert_kv_with_ttl(&"soon".to_string(), &[3], Duration::from_millis(10)).unwrap();
+        persister.insert_kv_with_ttl(&"later".to_string(), &[4], Duration::from_millis(100)).unwrap();
+        persister.insert_kv(&"lost".to_string(), &[5; 100]).unwrap();
+        persister.header.db_file.set_len(5).unwrap();
+        persister.set_eof_policy(EofPolicy::Quarantine);
+        let _ = persister.get_value(&"lost".to_string());
+        clock.set(10);
+
+        let path = dir.path().join("full");
+        assert_eq!(SnapshotInfo { keys: 3, bytes: 3, skipped_quarantined: 1 }, persister.snapshot_to(&path).unwrap());
+        let mut snapshot: Persister<String> = Persister::open_snapshot(&path).unwrap();
+        snapshot.set_clock(Box::new(clock.clone()));
+        assert_eq!(Vec::<u8>::new(), snapshot.get_value(&"a".to_string()).unwrap());
+        assert_eq!(vec![1, 2], snapshot.get_value(&"b".to_string()).unwrap());
+        assert_eq!(vec![4], snapshot.get_value(&"later".to_string()).unwrap());
+        clock.set(100);
+        assert_eq!(Err(KVError::KeyDoesNotExist), snapshot.get_value(&"later".to_string()));
+    }
+
+    #[test]
+    fn test_snapshot_failure_removes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"a".to_string(), &[1; 10]).unwrap();
+        persister.header.db_file.set_len(5).unwrap();
+
+        assert!(matches!(persister.snapshot_to(&path), Err(KVError::SlotBeyondEof { .. })));
+        assert!(!path.exists());
+        assert!(!dir.path().join("index_snapshot").exists());
+    }
+
+    #[test]
+    fn test_split_into() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = new_mock_persister();
+        for i in 0..30 {
+            persister.insert_kv(&format!("key_{:02}", i), &vec![i as u8; i % 7]).unwrap();
+        }
+        for i in (0..30).step_by(4) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        let expected: Vec<(String, Vec<u8>)> = persister.iter().map(|item| item.unwrap()).collect();
+
+        let boundaries = ["key_10".to_string(), "key_20".to_string()];
+        let report = persister.split_into(&boundaries, dir.path()).unwrap();
+        assert_eq!(3, report.parts.len());
+
+        // every part only holds keys of its range and together they hold the whole store
+        let ranges = [(None, Some(&boundaries[0])), (Some(&boundaries[0]), Some(&boundaries[1])), (Some(&boundaries[1]), None)];
+        let mut union = vec![];
+        for (part, (lower, upper)) in ranges.into_iter().enumerate() {
+            let path = dir.path().join(format!("part{}", part));
+            let store: Persister<String> = Persister::open_snapshot(&path).unwrap();
+            let entries: Vec<(String, Vec<u8>)> = store.iter().map(|item| item.unwrap()).collect();
+            assert!(entries.iter().all(|(key, _)| lower.is_none_or(|lower| key >= lower) && upper.is_none_or(|upper| key < upper)));
+
+            let bytes: usize = entries.iter().map(|(_, value)| value.len()).sum();
+            assert_eq!(SnapshotInfo { keys: entries.len(), bytes, skipped_quarantined: 0 }, report.parts[part]);
+            assert_eq!(FORMAT_HEADER_LEN + bytes as u64, std::fs::metadata(&path).unwrap().len());
+            union.extend(entries);
+        }
+        assert_eq!(expected, union);
+        assert_eq!(expected, persister.iter().map(|item| item.unwrap()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_split_into_edge_cases() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"b".to_string(), &[1]).unwrap();
+        persister.insert_kv(&"c".to_string(), &[2, 3]).unwrap();
+
+        // boundaries out of order or repeated write nothing
+        for boundaries in [vec!["c".to_string(), "b".to_string()], vec!["b".to_string(), "b".to_string()]] {
+            assert!(matches!(persister.split_into(&boundaries, dir.path()), Err(KVError::InvalidArgument(_))));
+            assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+        }
+
+        // no boundaries copy the whole store, parts without keys are still created
+        let whole = dir.path().join("whole");
+        std::fs::create_dir(&whole).unwrap();
+        assert_eq!(vec![SnapshotInfo { keys: 2, bytes: 3, skipped_quarantined: 0 }], persister.split_into(&[], &whole).unwrap().parts);
+        let report = persister.split_into(&["a".to_string(), "z".to_string()], dir.path()).unwrap();
+        assert_eq!(vec![SnapshotInfo::default(), SnapshotInfo { keys: 2, bytes: 3, skipped_quarantined: 0 }, SnapshotInfo::default()], report.parts);
+        assert!(Persister::<String>::open_snapshot(&dir.path().join("part0")).unwrap().is_empty());
+        assert!(Persister::<String>::open_snapshot(&dir.path().join("part2")).unwrap().is_empty());
+
+        // a part already present fails the split, the parts written before it are removed
+        let other = dir.path().join("other");
+        std::fs::create_dir(&other).unwrap();
+        std::fs::write(other.join("part1"), b"kept").unwrap();
+        assert_eq!(Err(KVError::DatastoreAlreadyExists), persister.split_into(&["c".to_string()], &other));
+        assert!(!other.join("part0").exists());
+        assert_eq!(b"kept".to_vec(), std::fs::read(other.join("part1")).unwrap());
+    }
+
+    // appends an entry to the write-ahead log without applying it, as if the process died
+    // right after logging the write
+    fn log_unapplied(persister: &Persister<String>, ops: &[WalOp]) {
+        let wal_file = persister.header.wal_file.as_ref().unwrap();
+        let len = wal_file.metadata().unwrap().len();
+        wal_file.write_all_at(&wal::encode_entry(ops), len).unwrap();
+    }
+
+    fn wal_put(key: &str, slot: Slot, value: &[u8]) -> WalOp {
+        WalOp::Put { key: encode_key(&key.to_string()).unwrap(), slot, expires_at: None, value: Some(value.to_vec()) }
+    }
+
+    #[test]
+    fn test_wal_replays_logged_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+            log_unapplied(&persister, &[
+                wal_put("key2", Slot { cursor: 3, space: 2 }, b"de"),
+                WalOp::Delete { key: encode_key(&"key1".to_string()).unwrap() },
+            ]);
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore.clone(), 0).unwrap();
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value(&"key1".to_string()));
+        assert_eq!(0, persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len());
+        drop(persister);
+
+        // the replayed writes made it to the index file
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![&"key2".to_string()], persister.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_wal_stops_at_torn_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            log_unapplied(&persister, &[wal_put("key1", Slot { cursor: 0, space: 2 }, b"ab")]);
+            let wal_file = persister.header.wal_file.as_ref().unwrap();
+            let len = wal_file.metadata().unwrap().len();
+            let torn = wal::encode_entry(&[wal_put("key2", Slot { cursor: 2, space: 2 }, b"cd")]);
+            wal_file.write_all_at(&torn[..torn.len() - 1], len).unwrap();
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![&"key1".to_string()], persister.keys().collect::<Vec<_>>());
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_wal_recovers_torn_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+            persister.update_value(&"key1".to_string(), b"xyz").unwrap();
+            persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+
+            // the write of key2 only got half way and the last index record got cut
+            persister.header.write_data_at(&[0, 0], 1).unwrap();
+            let len = persister.header.index_file.metadata().unwrap().len();
+            persister.header.index_file.set_len(len - 1).unwrap();
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![b'x', b'y', b'z'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_wal_recovers_index_record_torn_in_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+            persister.insert_kv(&"key2".to_string(), b"de").unwrap();
+
+            // the record of key2 is cut 3 bytes into its 12 byte key
+            let record_len = IndexRecord::Put { key: encode_key(&"key2".to_string()).unwrap(), slot: Slot { cursor: 3, space: 2 }, expires_at: None }.encode().len() as u64;
+            let len = persister.header.index_file.metadata().unwrap().len();
+            persister.header.index_file.set_len(len - record_len + 1 + 4 + 3).unwrap();
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec!["key1", "key2"], persister.keys().collect::<Vec<_>>());
+        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_wal_replays_batches_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+            let len = persister.header.index_file.metadata().unwrap().len();
+            let mut batch = WriteBatch::new();
+            batch.put("key2".to_string(), vec![b'b', b'c']);
+            batch.delete("key1".to_string());
+            persister.write_batch(batch).unwrap();
+
+            // only a piece of the first record of the batch reached the index file
+            persister.header.index_file.set_len(len + 1).unwrap();
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![&"key2".to_string()], persister.keys().collect::<Vec<_>>());
+        assert_eq!(vec![b'b', b'c'], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_flush_checkpoints_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let wal_len = || persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len();
+        assert!(wal_len() > 0);
+
+        persister.flush().unwrap();
+        assert_eq!(0, persister.header.wal_file.as_ref().unwrap().metadata().unwrap().len());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut persister = new_mock_persister();
+        assert_eq!(StoreStats::default(), persister.stats().unwrap());
+
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 10]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 5]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 8]).unwrap();
+        assert_eq!(StoreStats {
+            keys: 3, live_bytes: 23, file_len: 23, free_bytes: 0, free_fragments: 0,
+            largest_free_fragment: 0, last_cursor: 23, quarantined_keys: 0, cache_hits: 0, cache_misses: 0,
+        }, persister.stats().unwrap());
+
+        // 10..15 is freed
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        assert_eq!(StoreStats {
+            keys: 2, live_bytes: 18, file_len: 23, free_bytes: 5, free_fragments: 1,
+            largest_free_fragment: 5, last_cursor: 23, quarantined_keys: 0, cache_hits: 0, cache_misses: 0,
+        }, persister.stats().unwrap());
+
+        // key1 moves into 10..13 and frees 0..10, 13..15 is left of the free slot it took
+        persister.update_value(&"key1".to_string(), &[b'd'; 3]).unwrap();
+        assert_eq!(StoreStats {
+            keys: 2, live_bytes: 11, file_len: 23, free_bytes: 12, free_fragments: 2,
+            largest_free_fragment: 10, last_cursor: 23, quarantined_keys: 0, cache_hits: 0, cache_misses: 0,
+        }, persister.stats().unwrap());
+
+        // the last slot and the free slots right before it go back to the end of the data, the
+        // file keeps its length
+        persister.delete_kv(&"key3".to_string()).unwrap();
+        assert_eq!(StoreStats {
+            keys: 1, live_bytes: 3, file_len: 23, free_bytes: 10, free_fragments: 1,
+            largest_free_fragment: 10, last_cursor: 13, quarantined_keys: 0, cache_hits: 0, cache_misses: 0,
+        }, persister.stats().unwrap());
+
+        persister.insert_kv(&"key4".to_string(), &[b'e'; 10]).unwrap();
+        persister.insert_kv(&"key5".to_string(), &[]).unwrap();
+        assert_eq!(StoreStats {
+            keys: 3, live_bytes: 13, file_len: 23, free_bytes: 0, free_fragments: 0,
+            largest_free_fragment: 0, last_cursor: 13, quarantined_keys: 0, cache_hits: 0, cache_misses: 0,
+        }, persister.stats().unwrap());
+    }
+
+    #[test]
+    fn test_kv_error() {
+        let not_found = || KVError::from(std::io::Error::new(ErrorKind::NotFound, "no such file"));
+        assert_eq!("i/o error: no such file", not_found().to_string());
+        assert_eq!("key does not exist", KVError::KeyDoesNotExist.to_string());
+        assert_eq!(
+            "slot 4..10 extends past the end of the db file (6 bytes)",
+            KVError::SlotBeyondEof { cursor: 4, len: 6, file_len: 6 }.to_string()
+        );
+
+        // the io error is kept as the source, only its kind takes part in equality
+        let error: Box<dyn std::error::Error> = Box::new(not_found());
+        let source = error.source().unwrap().downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(ErrorKind::NotFound, source.kind());
+        assert_eq!(not_found(), KVError::IOError(std::io::Error::new(ErrorKind::NotFound, "other message")));
+        assert_ne!(not_found(), KVError::IOError(std::io::Error::other("no such file")));
+        assert_ne!(KVError::KeyDoesNotExist, KVError::KeyAlreadyExist);
+        assert_ne!(KVError::Corruption("a".to_string()), KVError::InvalidArgument("a".to_string()));
+    }
+
+    #[test]
+    fn test_load_index_unknown_record() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.header.index_file.write_all_at(&[9], 0).unwrap();
+
+        assert!(matches!(persister.load_index(), Err(KVError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_load_index_corrupt_key_length() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"b").unwrap();
+        // the key length of the first record, right after its type
+        persister.header.index_file.write_all_at(&u32::MAX.to_le_bytes(), 1).unwrap();
+
+        assert!(matches!(persister.load_index(), Err(KVError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_insert_kv_key_too_long() {
+        let mut persister = new_mock_persister();
+        let key = "k".repeat(indexlog::MAX_KEY_LEN);
+
+        assert!(matches!(persister.insert_kv(&key, b"a"), Err(KVError::InvalidArgument(_))));
+        assert_eq!(0, persister.header.db_file.metadata().unwrap().len());
+        assert_eq!(0, persister.header.index_file.metadata().unwrap().len());
+        assert_eq!(None, persister.keys().next());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_reads_follow_writes() {
+        let mut persister = new_mock_persister();
+        assert!(matches!(persister.get_value_ref(&"key1".to_string()), Err(KVError::InvalidArgument(_))));
+
+        // the db file is empty when the reads get mapped
+        persister.set_mmap_reads(true).unwrap();
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value_ref(&"key1".to_string()));
+
+        let mut batch = WriteBatch::new();
+        batch.put("key1".to_string(), vec![b'a'; 10]);
+        batch.put("key2".to_string(), vec![]);
+        persister.write_batch(batch).unwrap();
+        assert_eq!(&[b'a'; 10], persister.get_value_ref(&"key1".to_string()).unwrap());
+        assert_eq!(b"", persister.get_value_ref(&"key2".to_string()).unwrap());
+
+        // rewritten and grown past the end of the map
+        persister.update_value(&"key1".to_string(), &[b'b'; 10]).unwrap();
+        assert_eq!(&[b'b'; 10], persister.get_value_ref(&"key1".to_string()).unwrap());
+        persister.update_value(&"key1".to_string(), &vec![b'c'; 5000]).unwrap();
+        assert_eq!(&[b'c'; 5000][..], persister.get_value_ref(&"key1".to_string()).unwrap());
+        persister.insert_from_reader(&"key3".to_string(), &[b'd'; 100][..], 100).unwrap();
+        assert_eq!(&[b'd'; 100][..], persister.get_value_ref(&"key3".to_string()).unwrap());
+
+        // the freed space is reused by a new key
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        persister.insert_kv(&"key4".to_string(), &[b'e'; 7]).unwrap();
+        assert_eq!(&[b'e'; 7], persister.get_value_ref(&"key4".to_string()).unwrap());
+        assert_eq!(vec![b'e'; 7], persister.get_value(&"key4".to_string()).unwrap());
+        let mut buffer = [0; 7];
+        assert_eq!(7, persister.read_value_into(&"key4".to_string(), &mut buffer).unwrap());
+        assert_eq!([b'e'; 7], buffer);
+
+        // the map is dropped once reads go back to the file
+        persister.set_mmap_reads(false).unwrap();
+        assert!(matches!(persister.get_value_ref(&"key4".to_string()), Err(KVError::InvalidArgument(_))));
+        assert_eq!(vec![b'd'; 100], persister.get_value(&"key3".to_string()).unwrap());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_reads_match_file_reads() {
+        let mut persister = new_mock_persister();
+        let keys: Vec<String> = (0..1000).map(|i| format!("key{}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            persister.insert_kv(key, &(i as u128).to_le_bytes()).unwrap();
+        }
+
+        let from_file: Vec<Vec<u8>> = keys.iter().map(|key| persister.get_value(key).unwrap()).collect();
+        persister.set_mmap_reads(true).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(from_file[i], persister.get_value_ref(key).unwrap());
+            assert_eq!((i as u128).to_le_bytes(), persister.get_value_ref(key).unwrap());
+        }
+    }
+
+    #[cfg(all(feature = "mmap", feature = "compression"))]
+    #[test]
+    fn test_mmap_reads_with_compression() {
+        let mut persister = new_mock_persister();
+        persister.compression = Compression::Lz4;
+        persister.set_mmap_reads(true).unwrap();
+
+        persister.insert_kv(&"packed".to_string(), &b"abcdefgh".repeat(100)).unwrap();
+        persister.insert_from_reader(&"raw".to_string(), &b"stored as given"[..], 15).unwrap();
+        assert_eq!(b"abcdefgh".repeat(100), persister.get_value(&"packed".to_string()).unwrap());
+        assert!(matches!(persister.get_value_ref(&"packed".to_string()), Err(KVError::CompressionError(_))));
+        assert_eq!(b"stored as given", persister.get_value_ref(&"raw".to_string()).unwrap());
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, serde::Deserialize)]
+    struct SensorKey {
+        sensor: String,
+        reading: u32,
+    }
+
+    // writes the keys with their index as value, deletes the second one and checks the rest
+    // survives reopening the store
+    fn check_key_type<K>(keys: Vec<K>)
+    where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<K> = Persister::new(datastore.clone(), 0).unwrap();
+            for (i, key) in keys.iter().enumerate() {
+                persister.insert_kv(key, &vec![i as u8; i + 1]).unwrap();
+            }
+            persister.update_value(&keys[0], &[b'u'; 3]).unwrap();
+            persister.delete_kv(&keys[1]).unwrap();
+        }
+
+        let persister: Persister<K> = Persister::open_existing(datastore, 0).unwrap();
+        let mut expected = keys.clone();
+        expected.remove(1);
+        expected.sort();
+        assert_eq!(expected.iter().collect::<Vec<_>>(), persister.keys().collect::<Vec<_>>());
+        assert_eq!(vec![b'u'; 3], persister.get_value(&keys[0]).unwrap());
+        assert_eq!(Err(KVError::KeyDoesNotExist), persister.get_value(&keys[1]));
+        for (i, key) in keys.iter().enumerate().skip(2) {
+            assert_eq!(vec![i as u8; i + 1], persister.get_value(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_key_types() {
+        check_key_type::<u64>(vec![300, 7, u64::MAX, 0, 1 << 40]);
+        check_key_type::<i32>(vec![-5, 5, i32::MIN, 0]);
+        check_key_type::<Vec<u8>>(vec![vec![1, 2], vec![], vec![0xff], vec![1]]);
+        check_key_type::<(String, u32)>(vec![
+            ("b".to_string(), 1),
+            ("a".to_string(), 2),
+            ("a".to_string(), 1),
+            ("".to_string(), u32::MAX),
+        ]);
+        check_key_type::<SensorKey>(vec![
+            SensorKey { sensor: "north".to_string(), reading: 2 },
+            SensorKey { sensor: "north".to_string(), reading: 1 },
+            SensorKey { sensor: "east".to_string(), reading: 9 },
+        ]);
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"existing".to_string(), &[b'x'; 4]).unwrap();
+
+        let value_of = |i: usize| -> Vec<u8> { format!("value{}", i).into_bytes() };
+        let pairs: Vec<(String, Vec<u8>)> = (0..50_000).map(|i| (format!("key{:05}", i), value_of(i))).collect();
+        let total: usize = pairs.iter().map(|(_, value)| value.len()).sum();
+
+        persister.bulk_load(pairs).unwrap();
+
+        assert_eq!(50_001, persister.len());
+        assert_eq!(4 + total, persister.last_cursor);
+        for i in [0, 1, 9_999, 25_000, 49_999] {
+            assert_eq!(value_of(i), persister.get_value(&format!("key{:05}", i)).unwrap());
+        }
+        assert_eq!(vec![b'x'; 4], persister.get_value(&"existing".to_string()).unwrap());
+        assert_eq!(Slot {cursor: 4, space: 6}, persister.index[&"key00000".to_string()]);
+
+        // the index file replays to the same index
+        let index = persister.index.clone();
+        persister.load_index().unwrap();
+        assert_eq!(index, persister.index);
+        assert_eq!(4 + total, persister.last_cursor);
+    }
+
+    #[test]
+    fn test_bulk_load_edge_cases() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 3]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 3]).unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let stats = persister.stats().unwrap();
+
+        // duplicates within the load or with the store leave everything as it was
+        let duplicated = vec![("key3".to_string(), vec![1]), ("key3".to_string(), vec![2])];
+        assert_eq!(Err(KVError::KeyAlreadyExist), persister.bulk_load(duplicated));
+        let existing = vec![("key3".to_string(), vec![1]), ("key2".to_string(), vec![2])];
+        assert_eq!(Err(KVError::KeyAlreadyExist), persister.bulk_load(existing));
+        assert_eq!(stats, persister.stats().unwrap());
+        assert_eq!(vec![&"key2".to_string()], persister.keys().collect::<Vec<_>>());
+
+        // empty values, values bigger than a chunk and values around them, the free slot is
+        // left alone
+        let big = vec![b'c'; BULK_LOAD_CHUNK_SIZE + 10];
+        let almost = vec![b'd'; BULK_LOAD_CHUNK_SIZE - 1];
+        persister.bulk_load(vec![
+            ("empty".to_string(), vec![]),
+            ("small".to_string(), vec![b'e'; 2]),
+            ("big".to_string(), big.clone()),
+            ("almost".to_string(), almost.clone()),
+            ("tail".to_string(), vec![b'f'; 5]),
+        ]).unwrap();
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
+        assert_eq!(Slot {cursor: 6, space: 2}, persister.index[&"small".to_string()]);
+        assert_eq!(big, persister.get_value(&"big".to_string()).unwrap());
+        assert_eq!(almost, persister.get_value(&"almost".to_string()).unwrap());
+        assert_eq!(vec![b'f'; 5], persister.get_value(&"tail".to_string()).unwrap());
+        assert_eq!(6 + 2 + big.len() + almost.len() + 5, persister.last_cursor);
+        assert_eq!((3, 1), (persister.freelist.total_free_space(), persister.freelist.fragment_count()));
+        assert_eq!(Ok(()), persister.bulk_load(vec![]));
+    }
+
+    #[test]
+    fn test_bulk_load_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+
+        {
+            let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+            persister.bulk_load((0..100).map(|i| (format!("key{}", i), vec![i as u8; i]))).unwrap();
+            std::mem::forget(persister);
+        }
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(100, persister.len());
+        assert_eq!(vec![42; 42], persister.get_value(&"key42".to_string()).unwrap());
+    }
+
+    fn raw_bytes(persister: &Persister<String>, cursor: usize, space: usize) -> Vec<u8> {
+        read_slot(&persister.header, cursor, space).unwrap()
+    }
+
+    #[test]
+    fn test_secure_delete() {
+        let mut persister = new_mock_persister();
+        persister.set_secure_delete(true);
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 6]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 3]).unwrap();
+        persister.insert_kv(&"key4".to_string(), &[b'd'; 5]).unwrap();
+
+        // a key between two live values
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        assert_eq!(vec![0; 6], raw_bytes(&persister, 4, 6));
+        assert_eq!(vec![b'a'; 4], raw_bytes(&persister, 0, 4));
+        assert_eq!(vec![b'c'; 3], raw_bytes(&persister, 10, 3));
+
+        // the last value of the file
+        persister.delete_kv(&"key4".to_string()).unwrap();
+        assert_eq!(vec![0; 5], raw_bytes(&persister, 13, 5));
+
+        // an update zeroes the previous slot once the value is written to its new one
+        persister.update_value(&"key1".to_string(), &[b'e'; 1]).unwrap();
+        assert_eq!(Slot {cursor: 4, space: 1}, persister.index[&"key1".to_string()]);
+        assert_eq!([vec![0; 4], vec![b'e'], vec![0; 5], vec![b'c'; 3]].concat(), raw_bytes(&persister, 0, 13));
+        persister.update_value(&"key1".to_string(), &[b'f'; 5]).unwrap();
+        assert_eq!(Slot {cursor: 5, space: 5}, persister.index[&"key1".to_string()]);
+        assert_eq!([vec![0; 5], vec![b'f'; 5], vec![b'c'; 3]].concat(), raw_bytes(&persister, 0, 13));
+
+        // values replaced or deleted by a batch, key5 goes to 0..2 and the new key3 to 2..4
+        persister.insert_kv(&"key5".to_string(), &[b'g'; 2]).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("key3".to_string(), vec![b'h'; 2]);
+        batch.delete("key1".to_string());
+        persister.write_batch(batch).unwrap();
+        assert_eq!(Slot {cursor: 2, space: 2}, persister.index[&"key3".to_string()]);
+        assert_eq!([vec![b'g'; 2], vec![b'h'; 2], vec![0; 11]].concat(), raw_bytes(&persister, 0, 15));
+    }
+
+    #[test]
+    fn test_delete_keeps_bytes_without_secure_delete() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 2]).unwrap();
+
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        assert_eq!(vec![b'a'; 4], raw_bytes(&persister, 0, 4));
+    }
+
+    #[test]
+    fn test_txn_commit_and_rollback() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"b").unwrap();
+
+        let mut txn = persister.begin();
+        txn.put("key3".to_string(), vec![b'c']).put("key1".to_string(), vec![b'd', b'e']);
+        txn.delete(&"key2".to_string()).unwrap();
+        assert!(matches!(txn.delete(&"key4".to_string()), Err(KVError::KeyDoesNotExist)));
+        txn.rollback();
+
+        assert_eq!(vec![b'a'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'b'], persister.get_value(&"key2".to_string()).unwrap());
+        assert!(!persister.contains_key(&"key3".to_string()));
+
+        // dropping the transaction discards it as well
+        {
+            let mut txn = persister.begin();
+            txn.put("key3".to_string(), vec![b'c']);
+        }
+        assert!(!persister.contains_key(&"key3".to_string()));
+        assert_eq!(2, persister.len());
+
+        let mut txn = persister.begin();
+        txn.put("key3".to_string(), vec![b'c']).put("key1".to_string(), vec![b'd', b'e']);
+        txn.delete(&"key2".to_string()).unwrap();
+        assert_eq!(3, txn.len());
+        txn.commit().unwrap();
+
+        assert_eq!(vec![b'd', b'e'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key2".to_string()).unwrap_err());
+        assert_eq!(vec![b'c'], persister.get_value(&"key3".to_string()).unwrap());
+        assert_eq!(2, persister.len());
+    }
+
+    #[test]
+    fn test_txn_read_your_own_writes() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"b").unwrap();
+
+        let mut txn = persister.begin();
+        txn.put("key1".to_string(), vec![b'c']).put("key3".to_string(), vec![b'd']);
+        txn.delete(&"key2".to_string()).unwrap();
+
+        assert_eq!(vec![b'c'], txn.get(&"key1".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, txn.get(&"key2".to_string()).unwrap_err());
+        assert_eq!(vec![b'd'], txn.get(&"key3".to_string()).unwrap());
+        assert!(!txn.contains_key(&"key2".to_string()));
+        assert!(txn.contains_key(&"key3".to_string()));
+
+        // a key put and deleted inside the transaction never reaches the store
+        txn.delete(&"key3".to_string()).unwrap();
+        assert_eq!(KVError::KeyDoesNotExist, txn.get(&"key3".to_string()).unwrap_err());
+        assert!(matches!(txn.delete(&"key3".to_string()), Err(KVError::KeyDoesNotExist)));
+        txn.commit().unwrap();
+
+        assert_eq!(vec![b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        assert!(!persister.contains_key(&"key2".to_string()));
+        assert!(!persister.contains_key(&"key3".to_string()));
+    }
+
+    #[test]
+    fn test_txn_delete_then_insert() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+
+        let mut txn = persister.begin();
+        txn.delete(&"key1".to_string()).unwrap();
+        assert_eq!(KVError::KeyDoesNotExist, txn.get(&"key1".to_string()).unwrap_err());
+        txn.put("key1".to_string(), vec![b'd']);
+        assert_eq!(vec![b'd'], txn.get(&"key1".to_string()).unwrap());
+        assert_eq!(1, txn.len());
+        txn.commit().unwrap();
+
+        assert_eq!(vec![b'd'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(1, persister.len());
+    }
+
+    #[test]
+    fn test_txn_commit_failure_leaves_store_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_mock_persister(dir.path());
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"d").unwrap();
+
+        let freelist = persister.freelist.clone();
+        let index = persister.index.clone();
+        let live_slots = persister.live_slots.clone();
+
+        // swap the db file for a read-only handle so every value write fails
+        let writable = std::mem::replace(
+            &mut persister.header.db_file,
+            OpenOptions::new().read(true).open(dir.path().join("db")).unwrap(),
+        );
+
+        let mut txn = persister.begin();
+        txn.put("key3".to_string(), vec![b'e', b'f']).put("key1".to_string(), vec![b'g']);
+        txn.delete(&"key2".to_string()).unwrap();
+        assert!(matches!(txn.commit(), Err(KVError::IOError(_))));
+
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(index, persister.index);
+        assert_eq!(live_slots, persister.live_slots);
+        assert_eq!(vec![b'd'], persister.get_value(&"key2".to_string()).unwrap());
+        persister.header.db_file = writable;
+    }
+
+    #[test]
+    fn test_txn_commit_torn_index_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        persister.insert_kv(&"key2".to_string(), b"d").unwrap();
+        let index = persister.index.clone();
+        let live_slots = persister.live_slots.clone();
+
+        // the values are written, the records of the commit are cut after the first one
+        let mut txn = persister.begin();
+        txn.put("key3".to_string(), b"ef".to_vec()).put("key1".to_string(), b"g".to_vec());
+        txn.delete(&"key2".to_string()).unwrap();
+        faults::tear_next_write(FileKind::Index, 40);
+        assert!(matches!(txn.commit(), Err(KVError::IOError(_))));
+
+        assert_eq!(index, persister.index);
+        assert_eq!(live_slots, persister.live_slots);
+        assert_eq!(b"abc".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+        drop(persister);
+
+        // nothing of the commit is replayed either
+        let persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        assert_eq!(index, persister.index);
+        assert_eq!(b"abc".to_vec(), persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(b"d".to_vec(), persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+
+        // created when missing and reopened without losing the data, like Persister::new
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).open().unwrap();
+            assert!(!persister.read_only);
+            assert_eq!(0, persister.storage_limit);
+            assert_eq!(SyncMode::Never, persister.sync_mode);
+            persister.insert_kv(&"key1".to_string(), b"ab").unwrap();
+        }
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).open().unwrap();
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+        drop(persister);
+
+        let persister: Persister<String> = Persister::new(datastore.to_string_lossy().to_string(), 0).unwrap();
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+        drop(persister);
+
+        // truncating empties every file of the store
+        let mut persister: Persister<String> = PersisterBuilder::new()
+            .path(&datastore)
+            .truncate(true)
+            .sync_mode(SyncMode::Always)
+            .open()
+            .unwrap();
+        assert!(persister.is_empty());
+        assert_eq!(0, persister.last_cursor);
+        assert_eq!(SyncMode::Always, persister.sync_mode);
+        persister.insert_kv(&"key2".to_string(), b"c").unwrap();
+        assert_eq!(vec![&"key2".to_string()], persister.keys().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_builder_store_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).open().unwrap();
+            for (key, len) in [("key1", 4), ("hole1", 6), ("key2", 2), ("hole2", 4), ("key3", 2)] {
+                persister.insert_kv(&key.to_string(), &vec![b'a'; len]).unwrap();
+            }
+            persister.delete_kv(&"hole1".to_string()).unwrap();
+            persister.delete_kv(&"hole2".to_string()).unwrap();
+        }
+
+        let mut persister: Persister<String> = PersisterBuilder::new()
+            .path(&datastore)
+            .secure_delete(true)
+            .eof_policy(EofPolicy::Quarantine)
+            .allocation_strategy(AllocationStrategy::FirstFit)
+            .open()
+            .unwrap();
+        assert!(persister.secure_delete);
+        assert_eq!(EofPolicy::Quarantine, persister.eof_policy);
+        assert_eq!(AllocationStrategy::FirstFit, persister.freelist.strategy());
+
+        // the first hole is picked over the one that fits best, and deleted values are zeroed
+        persister.insert_kv(&"key4".to_string(), &[b'b'; 3]).unwrap();
+        assert_eq!(Slot { cursor: 4, space: 3 }, persister.index[&"key4".to_string()]);
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let offset = FORMAT_HEADER_LEN as usize;
+        assert_eq!(vec![0; 4], std::fs::read(&datastore).unwrap()[offset..offset + 4]);
+
+        persister.header.db_file.set_len(FORMAT_HEADER_LEN + 16).unwrap();
+        let _ = persister.get_value(&"key3".to_string()).unwrap_err();
+        assert_eq!(vec!["key3".to_string()], persister.quarantined_keys());
+    }
+
+    #[test]
+    fn test_builder_create_if_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+
+        let result = PersisterBuilder::new().path(&datastore).create_if_missing(false).open::<String>();
+        assert_eq!(KVError::DatastoreDoesNotExist, result.err().unwrap());
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new()
+                .path(&datastore)
+                .create_if_missing(true)
+                .open()
+                .unwrap();
+            persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        }
+
+        let persister: Persister<String> = PersisterBuilder::new()
+            .path(&datastore)
+            .create_if_missing(false)
+            .open()
+            .unwrap();
+        assert_eq!(vec![b'a'], persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_builder_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+
+        let result = PersisterBuilder::new().path(&datastore).read_only(true).open::<String>();
+        assert_eq!(KVError::DatastoreDoesNotExist, result.err().unwrap());
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).open().unwrap();
+            persister.insert_kv(&"key1".to_string(), b"ab").unwrap();
+            persister.insert_kv_with_ttl(&"key2".to_string(), b"c", Duration::from_secs(60)).unwrap();
+        }
+
+        let result = PersisterBuilder::new().path(&datastore).read_only(true).truncate(true).open::<String>();
+        assert!(matches!(result, Err(KVError::InvalidArgument(_))));
+
+        let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).read_only(true).open().unwrap();
+        let key = "key1".to_string();
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&key).unwrap());
+        assert_eq!(2, persister.len());
+
+        assert_eq!(KVError::ReadOnly, persister.insert_kv(&"key3".to_string(), b"d").unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.insert_kv_with_ttl(&"key3".to_string(), b"d", Duration::from_secs(1)).unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.insert_from_reader(&"key3".to_string(), &b"d"[..], 1).unwrap_err());
+        assert!(matches!(persister.reserve(&"key3".to_string(), 1), Err(KVError::ReadOnly)));
+        assert_eq!(KVError::ReadOnly, persister.update_value(&key, b"d").unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.update_value_with_ttl(&key, b"d", Duration::from_secs(1)).unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.put(&key, b"d").unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.put_with_ttl(&key, b"d", Duration::from_secs(1)).unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.delete_kv(&key).unwrap_err());
+        let mut batch = WriteBatch::new();
+        batch.put("key3".to_string(), vec![b'd']);
+        assert_eq!(KVError::ReadOnly, persister.write_batch(batch).unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.bulk_load(vec![("key3".to_string(), vec![b'd'])]).unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.purge_expired().unwrap_err());
+        assert_eq!(KVError::ReadOnly, persister.purge_quarantined().unwrap_err());
+        let mut txn = persister.begin();
+        txn.put("key3".to_string(), vec![b'd']);
+        assert_eq!(KVError::ReadOnly, txn.commit().unwrap_err());
+        persister.flush().unwrap();
+
+        // the handles have no write access either
+        assert!(persister.header.db_file.write_all_at(b"x", 0).is_err());
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&key).unwrap());
+        assert_eq!(vec![b'c'], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(2, persister.len());
+    }
+
+    // runs a test against every backend: files handed to the store by hand, a temporary
+    // store, a store on disk and, with the mmap feature, a store reading from a map of the db
+    // file
+    fn for_each_backend(test: impl Fn(Persister<String>)) {
+        test(new_mock_persister());
+        test(Persister::new_temporary().unwrap());
+        let dir = tempfile::tempdir().unwrap();
+        test(Persister::new(dir.path().join("store").to_string_lossy().to_string(), 0).unwrap());
+
+        #[cfg(feature = "mmap")]
+        {
+            let mut persister = Persister::new(dir.path().join("mapped").to_string_lossy().to_string(), 0).unwrap();
+            persister.set_mmap_reads(true).unwrap();
+            test(persister);
+        }
+    }
+
+    #[test]
+    fn test_backends_behave_alike() {
+        for_each_backend(|mut persister| {
+            for i in 0..20 {
+                persister.insert_kv(&format!("key{:02}", i), &vec![i as u8; i % 7]).unwrap();
+            }
+            persister.update_value(&"key03".to_string(), &[b'g'; 12]).unwrap();
+            persister.update_value(&"key06".to_string(), b"s").unwrap();
+            persister.delete_kv(&"key05".to_string()).unwrap();
+            persister.delete_kv(&"key19".to_string()).unwrap();
+
+            let mut batch = WriteBatch::new();
+            batch.put("key20".to_string(), vec![b'b'; 3]);
+            batch.delete("key00".to_string());
+            persister.write_batch(batch).unwrap();
+            persister.put(&"key01".to_string(), &[b'p'; 9]).unwrap();
+
+            let expected: Vec<(String, Vec<u8>)> = (1..21)
+                .filter(|i| *i != 5 && *i != 19)
+                .map(|i| {
+                    let value = match i {
+                        1 => vec![b'p'; 9],
+                        3 => vec![b'g'; 12],
+                        6 => vec![b's'],
+                        20 => vec![b'b'; 3],
+                        _ => vec![i as u8; i % 7],
+                    };
+                    (format!("key{:02}", i), value)
+                })
+                .collect();
+            assert_eq!(expected, persister.iter().collect::<Result<Vec<_>, _>>().unwrap());
+            assert!(persister.verify_integrity_strict().unwrap().is_consistent());
+
+            persister.compact_datastore().unwrap();
+            assert_eq!(expected, persister.iter().collect::<Result<Vec<_>, _>>().unwrap());
+            assert_packed_layout(&persister);
+        });
+    }
+
+    #[test]
+    fn test_builder_temporary() {
+        let result = PersisterBuilder::new().temporary().read_only(true).open::<String>();
+        assert!(matches!(result, Err(KVError::InvalidArgument(_))));
+
+        // the path is ignored, nothing is created there
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister: Persister<String> = PersisterBuilder::new().path(dir.path().join("store")).temporary().open().unwrap();
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+
+        for i in 0..10 {
+            persister.insert_kv(&format!("key{}", i), &vec![i as u8; i]).unwrap();
+        }
+        persister.update_value(&"key3".to_string(), &[b'x'; 20]).unwrap();
+        persister.delete_kv(&"key5".to_string()).unwrap();
+        persister.flush().unwrap();
+        persister.compact_datastore().unwrap();
+
+        assert_eq!(vec![b'x'; 20], persister.get_value(&"key3".to_string()).unwrap());
+        assert_eq!(vec![9; 9], persister.get_value(&"key9".to_string()).unwrap());
+        assert_eq!(9, persister.len());
+        assert!(persister.verify_integrity_strict().unwrap().is_consistent());
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_builder_read_only_with_pending_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+        {
+            let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).open().unwrap();
+            persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+            // a crash right after logging the write, before it reaches the index file
+            let slot = Slot { cursor: 1, space: 1 };
+            persister.log_put(&"key2".to_string(), &slot, None, Some(b"b")).unwrap();
+            persister.header.wal_file = None;
+        }
+
+        let result = PersisterBuilder::new().path(&datastore).read_only(true).open::<String>();
+        assert_eq!(KVError::ReadOnly, result.err().unwrap());
+
+        // opening it writable once recovers the write
+        drop(PersisterBuilder::new().path(&datastore).open::<String>().unwrap());
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).read_only(true).open().unwrap();
+        assert_eq!(vec![b'b'], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_storage_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+        let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).storage_limit(8).open().unwrap();
+
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 2]).unwrap();
+        assert_eq!(
+            KVError::StorageLimitExceeded { limit: 8, needed: 9 },
+            persister.insert_kv(&"key3".to_string(), &[b'c'; 3]).unwrap_err(),
+        );
+        assert_eq!(6, persister.last_cursor);
+        assert_eq!(
+            KVError::StorageLimitExceeded { limit: 8, needed: 11 },
+            persister.update_value(&"key1".to_string(), &[b'a'; 5]).unwrap_err(),
+        );
+        assert_eq!(vec![b'a'; 4], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(FreeList::new(), persister.freelist);
+
+        let mut batch = WriteBatch::new();
+        batch.put("key3".to_string(), vec![b'c']).put("key4".to_string(), vec![b'd'; 2]);
+        assert_eq!(KVError::StorageLimitExceeded { limit: 8, needed: 9 }, persister.write_batch(batch).unwrap_err());
+        assert!(matches!(persister.reserve(&"key3".to_string(), 3), Err(KVError::StorageLimitExceeded { .. })));
+        assert!(matches!(persister.insert_from_reader(&"key3".to_string(), &b"ccc"[..], 3), Err(KVError::StorageLimitExceeded { .. })));
+        assert!(matches!(persister.bulk_load(vec![("key3".to_string(), vec![b'c'; 3])]), Err(KVError::StorageLimitExceeded { .. })));
+        assert_eq!(6, persister.last_cursor);
+
+        // up to the limit and into freed space is fine
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 2]).unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        persister.update_value(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(vec![b'c'; 2], persister.get_value(&"key3".to_string()).unwrap());
+        assert_eq!(8, persister.last_cursor);
     }
 
-    pub fn insert_kv<'a>(&mut self, key: &K, value: &Vec<u8>) -> Result<(), KVError>
-    where K: Serialize + Deserialize<'a> {
-        let mut cursor: usize = 0;
+    #[test]
+    fn test_delete_range() {
+        let mut persister = new_mock_persister();
+        for i in 0..10 {
+            persister.insert_kv(&format!("key_{}", i), &[i as u8; 2]).unwrap();
+        }
 
-        if self.index.contains_key(&key) {
-            return Err(KVError::KeyAlreadyExist)
+        // a middle band leaves a single hole behind
+        assert_eq!(4, persister.delete_range("key_3".to_string()..="key_6".to_string()).unwrap());
+        assert_eq!(6, persister.len());
+        for i in [0, 1, 2, 7, 8, 9] {
+            assert_eq!(vec![i as u8; 2], persister.get_value(&format!("key_{}", i)).unwrap());
+        }
+        for i in 3..=6 {
+            assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&format!("key_{}", i)).unwrap_err());
         }
+        assert_eq!(1, persister.freelist.fragment_count());
+        assert_eq!(8, persister.freelist.total_free_space());
+        assert_eq!(20, persister.last_cursor);
 
-        if value.len() > 0 {
-            // try to retrieve free space, otherwise, add in the last cursor
-            match self.freelist.retrieve_free_space(value.len()) {
-                Some(empty_space_cursor) => cursor = empty_space_cursor,
-                None => {
-                    cursor = self.last_cursor;
-                    self.last_cursor = self.last_cursor + value.len();
-                }
-            }
+        // the freed space is reused before growing the file
+        persister.insert_kv(&"key_a".to_string(), &[b'a'; 5]).unwrap();
+        persister.insert_kv(&"key_b".to_string(), &[b'b'; 3]).unwrap();
+        assert_eq!(Slot { cursor: 6, space: 5 }, persister.index[&"key_a".to_string()]);
+        assert_eq!(Slot { cursor: 11, space: 3 }, persister.index[&"key_b".to_string()]);
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_eq!(20, persister.last_cursor);
+        assert_eq!(vec![b'b'; 3], persister.get_value(&"key_b".to_string()).unwrap());
+    }
 
-            if let Err(error) = self.persist_value(&value, cursor) {
-                // make sure to free the memory to prevent leaks
-                if cursor == self.last_cursor - value.len() {
-                    self.last_cursor = cursor - value.len()
-                }
-                return Err(error)
-            }
+    #[test]
+    fn test_delete_range_tail() {
+        let mut persister = new_mock_persister();
+        for i in 0..10 {
+            persister.insert_kv(&format!("key_{}", i), &[i as u8; 2]).unwrap();
         }
+        persister.insert_kv(&"key_empty".to_string(), &[]).unwrap();
 
-        // todo(): serialize and store the key in file
-        if let Err(_) = self.persist_key() {
+        assert_eq!(4, persister.delete_range("key_7".to_string()..).unwrap());
+        assert_eq!(14, persister.last_cursor);
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_eq!(vec![6; 2], persister.get_value(&"key_6".to_string()).unwrap());
 
-        }
+        persister.insert_kv(&"key_7".to_string(), &[b'x'; 3]).unwrap();
+        assert_eq!(Slot { cursor: 14, space: 3 }, persister.index[&"key_7".to_string()]);
+    }
 
-        // insert key in index
-        if self.index.insert(key.clone(), Slot {cursor, space: value.len()}).is_none() {
-            // todo(): return error and undo things (insert the slot as free space)
-        }
+    #[test]
+    fn test_delete_range_matching_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.insert_kv(&"key_1".to_string(), b"a").unwrap();
+        persister.insert_kv(&"key_2".to_string(), b"b").unwrap();
+        let index_len = persister.header.index_file.metadata().unwrap().len();
 
-        return Ok(());
+        assert_eq!(0, persister.delete_range("key_3".to_string()..).unwrap());
+        assert_eq!(0, persister.delete_range("key_2".to_string().."key_2".to_string()).unwrap());
+        assert_eq!(0, persister.delete_range("key_2".to_string().."key_1".to_string()).unwrap());
+        assert_eq!(index_len, persister.header.index_file.metadata().unwrap().len());
+        assert_eq!(2, persister.len());
+
+        // the removed keys stay removed once the store is reopened
+        assert_eq!(1, persister.delete_range(.."key_2".to_string()).unwrap());
+        drop(persister);
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![&"key_2".to_string()], persister.keys().collect::<Vec<_>>());
     }
 
-    pub fn get_value(&mut self, key: &K) -> Result<Vec<u8>, KVError> {
-        match self.index.get(key) {
-            Some(val) => {
-                return self.retrieve_value(val.cursor, val.space);
-            },
-            None => {
-                return Err(KVError::KeyDoesNotExist);
-            }
+    #[test]
+    fn test_modify_value_counter() {
+        let mut persister = new_mock_persister();
+        let key = "counter".to_string();
+        let increment = |value: &[u8]| (u64::from_le_bytes(value.try_into().unwrap()) + 1).to_le_bytes().to_vec();
+
+        assert_eq!(KVError::KeyDoesNotExist, persister.modify_value(&key, |_| unreachable!()).unwrap_err());
+        for _ in 0..1000 {
+            persister.modify_or_insert(&key, 1u64.to_le_bytes().to_vec(), increment).unwrap();
         }
+        assert_eq!(Ok(PutOutcome::Updated), persister.modify_or_insert(&key, vec![], increment));
+        persister.modify_value(&key, increment).unwrap();
+
+        // each update is written next to the previous value, the counter goes back and forth
+        // between two slots
+        assert_eq!(1002u64.to_le_bytes().to_vec(), persister.get_value(&key).unwrap());
+        assert_eq!(Slot { cursor: 8, space: 8 }, persister.index[&key]);
+        assert_eq!(16, persister.last_cursor);
+        assert_eq!(8, persister.freelist.total_free_space());
+
+        persister.modify_value(&key, increment).unwrap();
+        assert_eq!(Slot { cursor: 0, space: 8 }, persister.index[&key]);
+        assert_eq!(8, persister.last_cursor);
+        assert_eq!(0, persister.freelist.total_free_space());
     }
 
-    pub fn update_value(&mut self, key: &K, value: &Vec<u8>) -> Result<(), KVError> {
-        let mut slot;
+    #[test]
+    fn test_modify_value_grow_and_shrink() {
+        let mut persister = new_mock_persister();
+        let key = "key1".to_string();
+        persister.insert_kv(&key, &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
 
-        match self.index.get(key) {
-            Some(val) => {
-                slot = val.clone();
-            },
-            None => return Err(KVError::KeyDoesNotExist),
-        }
+        // past its slot the value moves to the end of the data and frees the slot
+        persister.modify_value(&key, |value| [value, b"cd"].concat()).unwrap();
+        assert_eq!(b"aaaacd".to_vec(), persister.get_value(&key).unwrap());
+        assert_eq!(Slot { cursor: 8, space: 6 }, persister.index[&key]);
+        assert_eq!((None, Some(&Slot { cursor: 0, space: 4 })), persister.freelist.neighbors_of(0, 0));
+        assert_eq!(4, persister.freelist.total_free_space());
+        assert_eq!(14, persister.last_cursor);
 
-        // free previous data and claim more space
-        if value.len() > slot.space {
-            self.freelist.insert_free_space(slot.cursor, slot.space);
-            if slot.cursor + slot.space == self.last_cursor {
-                self.last_cursor = slot.cursor;
-            }
+        // shrunk it moves into the free slot and gives its slot back to the end of the data
+        persister.modify_value(&key, |value| value[4..].to_vec()).unwrap();
+        assert_eq!(b"cd".to_vec(), persister.get_value(&key).unwrap());
+        assert_eq!(Slot { cursor: 0, space: 2 }, persister.index[&key]);
+        assert_eq!((None, Some(&Slot { cursor: 2, space: 2 })), persister.freelist.neighbors_of(0, 2));
+        assert_eq!(2, persister.freelist.total_free_space());
+        assert_eq!(8, persister.last_cursor);
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
 
-            match self.freelist.retrieve_free_space(value.len()) {
-                Some(val) => {
-                    if val >= self.last_cursor {
-                        self.last_cursor = val+value.len();
-                    }
+        // the free slot takes a value that fits in it
+        persister.modify_or_insert(&"key3".to_string(), vec![b'e'; 2], |_| unreachable!()).unwrap();
+        assert_eq!(Slot { cursor: 2, space: 2 }, persister.index[&"key3".to_string()]);
+    }
 
-                    slot.cursor = val;
-                },
-                None => {
-                    slot.cursor = self.last_cursor;
-                    self.last_cursor = self.last_cursor + value.len();
-                },
-            }
-        }
+    #[test]
+    fn test_modify_value_keeps_ttl() {
+        let mut persister = new_mock_persister();
+        let clock = ManualClock::new(1_000);
+        persister.set_clock(Box::new(clock.clone()));
+        let key = "key".to_string();
+
+        persister.insert_kv_with_ttl(&key, &[1], Duration::from_millis(100)).unwrap();
+        persister.modify_value(&key, |value| vec![value[0] + 1]).unwrap();
+        assert_eq!(vec![2], persister.get_value(&key).unwrap());
+
+        clock.set(1_100);
+        assert_eq!(KVError::KeyDoesNotExist, persister.modify_value(&key, |_| unreachable!()).unwrap_err());
+        assert_eq!(Ok(PutOutcome::Inserted), persister.modify_or_insert(&key, vec![7], |_| unreachable!()));
+        assert_eq!(vec![7], persister.get_value(&key).unwrap());
+    }
 
-        // downsize the leftover space if the space is smaller
-        if value.len() < slot.space {
-            self.freelist.insert_free_space(slot.cursor+value.len(), slot.space - value.len());
+    #[test]
+    fn test_format_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let index_path = dir.path().join("index_store");
+
+        {
+            let mut persister: Persister<String> = Persister::create_new(datastore.clone(), 0).unwrap();
+            persister.insert_kv(&"key1".to_string(), b"ab").unwrap();
+            assert_eq!(Slot { cursor: 0, space: 2 }, persister.index[&"key1".to_string()]);
+            assert_eq!(2, persister.stats().unwrap().file_len);
         }
 
-        // update slot space required
-        slot.space = value.len();
+        let db = std::fs::read(&datastore).unwrap();
+        assert_eq!(b"EKV1", &db[0..4]);
+        assert_eq!(b"ab", &db[FORMAT_HEADER_LEN as usize..]);
+        assert_eq!(b"EKV1", &std::fs::read(&index_path).unwrap()[0..4]);
 
-        // persist the value
-        let _ = self.persist_value(value, slot.cursor);
+        let persister: Persister<String> = Persister::open_existing(datastore.clone(), 0).unwrap();
+        assert_eq!(vec![b'a', b'b'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(2, persister.last_cursor);
+        drop(persister);
 
-        // todo(): serialize the new key data
-        if let Err(_) = self.persist_key() {
+        // the files can't be swapped
+        let swapped = dir.path().join("swapped").to_string_lossy().to_string();
+        std::fs::copy(&index_path, &swapped).unwrap();
+        std::fs::copy(&datastore, dir.path().join("index_swapped")).unwrap();
+        assert!(matches!(Persister::<String>::open_existing(swapped, 0), Err(KVError::NotADatastore { .. })));
+    }
 
-        }
+    #[test]
+    fn test_format_header_rejects_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        drop(Persister::<String>::new(datastore.clone(), 0).unwrap());
+        let db_file = OpenOptions::new().write(true).open(&datastore).unwrap();
 
-        // update the index
-        self.index.insert(key.clone(), Slot{cursor: slot.cursor, space: slot.space});
+        db_file.write_all_at(b"XKV1", 0).unwrap();
+        assert!(matches!(Persister::<String>::open_existing(datastore.clone(), 0), Err(KVError::NotADatastore { .. })));
+        assert!(matches!(Persister::<String>::new(datastore.clone(), 0), Err(KVError::NotADatastore { .. })));
+
+        // a store written by a later version of the format
+        db_file.write_all_at(b"EKV1", 0).unwrap();
+        db_file.write_all_at(&(FORMAT_VERSION + 1).to_le_bytes(), 4).unwrap();
+        assert_eq!(
+            KVError::UnsupportedVersion { found: FORMAT_VERSION + 1, supported: FORMAT_VERSION },
+            Persister::<String>::open_existing(datastore.clone(), 0).err().unwrap(),
+        );
+
+        // a store created with compression by a build that has it
+        db_file.write_all_at(&FORMAT_VERSION.to_le_bytes(), 4).unwrap();
+        db_file.write_all_at(&[1], 8).unwrap();
+        assert_eq!(
+            KVError::InvalidFormat("db file was created with compression lz4, opened with none".to_string()),
+            Persister::<String>::open_existing(datastore.clone(), 0).err().unwrap(),
+        );
+
+        // a file too short to hold a header, like the files of stores predating it
+        db_file.set_len(3).unwrap();
+        assert!(matches!(Persister::<String>::open_existing(datastore.clone(), 0), Err(KVError::NotADatastore { .. })));
 
-        return Ok(())
+        // an empty file holds nothing to misread and gets a header when the store could be
+        // created, otherwise it isn't a datastore
+        db_file.set_len(0).unwrap();
+        let result = PersisterBuilder::new().path(&datastore).read_only(true).open::<String>();
+        assert!(matches!(result, Err(KVError::NotADatastore { .. })));
+        assert!(matches!(Persister::<String>::open_existing(datastore.clone(), 0), Err(KVError::NotADatastore { .. })));
+        let mut persister: Persister<String> = Persister::new(datastore, 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), b"a").unwrap();
+        assert_eq!(vec![b'a'], persister.get_value(&"key1".to_string()).unwrap());
     }
 
-    pub fn delete_kv(&mut self, key: &K) -> Result<(), KVError> {
-        // check if key exists and insert freed space
-        match self.index.get(key) {
-            Some(val) => {
-                // update the last cursor position
-                if self.last_cursor == val.cursor + val.space {
-                    self.last_cursor = val.cursor;
-                }
+    #[test]
+    fn test_adopt_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("legacy");
+        let index_path = dir.path().join("index_legacy");
 
-                self.freelist.insert_free_space(val.cursor, val.space)
-            },
-            None => return Err(KVError::KeyDoesNotExist),
+        // values packed from the first byte of the db file and an empty index file, with the
+        // slots of the keys known by the process that wrote them
+        let values: Vec<(String, Vec<u8>)> = (0..10).map(|i| (format!("key_{}", i), vec![i as u8; i])).collect();
+        let mut data = vec![];
+        let mut hints = vec![];
+        for (key, value) in values.iter() {
+            hints.push((key.clone(), data.len()..data.len() + value.len()));
+            data.extend_from_slice(value);
         }
+        data.extend_from_slice(&[0xee; 7]); // bytes of deleted values
+        std::fs::write(&db_path, &data).unwrap();
+        std::fs::write(&index_path, b"").unwrap();
 
-        // todo(): remove serialized key from file
-        // insert key space into file
-        let _ = self.delete_key();
+        let adopted: Persister<String> = Persister::adopt_legacy(&db_path, &index_path, &hints).unwrap();
+        assert_eq!(values, adopted.iter().map(|item| item.unwrap()).collect::<Vec<_>>());
+        assert!(adopted.verify_integrity_strict().unwrap().is_consistent());
+        assert!(adopted.creation_info().is_some());
+        drop(adopted);
 
-        // remove key from index
-        match self.index.remove(key) {
-            Some(_) => Ok(()),
-            None => Err(KVError::KeyDoesNotExist), // should never happen
-        }
+        // the originals are untouched and the adopted store opens like any other
+        assert_eq!(data, std::fs::read(&db_path).unwrap());
+        assert_eq!(Vec::<u8>::new(), std::fs::read(&index_path).unwrap());
+        let adopted_path = dir.path().join("legacy.adopted").to_string_lossy().to_string();
+        let adopted: Persister<String> = Persister::open_existing(adopted_path, 0).unwrap();
+        assert_eq!(values.len(), adopted.len());
+        assert!(!dir.path().join("legacy.adopting").exists());
+        assert!(!dir.path().join("index_legacy.adopting").exists());
+
+        assert_eq!(Some(KVError::DatastoreAlreadyExists), Persister::adopt_legacy(&db_path, &index_path, &hints).err());
     }
 
-    fn persist_value(&mut self, data: &Vec<u8>, cursor: usize) -> Result<(), KVError> {
-        self.header.db_file.seek(SeekFrom::Start(cursor as u64))
-            .map_err(|io_error| KVError::IOError(io_error.to_string()))?;
-        self.header.db_file.write_all(data.as_ref())
-            .map_err(|io_error| KVError::IOError(io_error.to_string()))?;
+    #[test]
+    fn test_adopt_legacy_index_records_and_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("legacy");
+        let index_path = dir.path().join("index_legacy");
+
+        // a legacy index file holding records without a header, the hints win over them
+        std::fs::write(&db_path, b"abcdef").unwrap();
+        let mut index = vec![];
+        index.extend(IndexRecord::Put { key: encode_key(&"a".to_string()).unwrap(), slot: Slot { cursor: 0, space: 2 }, expires_at: None }.encode());
+        index.extend(IndexRecord::Put { key: encode_key(&"b".to_string()).unwrap(), slot: Slot { cursor: 2, space: 2 }, expires_at: None }.encode());
+        index.extend(IndexRecord::Delete { key: encode_key(&"a".to_string()).unwrap() }.encode());
+        index.extend(IndexRecord::Put { key: encode_key(&"c".to_string()).unwrap(), slot: Slot { cursor: 0, space: 1 }, expires_at: None }.encode());
+        index.extend(&[1, 0]); // torn record
+        std::fs::write(&index_path, &index).unwrap();
 
-        Ok(())
+        let adopted: Persister<String> = Persister::adopt_legacy(&db_path, &index_path, &[("c".to_string(), 4..6)]).unwrap();
+        assert_eq!(vec![
+            ("b".to_string(), b"cd".to_vec()),
+            ("c".to_string(), b"ef".to_vec()),
+        ], adopted.iter().map(|item| item.unwrap()).collect::<Vec<_>>());
+        assert_eq!(index, std::fs::read(&index_path).unwrap());
+
+        // a value past the end of the db file writes nothing
+        let other_db = dir.path().join("other");
+        let other_index = dir.path().join("index_other");
+        std::fs::write(&other_db, b"abc").unwrap();
+        std::fs::write(&other_index, b"").unwrap();
+        assert_eq!(
+            Some(KVError::SlotBeyondEof { cursor: 2, len: 4, file_len: 3 }),
+            Persister::<String>::adopt_legacy(&other_db, &other_index, &[("x".to_string(), 2..6)]).err()
+        );
+        assert!(!dir.path().join("other.adopted").exists());
+        assert!(!dir.path().join("other.adopting").exists());
+
+        // stores in the current format and missing files
+        let current = dir.path().join("current");
+        drop(Persister::<String>::new(current.to_string_lossy().to_string(), 0).unwrap());
+        let result = Persister::<String>::adopt_legacy(&current, &dir.path().join("index_current"), &[]);
+        assert!(matches!(result, Err(KVError::InvalidArgument(_))));
+        let result = Persister::<String>::adopt_legacy(&dir.path().join("missing"), &index_path, &[]);
+        assert_eq!(Some(KVError::DatastoreDoesNotExist), result.err());
     }
 
-    fn retrieve_value(&mut self, cursor: usize, space: usize) -> Result<Vec<u8>, KVError> {
-        // todo(buffer): use a fixed buffer instead of a vec
-        let mut buffer = vec![0; space];
+    #[test]
+    fn test_creation_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let before = SystemClock.now_millis();
+        let mut persister: Persister<String> = PersisterBuilder::new().path(&datastore).label("orders").open().unwrap();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        let info = persister.creation_info().unwrap().clone();
+        assert_eq!((env!("CARGO_PKG_VERSION"), "none", Some("orders")), (info.crate_version.as_str(), info.compression.as_str(), info.label.as_deref()));
+        assert!(info.created_at >= before);
+        drop(persister);
+
+        // read back from the index file, the label of an existing store doesn't change
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).label("other").open().unwrap();
+        assert_eq!(Some(&info), persister.creation_info());
+        drop(persister);
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).read_only(true).open().unwrap();
+        assert_eq!(Some(&info), persister.creation_info());
+        drop(persister);
 
-        // todo: handle the error and returns
-        let _ = self.header.db_file.seek(SeekFrom::Start(cursor as u64));
-        let _ = self.header.db_file.read_exact_at(&mut buffer.as_mut_slice(), cursor as u64)
-            .map_err(|io_error| KVError::IOError(io_error.to_string()))?;
+        // fields written by a later release are kept when a snapshot writes the info again
+        let mut fields: BTreeMap<String, String> = bincode::deserialize(&info.encode()).unwrap();
+        fields.insert("checksum".to_string(), "crc32c".to_string());
+        let block = bincode::serialize(&fields).unwrap();
+        let index_path = dir.path().join("index_store");
+        let mut index = std::fs::read(&index_path).unwrap();
+        index.extend(IndexRecord::Config { block: block.clone() }.encode());
+        std::fs::write(&index_path, &index).unwrap();
 
-        return Ok(buffer.to_vec())
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        let snapshot_path = dir.path().join("snapshot");
+        persister.snapshot_to(&snapshot_path).unwrap();
+        let snapshot: Persister<String> = Persister::open_snapshot(&snapshot_path).unwrap();
+        assert_eq!(persister.creation_info(), snapshot.creation_info());
+        assert_eq!(block, snapshot.creation_info().unwrap().encode());
+        assert_eq!(b"abc".to_vec(), snapshot.get_value(&"key1".to_string()).unwrap());
+
+        // a store built without a datastore has none, its snapshots get their own
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), b"abc").unwrap();
+        assert_eq!(None, persister.creation_info());
+        let snapshot_path = dir.path().join("from_mock");
+        persister.snapshot_to(&snapshot_path).unwrap();
+        let snapshot: Persister<String> = Persister::open_snapshot(&snapshot_path).unwrap();
+        assert_eq!((None, "none"), (snapshot.creation_info().unwrap().label.as_deref(), snapshot.creation_info().unwrap().compression.as_str()));
     }
 
-    fn persist_key(&mut self) -> Result<(), KVError> {
-        return Ok(());
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_creation_info_compression_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).compression(Compression::Lz4).open().unwrap();
+        assert_eq!("lz4", persister.creation_info().unwrap().compression);
+        drop(persister);
+
+        // the error quotes the compression the store was created with and the one requested
+        let error = Persister::<String>::open_existing(datastore.clone(), 0).err().unwrap();
+        assert_eq!("invalid format: db file was created with compression lz4, opened with none", error.to_string());
+        let persister: Persister<String> = PersisterBuilder::new().path(&datastore).compression(Compression::Zstd { level: 0 }).open().unwrap();
+        assert_eq!("lz4", persister.creation_info().unwrap().compression);
     }
 
-    fn delete_key(&mut self) -> Result<(), KVError> {
-        return Ok(());
+    #[test]
+    fn test_open_classifies_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+        let open = || Persister::<String>::open_existing(datastore.to_string_lossy().to_string(), 0);
+        let not_a_datastore = |detail: &str| KVError::NotADatastore { path: datastore.clone(), detail: detail.to_string() };
+        let corrupt = |phase, detail: &str| KVError::CorruptDatastore { path: datastore.clone(), phase, detail: detail.to_string() };
+
+        // a random binary file, nothing is created next to it
+        let mut state = 0x2545f491u32;
+        let random: Vec<u8> = (0..4096).map(|_| { state ^= state << 13; state ^= state >> 17; state ^= state << 5; state as u8 }).collect();
+        std::fs::write(&datastore, &random).unwrap();
+        assert_eq!(Some(not_a_datastore("db file has no datastore header")), open().err());
+        assert_eq!(Some(not_a_datastore("db file has no datastore header")), Persister::<String>::new(datastore.to_string_lossy().to_string(), 0).err());
+        assert!(!dir.path().join("index_store").exists());
+
+        // an empty file and a directory
+        std::fs::write(&datastore, b"").unwrap();
+        assert_eq!(Some(not_a_datastore("db file is empty")), open().err());
+        std::fs::remove_file(&datastore).unwrap();
+        std::fs::create_dir(&datastore).unwrap();
+        let error = open().err().unwrap();
+        assert!(matches!(&error, KVError::NotADatastore { path, detail } if *path == datastore && detail.contains("is a directory")), "{}", error);
+        let result = PersisterBuilder::new().path(&datastore).read_only(true).open::<String>();
+        assert!(matches!(result, Err(KVError::NotADatastore { .. })));
+        std::fs::remove_dir(&datastore).unwrap();
+
+        // files of a datastore cut inside the header, and with records that can't be decoded
+        let mut persister: Persister<String> = Persister::new(datastore.to_string_lossy().to_string(), 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), b"value").unwrap();
+        drop(persister);
+        let db = std::fs::read(&datastore).unwrap();
+        std::fs::write(&datastore, &db[..10]).unwrap();
+        assert_eq!(Some(corrupt(OpenPhase::Header, "db file is too short for a datastore header")), open().err());
+        std::fs::write(&datastore, &db).unwrap();
+
+        let index_path = dir.path().join("index_store");
+        let index = std::fs::read(&index_path).unwrap();
+        let mut damaged = index.clone();
+        damaged[FORMAT_HEADER_LEN as usize] = 0xff;
+        std::fs::write(&index_path, &damaged).unwrap();
+        assert!(matches!(open(), Err(KVError::CorruptDatastore { phase: OpenPhase::Index, .. })));
+        std::fs::write(&index_path, &index).unwrap();
+        assert_eq!(b"value".to_vec(), open().unwrap().get_value(&"key1".to_string()).unwrap());
+
+        // a later version of the format
+        let mut future = db.clone();
+        future[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&datastore, &future).unwrap();
+        assert_eq!(Some(KVError::UnsupportedVersion { found: FORMAT_VERSION + 1, supported: FORMAT_VERSION }), open().err());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::string::String;
-    use std::fs::OpenOptions;
-    use super::*;
+    #[test]
+    fn test_compact_datastore() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        for i in 0..40usize {
+            persister.insert_kv(&format!("key_{:02}", i), &vec![i as u8; i % 7]).unwrap();
+        }
+        for i in (0..40).step_by(3) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        for i in (1..40).step_by(5).filter(|i| i % 3 != 0) {
+            persister.update_value(&format!("key_{:02}", i), &[b'x'; 9]).unwrap();
+        }
+        persister.delete_kv(&"key_38".to_string()).unwrap();
+        let expected: Vec<(String, Vec<u8>)> = persister.iter().map(|item| item.unwrap()).collect();
+        let live_bytes: usize = expected.iter().map(|(_, value)| value.len()).sum();
+        let file_len = persister.stats().unwrap().file_len;
+        assert!(persister.freelist.fragment_count() > 5);
 
-    fn new_mock_persister() -> Persister<String> {
-        Persister {
-            freelist: FreeList::new(),
-            header: FileHeader {
-                db_file: tempfile::tempfile().unwrap(),
-                index_file: tempfile::tempfile().unwrap(),
-            },
-            index: BTreeMap::new(),
-            last_cursor: 0,
+        let report = persister.compact_datastore().unwrap();
+        assert_eq!(file_len - live_bytes as u64, report.bytes_reclaimed);
+        assert!(report.values_moved > 0);
+        assert_eq!(live_bytes, persister.last_cursor);
+        assert_eq!(live_bytes as u64, persister.stats().unwrap().file_len);
+        assert_eq!(FORMAT_HEADER_LEN + live_bytes as u64, std::fs::metadata(&datastore).unwrap().len());
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_eq!(expected, persister.iter().map(|item| item.unwrap()).collect::<Vec<_>>());
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"key_07".to_string()).unwrap());
+
+        // nothing left to move
+        assert_eq!(CompactionReport::default(), persister.compact_datastore().unwrap());
+
+        // new values append at the end of the packed data
+        persister.insert_kv(&"new".to_string(), &[b'n'; 4]).unwrap();
+        assert_eq!(Slot { cursor: live_bytes, space: 4 }, persister.index[&"new".to_string()]);
+        drop(persister);
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        for (key, value) in expected.iter() {
+            assert_eq!(*value, persister.get_value(key).unwrap());
         }
+        assert_eq!(vec![b'n'; 4], persister.get_value(&"new".to_string()).unwrap());
+        assert_eq!(live_bytes + 4, persister.last_cursor);
     }
 
     #[test]
-    fn test_insert_kv_empty_values() {
+    fn test_compact_datastore_in_chunks() {
         let mut persister = new_mock_persister();
+        let big = BULK_LOAD_CHUNK_SIZE / 2 + 1;
+        persister.insert_kv(&"hole".to_string(), &[0; 10]).unwrap();
+        for i in 0..4u8 {
+            persister.insert_kv(&format!("key_{}", i), &vec![i; big]).unwrap();
+        }
+        persister.delete_kv(&"hole".to_string()).unwrap();
 
-        assert_eq!(Ok(()), persister.insert_kv(&"empty_value".to_string(), &vec![]));
-        assert_eq!(
-            Slot{cursor: 0, space: 0},
-            persister.index.get(&"empty_value".to_string()).unwrap().clone()
-        );
-        assert_eq!(0, persister.last_cursor);
+        let report = persister.compact_datastore().unwrap();
+        assert_eq!(CompactionReport { values_moved: 4, bytes_moved: 4 * big, bytes_reclaimed: 10 }, report);
+        for i in 0..4u8 {
+            assert_eq!(Slot { cursor: i as usize * big, space: big }, persister.index[&format!("key_{}", i)]);
+            assert_eq!(vec![i; big], persister.get_value(&format!("key_{}", i)).unwrap());
+        }
+        assert_eq!(4 * big as u64, persister.header.db_file.metadata().unwrap().len());
     }
 
     #[test]
-    fn test_insert_kv_two_times_same_key() {
+    fn test_compact_empty_datastore() {
         let mut persister = new_mock_persister();
+        assert_eq!(CompactionReport::default(), persister.compact_datastore().unwrap());
 
-        assert_eq!(Ok(()), persister.insert_kv(&"key_duplicated".to_string(), &vec![]));
-        assert_eq!(KVError::KeyAlreadyExist, persister.insert_kv(&"key_duplicated".to_string(), &vec![]).unwrap_err());
+        persister.insert_kv(&"empty".to_string(), &[]).unwrap();
+        persister.insert_kv(&"key".to_string(), &[b'a'; 3]).unwrap();
+        persister.delete_kv(&"key".to_string()).unwrap();
+        assert_eq!(CompactionReport { values_moved: 0, bytes_moved: 0, bytes_reclaimed: 3 }, persister.compact_datastore().unwrap());
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"empty".to_string()).unwrap());
         assert_eq!(0, persister.last_cursor);
     }
 
+    fn open_cached_persister(dir: &Path, max_bytes: usize) -> Persister<String> {
+        PersisterBuilder::new()
+            .path(dir.join("store"))
+            .cache(CacheConfig { max_bytes })
+            .open()
+            .unwrap()
+    }
+
     #[test]
-    fn test_insert_kv_multiple_kvs() {
+    fn test_cache_hits_skip_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_cached_persister(dir.path(), 1024);
+        persister.insert_kv(&"hot".to_string(), &[b'a'; 10]).unwrap();
+        persister.insert_kv(&"cold".to_string(), &[b'b'; 10]).unwrap();
+        assert_eq!(vec![b'a'; 10], persister.get_value(&"hot".to_string()).unwrap());
+
+        // the slots are gone from the file, only the cached value can still be read
+        persister.header.set_data_len(0).unwrap();
+        for _ in 0..3 {
+            assert_eq!(vec![b'a'; 10], persister.get_value(&"hot".to_string()).unwrap());
+        }
+        assert!(matches!(persister.get_value(&"cold".to_string()), Err(KVError::SlotBeyondEof { .. })));
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"missing".to_string()).unwrap_err());
+
+        let stats = persister.stats().unwrap();
+        assert_eq!((3, 2), (stats.cache_hits, stats.cache_misses));
+
+        // without a cache nothing is counted
         let mut persister = new_mock_persister();
-        let keys: Vec<String> = vec![
-            "key_1".to_string(),
-            "key_2".to_string(),
-            "key_3".to_string(),
-            "key_4".to_string(),
-            "key_5".to_string(),
-        ];
+        persister.insert_kv(&"key".to_string(), b"a").unwrap();
+        persister.get_value(&"key".to_string()).unwrap();
+        assert_eq!((0, 0), (persister.stats().unwrap().cache_hits, persister.stats().unwrap().cache_misses));
+    }
 
-        let values: Vec<Vec<u8>> = vec![
-            vec![b'a', b'b', b'c'],
-            vec![b'd', b'e', b'f', b'g'],
-            vec![b'h', b'i', b'j', b'k', b'l'],
-            vec![b'm', b'n', b'o', b'p'],
-            vec![b'q', b'r', b's', b't', b'u', b'v'],
-        ];
+    #[test]
+    fn test_cache_invalidated_by_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_cached_persister(dir.path(), 1024);
+        let read_all = |persister: &Persister<String>| {
+            for key in persister.keys() {
+                persister.get_value(key).unwrap();
+            }
+        };
+        for key in ["key1", "key2", "key3", "key4"] {
+            persister.insert_kv(&key.to_string(), &[b'a'; 4]).unwrap();
+        }
+        read_all(&persister);
 
-        let slots: Vec<Slot> = vec![
-            Slot { space: 3, cursor: 0 },
-            Slot { space: 4, cursor: 3 },
-            Slot { space: 5, cursor: 7 },
-            Slot { space: 4, cursor: 12 },
-            Slot { space: 6, cursor: 16 },
-        ];
+        // every update moves the value, whatever its size
+        persister.update_value(&"key1".to_string(), &[b'b'; 8]).unwrap();
+        persister.update_value(&"key2".to_string(), &[b'c'; 4]).unwrap();
+        persister.modify_value(&"key3".to_string(), |value| value[..2].to_vec()).unwrap();
+        assert_eq!(vec![b'b'; 8], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'c'; 4], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(vec![b'a'; 2], persister.get_value(&"key3".to_string()).unwrap());
 
-        // insert multiple non empty values and make sure that cursor is incremented
-        let mut expected_cursor = 0;
-        for kv in keys.iter().zip(values.iter()) {
-            assert_eq!(expected_cursor, persister.last_cursor);
-            persister.insert_kv(kv.0, kv.1).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("key1".to_string(), vec![b'd'; 3]);
+        batch.delete("key2".to_string());
+        persister.write_batch(batch).unwrap();
+        assert_eq!(vec![b'd'; 3], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key2".to_string()).unwrap_err());
 
-            expected_cursor += kv.1.len();
+        read_all(&persister);
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        persister.delete_range("key3".to_string()..).unwrap();
+        assert!(persister.is_empty());
+        for key in ["key1", "key3", "key4"] {
+            assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&key.to_string()).unwrap_err());
         }
 
-        // make sure that all keys can be retrieved with the corresponding slot
-        let mut iteration = 0;
-        for kv in keys.iter().zip(values.iter()) {
-            assert_eq!(
-                slots[iteration],
-                persister.index.get(kv.0).unwrap().clone()
-            );
+        // a key inserted again is read from the file
+        persister.insert_kv(&"key1".to_string(), &[b'e'; 5]).unwrap();
+        assert_eq!(vec![b'e'; 5], persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_cached_persister(dir.path(), 10);
+        for (key, byte) in [("a", b'a'), ("b", b'b'), ("c", b'c')] {
+            persister.insert_kv(&key.to_string(), &[byte; 4]).unwrap();
+        }
+        persister.insert_kv(&"big".to_string(), &[b'x'; 11]).unwrap();
 
-            iteration += 1;
+        // a is used again after b, so b makes room for c. The value bigger than the whole
+        // budget doesn't evict anything
+        for key in ["a", "b", "a", "c", "big"] {
+            persister.get_value(&key.to_string()).unwrap();
         }
 
-        // check that the resulting file is the same
-        persister.header.db_file.flush().unwrap();
-        assert_slots_eq(
-              open_file("tests/data/insert_kv-01.dat"),
-              persister.header.db_file,
-              &slots
-        )
+        persister.header.set_data_len(0).unwrap();
+        assert_eq!(vec![b'a'; 4], persister.get_value(&"a".to_string()).unwrap());
+        assert_eq!(vec![b'c'; 4], persister.get_value(&"c".to_string()).unwrap());
+        assert!(matches!(persister.get_value(&"b".to_string()), Err(KVError::SlotBeyondEof { .. })));
+        assert!(matches!(persister.get_value(&"big".to_string()), Err(KVError::SlotBeyondEof { .. })));
     }
 
     #[test]
-    fn test_insert_kv_check_free_spots() {
+    fn test_get_many_keeps_input_order() {
         let mut persister = new_mock_persister();
+        persister.set_clock(Box::new(ManualClock::new(1_000)));
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 3]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 2]).unwrap();
+        persister.insert_kv_with_ttl(&"expired".to_string(), b"d", Duration::ZERO).unwrap();
+
+        let keys: Vec<String> = ["key3", "missing", "key1", "key2", "key3", "expired"].iter().map(|key| key.to_string()).collect();
+        assert_eq!(vec![
+            Ok(vec![b'c'; 2]),
+            Err(KVError::KeyDoesNotExist),
+            Ok(vec![b'a'; 3]),
+            Ok(vec![]),
+            Ok(vec![b'c'; 2]),
+            Err(KVError::KeyDoesNotExist),
+        ], persister.get_many(&keys));
 
-        // create a free spot in the middle of two keys with size 2 and test whether we
-        // make use of the free space generated
-        let _ = persister.insert_kv(&"key_1".to_string(), &vec![b'a', b'b', b'c']);
-        let _ = persister.insert_kv(&"key_2".to_string(), &vec![b'd', b'e']);
-        let _ = persister.insert_kv(&"key_3".to_string(), &vec![b'f', b'g', b'h']);
+        assert!(persister.get_many(&Vec::<String>::new()).is_empty());
+        assert!(persister.get_many([]).is_empty());
+    }
+
+    #[test]
+    fn test_get_many_scattered_slots() {
+        let mut persister = new_mock_persister();
+        let value = |i: usize| (0..(i * 7) % 13 + 1).map(|byte| (byte * 31 + i) as u8).collect::<Vec<u8>>();
+        for i in 0..30 {
+            persister.insert_kv(&format!("key_{:02}", i), &value(i)).unwrap();
+        }
+        // moved slots and reused holes leave the cursors in no particular order of the keys
+        for i in (0..30).step_by(4) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        for i in (1..30).step_by(3).filter(|i| i % 4 != 0) {
+            persister.update_value(&format!("key_{:02}", i), &value(i + 100)).unwrap();
+        }
+        for i in (0..30).step_by(4) {
+            persister.insert_kv(&format!("key_{:02}", i), &value(i + 200)).unwrap();
+        }
 
-        // delete the middle kv
-        let _ = persister.delete_kv(&"key_2".to_string()).unwrap();
+        let mut keys: Vec<String> = persister.keys().cloned().collect();
+        keys.reverse();
+        keys.swap(3, 17);
+        let values = persister.get_many(&keys);
+        assert_eq!(keys.len(), values.len());
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(persister.get_value(key).unwrap(), value.unwrap());
+        }
+    }
 
-        let _ = persister.insert_kv(&"key_4".to_string(), &vec![b'i', b'j', b'k']);
-        assert_eq!(8, persister.index.get(&"key_4".to_string()).unwrap().cursor);
-        assert_eq!(3, persister.index.get(&"key_4".to_string()).unwrap().space);
+    #[test]
+    fn test_get_many_failed_read() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 4]).unwrap();
 
-        let _ = persister.insert_kv(&"key_5".to_string(), &vec![b'l']);
-        assert_eq!(3, persister.index.get(&"key_5".to_string()).unwrap().cursor);
-        assert_eq!(1, persister.index.get(&"key_5".to_string()).unwrap().space);
+        // the three slots can't be read at once, each key gets the result of its own read
+        persister.header.set_data_len(10).unwrap();
+        let keys = vec!["key3".to_string(), "key1".to_string(), "key2".to_string()];
+        let values = persister.get_many(&keys);
+        assert!(matches!(values[0], Err(KVError::SlotBeyondEof { cursor: 8, len: 4, file_len: 10 })));
+        assert_eq!(Ok(vec![b'a'; 4]), values[1]);
+        assert_eq!(Ok(vec![b'b'; 4]), values[2]);
+    }
 
-        // check that the resulting file is the same
-        let _ = persister.header.db_file.flush().unwrap();
-        assert_slots_eq(
-            open_file("tests/data/insert_kv-02.dat"),
-            persister.header.db_file,
-            &vec![
-                Slot{space: 3, cursor: 0},
-                Slot{space: 3, cursor: 5},
-                Slot{space: 3, cursor: 8},
-                Slot{space: 1, cursor: 3}
-            ]
-        )
+    // the end of the data is the end of the highest live slot, and every byte below it is
+    // either live or free exactly once
+    fn assert_packed_layout(persister: &Persister<String>) {
+        let live_end = persister.live_slots.iter().next_back().map_or(0, |(cursor, space)| cursor + space);
+        assert_eq!(live_end, persister.last_cursor);
+        let live_bytes: usize = persister.live_slots.values().sum();
+        assert_eq!(persister.last_cursor, live_bytes + persister.freelist.total_free_space());
     }
 
     #[test]
-    fn test_get_value() {
+    fn test_update_value_grow_last_key() {
         let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
 
-        let _ = persister.insert_kv(&"key1".to_string(), &vec![b'a', b'b', b'c']).unwrap();
-        assert_eq!(vec![b'a', b'b', b'c'], persister.get_value(&"key1".to_string()).unwrap());
+        // no free slot fits, the last value moves to the end of the data and its old slot is
+        // freed once the new one is written
+        persister.update_value(&"key2".to_string(), &[b'c'; 6]).unwrap();
+        assert_eq!(Slot { cursor: 8, space: 6 }, persister.index[&"key2".to_string()]);
+        assert_eq!(14, persister.last_cursor);
+        assert_eq!(&[Slot { cursor: 4, space: 4 }], persister.freelist.fragments());
+        assert_packed_layout(&persister);
+
+        // deleting the last value gives back the free slot before it to the end of the data too
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        assert_eq!(4, persister.last_cursor);
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_packed_layout(&persister);
+
+        persister.insert_kv(&"key3".to_string(), &[b'd'; 2]).unwrap();
+        persister.update_value(&"key1".to_string(), &[b'e'; 6]).unwrap();
+        assert_eq!(Slot { cursor: 6, space: 6 }, persister.index[&"key1".to_string()]);
+        assert_eq!(&[Slot { cursor: 0, space: 4 }], persister.freelist.fragments());
+        assert_packed_layout(&persister);
 
-        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"non_existent_key".to_string()).unwrap_err())
+        // new values don't land on space that is still used
+        persister.insert_kv(&"key4".to_string(), &[b'f'; 4]).unwrap();
+        persister.insert_kv(&"key5".to_string(), &[b'g'; 2]).unwrap();
+        assert_eq!(Slot { cursor: 0, space: 4 }, persister.index[&"key4".to_string()]);
+        assert_eq!(Slot { cursor: 12, space: 2 }, persister.index[&"key5".to_string()]);
+        assert_packed_layout(&persister);
+        for (key, value) in [("key1", vec![b'e'; 6]), ("key3", vec![b'd'; 2]), ("key4", vec![b'f'; 4]), ("key5", vec![b'g'; 2])] {
+            assert_eq!(value, persister.get_value(&key.to_string()).unwrap());
+        }
     }
 
     #[test]
-    fn test_update_value() {
+    fn test_update_value_grow_middle_key() {
         let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 4]).unwrap();
 
-        let _ = persister.insert_kv(&"key1".to_string(), &vec![b'a', b'c', b'd']);
-        let _ = persister.update_value(&"key1".to_string(), &vec![b'e', b'f', b'g']);
-        assert_eq!(3, persister.last_cursor);
+        persister.update_value(&"key2".to_string(), &[b'd'; 6]).unwrap();
+        assert_eq!(Slot { cursor: 12, space: 6 }, persister.index[&"key2".to_string()]);
+        assert_eq!((None, Some(&Slot { cursor: 4, space: 4 })), persister.freelist.neighbors_of(4, 0));
+        assert_eq!(18, persister.last_cursor);
+        assert_packed_layout(&persister);
+        assert_eq!(vec![b'a'; 4], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'd'; 6], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!(vec![b'c'; 4], persister.get_value(&"key3".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_update_value_grow_into_tail_hole() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 2]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 3]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 2]).unwrap();
 
-        assert_eq!(vec![b'e', b'f', b'g'], persister.get_value(&"key1".to_string()).unwrap());
+        // deleting key2 leaves a hole of 3 bytes, deleting key3 afterwards moves the end of
+        // the data back over both of them
+        persister.delete_kv(&"key2".to_string()).unwrap();
+        persister.delete_kv(&"key3".to_string()).unwrap();
+        assert_eq!(2, persister.last_cursor);
+        assert_eq!(0, persister.freelist.total_free_space());
+        assert_packed_layout(&persister);
 
-        // delete the kv and try to update again
-        let _ = persister.delete_kv(&"key1".to_string());
-        assert_eq!(
-            KVError::KeyDoesNotExist,
-            persister.update_value(&"key1".to_string(), &vec![b'e', b'f', b'g']).unwrap_err()
+        persister.update_value(&"key1".to_string(), &[b'd'; 3]).unwrap();
+        assert_eq!(Slot { cursor: 2, space: 3 }, persister.index[&"key1".to_string()]);
+        assert_eq!(5, persister.last_cursor);
+        assert_eq!((None, Some(&Slot { cursor: 0, space: 2 })), persister.freelist.neighbors_of(0, 0));
+        assert_packed_layout(&persister);
+
+        persister.insert_kv(&"key4".to_string(), &[b'e'; 2]).unwrap();
+        persister.insert_kv(&"key5".to_string(), &[b'f'; 1]).unwrap();
+        assert_eq!(Slot { cursor: 0, space: 2 }, persister.index[&"key4".to_string()]);
+        assert_eq!(Slot { cursor: 5, space: 1 }, persister.index[&"key5".to_string()]);
+        assert_packed_layout(&persister);
+        assert_eq!(vec![b'd'; 3], persister.get_value(&"key1".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_update_value_write_failure_keeps_previous_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_mock_persister(dir.path());
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"hole".to_string(), &[0; 6]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 4]).unwrap();
+        persister.delete_kv(&"hole".to_string()).unwrap();
+
+        let freelist = persister.freelist.clone();
+        let index = persister.index.clone();
+        let live_slots = persister.live_slots.clone();
+        let last_cursor = persister.last_cursor;
+
+        // swap the db file for a read-only handle so every value write fails
+        let writable = std::mem::replace(
+            &mut persister.header.db_file,
+            OpenOptions::new().read(true).open(dir.path().join("db")).unwrap(),
         );
-        assert_eq!(0, persister.last_cursor);
+
+        // into the free slot, the last value and another one at the end of the data, and
+        // shrunk into the free slot
+        let updates = [("key3", 5), ("key3", 8), ("key2", 8), ("key1", 2)];
+        for (key, len) in updates {
+            let result = persister.update_value(&key.to_string(), &vec![b'x'; len]);
+            assert!(matches!(result, Err(KVError::IOError(_))), "{} to {} bytes: {:?}", key, len, result);
+        }
+        let result = persister.modify_value(&"key2".to_string(), |value| [value, b"yy"].concat());
+        assert!(matches!(result, Err(KVError::IOError(_))));
+
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(index, persister.index);
+        assert_eq!(live_slots, persister.live_slots);
+        assert_eq!(last_cursor, persister.last_cursor);
+        for (key, value) in [("key1", vec![b'a'; 4]), ("key2", vec![b'b'; 4]), ("key3", vec![b'c'; 4])] {
+            assert_eq!(value, persister.get_value(&key.to_string()).unwrap());
+        }
+
+        persister.header.db_file = writable;
+        persister.update_value(&"key3".to_string(), &[b'd'; 8]).unwrap();
+        assert_eq!(Slot { cursor: 18, space: 8 }, persister.index[&"key3".to_string()]);
+        assert_packed_layout(&persister);
     }
 
     #[test]
-    fn test_update_value_with_more_space() {
+    fn test_update_value_torn_write_keeps_previous_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister = open_mock_persister(dir.path());
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        let freelist = persister.freelist.clone();
+        let index = persister.index.clone();
+        let last_cursor = persister.last_cursor;
+
+        // only part of the bigger value reaches the file, the bytes of the previous one stay
+        // untouched since the value at the end of the data doesn't grow over them
+        faults::tear_next_write(FileKind::Db, 5);
+        let result = persister.update_value(&"key2".to_string(), &[b'c'; 8]);
+        assert!(matches!(result, Err(KVError::IOError(_))), "{:?}", result);
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(index, persister.index);
+        assert_eq!(last_cursor, persister.last_cursor);
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
+
+        assert_eq!(b"bbbb", &std::fs::read(dir.path().join("db")).unwrap()[4..8]);
+
+        // same for values that fit in the slot they replace
+        for len in [4, 2] {
+            faults::tear_next_write(FileKind::Db, len / 2);
+            let result = persister.update_value(&"key1".to_string(), &vec![b'd'; len]);
+            assert!(matches!(result, Err(KVError::IOError(_))), "{} bytes: {:?}", len, result);
+            assert_eq!(index, persister.index);
+            assert_eq!(vec![b'a'; 4], persister.get_value(&"key1".to_string()).unwrap());
+            assert_eq!(b"aaaa", &std::fs::read(dir.path().join("db")).unwrap()[0..4]);
+        }
+
+        persister.update_value(&"key2".to_string(), &[b'c'; 8]).unwrap();
+        assert_eq!(vec![b'c'; 8], persister.get_value(&"key2".to_string()).unwrap());
+        assert_packed_layout(&persister);
+    }
+
+    #[test]
+    fn test_modify_value_torn_write_keeps_previous_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store").to_string_lossy().to_string();
+        let mut persister: Persister<String> = Persister::new(datastore.clone(), 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        let index = persister.index.clone();
+
+        // the value fits in its slot but is written elsewhere, only half of it gets there
+        faults::tear_next_write(FileKind::Db, 2);
+        let result = persister.modify_value(&"key1".to_string(), |_| vec![b'c'; 4]);
+        assert!(matches!(result, Err(KVError::IOError(_))), "{:?}", result);
+        faults::tear_next_write(FileKind::Db, 1);
+        let result = persister.modify_or_insert(&"key2".to_string(), vec![], |_| vec![b'd'; 2]);
+        assert!(matches!(result, Err(KVError::IOError(_))), "{:?}", result);
+
+        assert_eq!(index, persister.index);
+        assert_eq!(vec![b'a'; 4], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
+        assert_eq!([[b'a'; 4], [b'b'; 4]].concat(), raw_bytes(&persister, 0, 8));
+        drop(persister);
+
+        let persister: Persister<String> = Persister::open_existing(datastore, 0).unwrap();
+        assert_eq!(vec![b'a'; 4], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_insert_kv_failure_leaves_store_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let datastore = dir.path().join("store");
+        let mut persister: Persister<String> = Persister::new(datastore.to_string_lossy().to_string(), 0).unwrap();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"hole".to_string(), &[0; 6]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.delete_kv(&"hole".to_string()).unwrap();
+
+        let index = persister.index.clone();
+        let freelist = persister.freelist.clone();
+        let live_slots = persister.live_slots.clone();
+        let last_cursor = persister.last_cursor;
+
+        // the write-ahead log, the db file and the index file fail in turn, for a value going
+        // to the free slot, one going to the end of the data and an empty one
+        let paths = [dir.path().join("wal_store"), datastore.clone(), dir.path().join("index_store")];
+        for (step, path) in paths.iter().enumerate() {
+            for value in [vec![b'x'; 5], vec![b'y'; 8], vec![]] {
+                if step == 1 && value.is_empty() {
+                    continue; // an empty value writes nothing to the db file
+                }
+
+                let file = match step {
+                    0 => persister.header.wal_file.as_mut().unwrap(),
+                    1 => &mut persister.header.db_file,
+                    _ => &mut persister.header.index_file,
+                };
+                let writable = std::mem::replace(file, OpenOptions::new().read(true).open(path).unwrap());
+                let result = persister.insert_kv(&"new".to_string(), &value);
+                let file = match step {
+                    0 => persister.header.wal_file.as_mut().unwrap(),
+                    1 => &mut persister.header.db_file,
+                    _ => &mut persister.header.index_file,
+                };
+                *file = writable;
+
+                assert!(matches!(result, Err(KVError::IOError(_))), "step {} with {} bytes: {:?}", step, value.len(), result);
+                assert_eq!(index, persister.index);
+                assert_eq!(freelist, persister.freelist);
+                assert_eq!(live_slots, persister.live_slots);
+                assert_eq!(last_cursor, persister.last_cursor);
+                assert!(!persister.contains_key(&"new".to_string()));
+            }
+        }
+
+        persister.insert_kv(&"new".to_string(), &[b'x'; 5]).unwrap();
+        assert_eq!(Slot { cursor: 4, space: 5 }, persister.index[&"new".to_string()]);
+        drop(persister);
+
+        let persister: Persister<String> = Persister::open_existing(datastore.to_string_lossy().to_string(), 0).unwrap();
+        assert_eq!(vec!["key1", "key2", "new"], persister.keys().collect::<Vec<_>>());
+        assert_eq!(vec![b'x'; 5], persister.get_value(&"new".to_string()).unwrap());
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_torn_index_appends_are_cut_back() {
         let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let index = persister.index.clone();
+        let index_len = persister.header.index_file.metadata().unwrap().len();
 
-        let _ = persister.insert_kv(&"key1".to_string(), &vec![b'a', b'c', b'd']);
-        let _ = persister.update_value(&"key1".to_string(), &vec![b'e', b'f', b'g', b'h']);
-        assert_eq!(4, persister.last_cursor);
+        // each of them appends several records at once, only the first bytes get to the file
+        faults::tear_next_write(FileKind::Index, 3);
+        assert!(matches!(persister.delete_range(..), Err(KVError::IOError(_))));
+        faults::tear_next_write(FileKind::Index, 20);
+        let pairs = vec![("key3".to_string(), vec![b'c'; 2]), ("key4".to_string(), vec![b'd'; 2])];
+        assert!(matches!(persister.bulk_load(pairs), Err(KVError::IOError(_))));
+        faults::tear_next_write(FileKind::Index, 5);
+        assert!(matches!(persister.compact_datastore(), Err(KVError::IOError(_))));
 
-        assert_eq!(vec![b'e', b'f', b'g', b'h'], persister.get_value(&"key1".to_string()).unwrap());
+        assert_eq!(index_len, persister.header.index_file.metadata().unwrap().len());
+        assert_eq!(index, persister.index);
+        persister.load_index().unwrap();
+        assert_eq!(index, persister.index);
+        assert_eq!(vec![b'b'; 4], persister.get_value(&"key2".to_string()).unwrap());
 
-        // delete the kv and try to update again
-        let _ = persister.delete_kv(&"key1".to_string());
-        assert_eq!(0, persister.last_cursor);
+        // the records appended next are read back
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 2]).unwrap();
+        persister.load_index().unwrap();
+        assert_eq!(vec!["key2", "key3"], persister.keys().collect::<Vec<_>>());
     }
 
     #[test]
-    fn test_update_value_with_middle_space_not_enough() {
+    fn test_insert_kv_storage_limit_leaves_store_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut persister: Persister<String> = PersisterBuilder::new().path(dir.path().join("store")).storage_limit(10).open().unwrap();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 3]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.delete_kv(&"key1".to_string()).unwrap();
+        let freelist = persister.freelist.clone();
+
+        assert!(matches!(persister.insert_kv(&"key3".to_string(), &[b'c'; 4]), Err(KVError::StorageLimitExceeded { .. })));
+        assert_eq!(freelist, persister.freelist);
+        assert_eq!(7, persister.last_cursor);
+
+        // the free slot still takes a value that fits in it
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 3]).unwrap();
+        assert_eq!(Slot { cursor: 0, space: 3 }, persister.index[&"key3".to_string()]);
+    }
+
+    #[test]
+    fn test_verify_integrity_consistent_store() {
         let mut persister = new_mock_persister();
+        for i in 0..20usize {
+            persister.insert_kv(&format!("key_{:02}", i), &vec![i as u8; i % 5]).unwrap();
+        }
+        for i in (0..20).step_by(3) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        for i in (1..20).step_by(4).filter(|i| i % 3 != 0) {
+            persister.update_value(&format!("key_{:02}", i), &[b'x'; 7]).unwrap();
+        }
 
-        let _ = persister.insert_kv(&"key1".to_string(), &vec![b'a', b'c', b'd']);
-        let _ = persister.insert_kv(&"key2".to_string(), &vec![b'e', b'f', b'g']);
-        let _ = persister.insert_kv(&"key3".to_string(), &vec![b'h', b'i', b'j']);
+        let report = persister.verify_integrity().unwrap();
+        assert!(report.is_consistent(), "{:?}", report.violations);
+        assert_eq!((persister.len(), persister.freelist.fragment_count(), 0), (report.keys_checked, report.free_slots_checked, report.values_read));
 
-        // try to update middle kv with a bigger value
-        let _ = persister.update_value(&"key2".to_string(), &vec![b'k', b'l', b'm', b'n']);
-        assert_eq!(13, persister.last_cursor);
+        let report = persister.verify_integrity_strict().unwrap();
+        assert!(report.is_consistent(), "{:?}", report.violations);
+        assert_eq!(persister.len(), report.values_read);
+    }
 
-        assert_eq!(vec![b'k', b'l', b'm', b'n'], persister.get_value(&"key2".to_string()).unwrap());
+    #[test]
+    fn test_verify_integrity_overlapping_slots() {
+        let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 4]).unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
 
-        // delete the kv and try to update again
-        let _ = persister.delete_kv(&"key2".to_string());
-        assert_eq!(9, persister.last_cursor);
+        // key4 claims bytes of key1 and of the free slot left by key2
+        persister.index.insert("key4".to_string(), Slot { cursor: 2, space: 4 });
+        assert_eq!(vec![
+            Violation::OverlappingSlots { first: "key1".to_string(), second: "key4".to_string(), overlap: 2..4 },
+            Violation::SlotInFreeSpace { key: "key4".to_string(), slot: 2..6, free: 4..8 },
+        ], persister.verify_integrity().unwrap().violations);
+        persister.index.remove("key4");
+
+        // the free list hands out the slot of key3
+        persister.freelist.insert_free_space(9, 2);
+        assert_eq!(vec![
+            Violation::SlotInFreeSpace { key: "key3".to_string(), slot: 8..12, free: 9..11 },
+        ], persister.verify_integrity().unwrap().violations);
     }
 
     #[test]
-    fn delete_kv() {
+    fn test_verify_integrity_drifted_counters() {
         let mut persister = new_mock_persister();
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.insert_kv(&"key3".to_string(), &[b'c'; 4]).unwrap();
+        persister.delete_kv(&"key2".to_string()).unwrap();
 
-        let _ = persister.insert_kv(&"key1".to_string(), &vec![b'a', b'c', b'd']);
-        let _ = persister.delete_kv(&"key1".to_string());
-        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"key1".to_string()).unwrap_err());
+        persister.freelist = FreeList::from_parts(vec![Slot { cursor: 4, space: 4 }], 3);
+        assert_eq!(vec![Violation::FreeSpaceMismatch { counted: 3, fragments: 4 }], persister.verify_integrity().unwrap().violations);
 
-        assert_eq!(0, persister.last_cursor);
+        // the end of the data went back below key3 and over the free slot, violations are
+        // listed in cursor order
+        persister.freelist = FreeList::from_parts(vec![Slot { cursor: 4, space: 4 }], 4);
+        persister.last_cursor = 6;
+        assert_eq!(vec![
+            Violation::FreeSlotBeyondLastCursor { free: 4..8, last_cursor: 6 },
+            Violation::SlotBeyondLastCursor { key: "key3".to_string(), slot: 8..12, last_cursor: 6 },
+        ], persister.verify_integrity().unwrap().violations);
+    }
+
+    #[test]
+    fn test_verify_integrity_truncated_file() {
+        let mut persister = new_mock_persister();
+        persister.set_eof_policy(EofPolicy::Quarantine);
+        persister.insert_kv(&"key1".to_string(), &[b'a'; 4]).unwrap();
+        persister.insert_kv(&"key2".to_string(), &[b'b'; 4]).unwrap();
+        persister.header.set_data_len(6).unwrap();
+
+        let report = persister.verify_integrity().unwrap();
+        assert_eq!(vec![Violation::SlotBeyondEof { key: "key2".to_string(), slot: 4..8, file_len: 6 }], report.violations);
+
+        let report = persister.verify_integrity_strict().unwrap();
+        assert_eq!(2, report.violations.len());
+        assert!(matches!(&report.violations[1], Violation::UnreadableValue { key, .. } if key == "key2"));
+
+        // nothing was quarantined or changed by the reads
+        assert!(persister.quarantined_keys().is_empty());
+        assert_eq!(6, persister.header.data_len().unwrap());
+    }
+
+    // runs a verification in steps of `budget_bytes`, saving and loading the state between
+    // steps as a process resuming it would, and calls `between` before every step
+    fn verify_in_steps(persister: &mut Persister<String>, budget_bytes: usize, mut between: impl FnMut(&mut Persister<String>, usize)) -> (IntegrityReport<String>, VerifyState<String>, usize) {
+        let mut state = VerifyState::new();
+        let mut steps = 0;
+        loop {
+            between(persister, state.cursor());
+            state = bincode::deserialize(&bincode::serialize(&state).unwrap()).unwrap();
+            steps += 1;
+            if let StepOutcome::Done(report) = persister.verify_step(budget_bytes, &mut state).unwrap() {
+                assert_eq!(StepOutcome::Done(report.clone()), persister.verify_step(budget_bytes, &mut state).unwrap());
+                return (report, state, steps);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_step_matches_single_pass() {
+        let mut persister = new_mock_persister();
+        for i in 0..20usize {
+            persister.insert_kv(&format!("key_{:02}", i), &vec![i as u8; i % 5]).unwrap();
+        }
+        for i in (0..20).step_by(3) {
+            persister.delete_kv(&format!("key_{:02}", i)).unwrap();
+        }
+        let (report, _, _) = verify_in_steps(&mut persister, 0, |_, _| {});
+        assert!(report.is_consistent());
+        assert_eq!(persister.verify_integrity_strict().unwrap(), report);
+
+        // overlapping slots, a slot in free space, a slot past the end of the file and drifted
+        // counters
+        persister.index.insert("overlap".to_string(), Slot { cursor: 3, space: 6 });
+        persister.index.insert("inside".to_string(), Slot { cursor: 4, space: 1 });
+        persister.freelist.insert_free_space(20, 3);
+        let data_len = persister.header.data_len().unwrap() as usize;
+        persister.header.set_data_len(data_len as u64 - 2).unwrap();
+        let expected = persister.verify_integrity_strict().unwrap();
+        assert!(expected.violations.len() >= 5, "{:?}", expected.violations);
+
+        let mut steps_taken = vec![];
+        for budget in [0, 1, 3, 10, usize::MAX] {
+            let (report, state, steps) = verify_in_steps(&mut persister, budget, |_, _| {});
+            assert_eq!(expected, report, "budget {}", budget);
+            assert_eq!(0, state.skipped());
+            steps_taken.push(steps);
+        }
+        assert_eq!(1, *steps_taken.last().unwrap());
+        assert!(steps_taken[0] > 10, "{:?}", steps_taken);
+    }
+
+    #[test]
+    fn test_verify_step_with_deletes_between_steps() {
+        let mut persister = new_mock_persister();
+        for i in 0..30usize {
+            persister.insert_kv(&format!("key_{:02}", i), &[i as u8; 4]).unwrap();
+        }
+        persister.index.insert("rogue".to_string(), Slot { cursor: 2, space: 4 });
+
+        // keys deleted ahead of the resume point are checked as they are when reached
+        let (report, state, _) = verify_in_steps(&mut persister, 4, |persister, cursor| {
+            if cursor == 40 {
+                persister.delete_kv(&"key_20".to_string()).unwrap();
+                persister.delete_kv(&"key_24".to_string()).unwrap();
+            }
+        });
+        assert_eq!(persister.verify_integrity_strict().unwrap(), report);
+        assert_eq!(0, state.skipped());
+
+        // the violation about a key deleted behind the resume point is dropped
+        let (report, state, _) = verify_in_steps(&mut persister, 4, |persister, cursor| {
+            if cursor == 40 {
+                persister.delete_kv(&"key_00".to_string()).unwrap();
+            }
+        });
+        assert_eq!(vec![
+            Violation::OverlappingSlots { first: "rogue".to_string(), second: "key_01".to_string(), overlap: 4..6 },
+        ], report.violations);
+        assert_eq!(1, state.skipped());
+        assert_eq!(persister.len() + 1, report.keys_checked);
     }
 
-    fn assert_slots_eq(mut file_exp: File, mut file_obt: File, slots: &Vec<Slot>) {
+    fn assert_slots_eq(mut file_exp: File, header: &FileHeader, slots: &[Slot]) {
         let highest_cursor = slots.iter().map(|slot| slot.cursor + slot.space).max().unwrap_or(0);
 
         assert_ne!(0, highest_cursor);
@@ -403,8 +6988,7 @@ mod tests {
         file_exp.read_exact(&mut read_exp).unwrap();
 
         let mut read_obt = vec![0; highest_cursor];
-        file_obt.seek(SeekFrom::Start(0)).unwrap();
-        file_obt.read_exact(&mut read_obt).unwrap();
+        header.read_data_at(&mut read_obt, 0).unwrap();
 
         // only compare the slots, files may contain junk in unwritten parts
         for slot in slots.iter() {
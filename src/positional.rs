@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::Error;
+
+/// Reads and writes at absolute offsets of a file. On unix the position of the file is left
+/// untouched, on windows it is moved, so callers must not rely on it either way
+pub(crate) trait PositionalFile {
+    /// Fills the whole buffer, a read cut short by the end of the file fails with
+    /// `ErrorKind::UnexpectedEof`
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> Result<(), Error>;
+
+    fn write_all_at(&self, buffer: &[u8], offset: u64) -> Result<(), Error>;
+}
+
+#[cfg(unix)]
+impl PositionalFile for File {
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> Result<(), Error> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buffer, offset)
+    }
+
+    fn write_all_at(&self, buffer: &[u8], offset: u64) -> Result<(), Error> {
+        std::os::unix::fs::FileExt::write_all_at(self, buffer, offset)
+    }
+}
+
+// seek_read and seek_write may transfer less than asked, so they are retried until done
+#[cfg(windows)]
+impl PositionalFile for File {
+    fn read_exact_at(&self, mut buffer: &mut [u8], mut offset: u64) -> Result<(), Error> {
+        use std::io::ErrorKind;
+        use std::os::windows::fs::FileExt;
+
+        while !buffer.is_empty() {
+            match self.seek_read(buffer, offset) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+                Ok(read) => {
+                    buffer = &mut buffer[read..];
+                    offset += read as u64;
+                },
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buffer: &[u8], mut offset: u64) -> Result<(), Error> {
+        use std::io::ErrorKind;
+        use std::os::windows::fs::FileExt;
+
+        while !buffer.is_empty() {
+            match self.seek_write(buffer, offset) {
+                Ok(0) => return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer")),
+                Ok(written) => {
+                    buffer = &buffer[written..];
+                    offset += written as u64;
+                },
+                Err(error) if error.kind() == ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use super::*;
+
+    #[test]
+    fn test_read_write_at() {
+        let file = tempfile::tempfile().unwrap();
+        file.write_all_at(b"abc", 0).unwrap();
+        file.write_all_at(b"xyz", 6).unwrap();
+        file.write_all_at(b"de", 3).unwrap();
+
+        let mut buffer = [0; 2];
+        file.read_exact_at(&mut buffer, 1).unwrap();
+        assert_eq!(b"bc", &buffer);
+        file.read_exact_at(&mut buffer, 7).unwrap();
+        assert_eq!(b"yz", &buffer);
+
+        // reads can span several previous writes and the gaps left between them
+        let mut buffer = [0; 9];
+        file.read_exact_at(&mut buffer, 0).unwrap();
+        assert_eq!(b"abcde\0xyz", &buffer);
+
+        // overwrites only touch their own range
+        file.write_all_at(b"C", 2).unwrap();
+        file.read_exact_at(&mut buffer, 0).unwrap();
+        assert_eq!(b"abCde\0xyz", &buffer);
+    }
+
+    #[test]
+    fn test_short_read() {
+        let file = tempfile::tempfile().unwrap();
+        file.write_all_at(b"abc", 0).unwrap();
+
+        let mut buffer = [0; 2];
+        assert_eq!(ErrorKind::UnexpectedEof, file.read_exact_at(&mut buffer, 2).unwrap_err().kind());
+        assert_eq!(ErrorKind::UnexpectedEof, file.read_exact_at(&mut buffer, 10).unwrap_err().kind());
+        file.read_exact_at(&mut [], 10).unwrap();
+    }
+}
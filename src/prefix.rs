@@ -0,0 +1,64 @@
+/// Keys that can be scanned by prefix with `Persister::scan_prefix`
+pub trait PrefixKey: Ord + Clone + AsRef<[u8]> {
+    /// Returns the smallest key that is greater than every key starting with `self`, or `None`
+    /// when there is no such key (empty prefix, or a prefix that can't be incremented)
+    fn prefix_end(&self) -> Option<Self>;
+}
+
+impl PrefixKey for Vec<u8> {
+    fn prefix_end(&self) -> Option<Self> {
+        // drop the trailing 0xff bytes since they can't be incremented, then bump the last one
+        let mut end = self.clone();
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                return Some(end);
+            }
+        }
+
+        None
+    }
+}
+
+impl PrefixKey for String {
+    fn prefix_end(&self) -> Option<Self> {
+        // strings are ordered by their utf-8 bytes, which follows the code point order, so
+        // bumping the last char that can be bumped gives the end of the prefix range while
+        // keeping the bound a valid string
+        let mut end: Vec<char> = self.chars().collect();
+        while let Some(last) = end.pop() {
+            let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+            if let Some(next) = next {
+                end.push(next);
+                return Some(end.into_iter().collect());
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytes_prefix_end() {
+        assert_eq!(Some(vec![b'a', b'c']), vec![b'a', b'b'].prefix_end());
+        assert_eq!(Some(vec![1]), vec![0].prefix_end());
+        assert_eq!(Some(vec![b'b']), vec![b'a', 0xff, 0xff].prefix_end());
+        assert_eq!(None, vec![0xff, 0xff].prefix_end());
+        assert_eq!(None, Vec::<u8>::new().prefix_end());
+    }
+
+    #[test]
+    fn test_string_prefix_end() {
+        assert_eq!(Some("abd".to_string()), "abc".to_string().prefix_end());
+        assert_eq!(Some("user:43".to_string()), "user:42".to_string().prefix_end());
+        assert_eq!(Some("a\u{80}".to_string()), "a\u{7f}".to_string().prefix_end());
+        assert_eq!(Some("a\u{e000}".to_string()), "a\u{d7ff}".to_string().prefix_end());
+        assert_eq!(Some("b".to_string()), "a\u{10ffff}".to_string().prefix_end());
+        assert_eq!(None, "\u{10ffff}".to_string().prefix_end());
+        assert_eq!(None, String::new().prefix_end());
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs::File;
+use std::io::Error;
+use memmap2::Mmap;
+
+/// Read-only map of the db file serving reads without a syscall, see
+/// `Persister::set_mmap_reads`. Writes keep going through the file handle: the map is shared
+/// with the page cache, so they show up in it right away, and the map is redone whenever the
+/// file length changes so that new slots are covered
+pub(crate) struct ReadMap {
+    map: Option<Mmap>, // empty db files can't be mapped
+}
+
+impl ReadMap {
+    pub fn new(db_file: &File) -> Result<Self, Error> {
+        let mut read_map = Self { map: None };
+        read_map.refresh(db_file)?;
+
+        Ok(read_map)
+    }
+
+    /// Maps the file again if its length no longer matches the map
+    pub fn refresh(&mut self, db_file: &File) -> Result<(), Error> {
+        let file_len = db_file.metadata()?.len() as usize;
+        if self.len() == file_len {
+            return Ok(());
+        }
+
+        // the old map is dropped first, it may reach past the end of a file that shrank
+        self.map = None;
+        if file_len > 0 {
+            // SAFETY: the db file is only written through the Persister owning this map, with
+            // `&mut self`, so no slice handed out by `slice` is alive while the bytes under it
+            // change. Another process truncating the file while it is mapped is not supported
+            self.map = Some(unsafe { Mmap::map(db_file) }?);
+        }
+
+        Ok(())
+    }
+
+    /// Bytes of the slot, None when the slot reaches past the end of the map
+    pub fn slice(&self, cursor: usize, space: usize) -> Option<&[u8]> {
+        if cursor + space > self.len() {
+            return None;
+        }
+
+        match &self.map {
+            Some(map) => Some(&map[cursor..cursor + space]),
+            None => Some(&[]),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.map.as_ref().map_or(0, |map| map.len())
+    }
+}
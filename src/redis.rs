@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use crate::batch::WriteBatch;
+use crate::persist::{KVError, Persister};
+
+/// Options of the Redis imports
+#[derive(Debug, Clone)]
+pub struct RedisImportOptions {
+    /// number of commands grouped in each write batch
+    pub batch_size: usize,
+}
+
+impl Default for RedisImportOptions {
+    fn default() -> Self {
+        Self { batch_size: 1000 }
+    }
+}
+
+/// Outcome of a Redis import
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportReport {
+    /// commands written to the store
+    pub applied: usize,
+    /// unsupported commands left out, by upper-cased command name
+    pub skipped: BTreeMap<String, usize>,
+    /// commands that could not be applied, with the key they target and the reason
+    pub failed: Vec<(String, String)>,
+    /// expirations dropped because the store has no TTL support yet, from the expiry
+    /// commands and the expiry options of SET
+    pub expiries_ignored: usize,
+}
+
+impl ImportReport {
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.values().sum()
+    }
+}
+
+// commands working on the non-string types of Redis, reported as a type mismatch on their key
+const NON_STRING_COMMANDS: &[&str] = &[
+    "LPUSH", "RPUSH", "LSET", "LINSERT", "HSET", "HMSET", "HSETNX", "SADD", "ZADD", "XADD",
+    "PFADD", "GEOADD",
+];
+const EXPIRY_COMMANDS: &[&str] = &["EXPIRE", "PEXPIRE", "EXPIREAT", "PEXPIREAT", "PERSIST"];
+
+impl Persister<String> {
+    /// Imports the RESP command stream of a Redis append-only file. SET and DEL are applied
+    /// through write batches, commands on other Redis types fail for their key and any other
+    /// command is skipped. Keys and values are taken as strings
+    pub fn import_redis_appendonly<R: BufRead>(&mut self, mut reader: R, opts: RedisImportOptions) -> Result<ImportReport, KVError> {
+        let mut importer = Importer::new(self, opts);
+        while let Some(command) = read_resp_command(&mut reader)? {
+            importer.apply(command)?;
+        }
+
+        importer.finish()
+    }
+
+    /// Imports the textual form of Redis commands, one inline command per line as accepted by
+    /// `redis-cli`, e.g. `SET "some key" value`. Empty lines and lines starting with `#` are
+    /// ignored. Commands are handled as in `Persister::import_redis_appendonly`
+    pub fn import_redis_protocol_dump<R: BufRead>(&mut self, reader: R, opts: RedisImportOptions) -> Result<ImportReport, KVError> {
+        let mut importer = Importer::new(self, opts);
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            let command = parse_inline_command(&line)
+                .map_err(|error| KVError::SerializationError(format!("line {}: {}", number + 1, error)))?;
+            importer.apply(command)?;
+        }
+
+        importer.finish()
+    }
+}
+
+// groups the writes of consecutive commands into batches, the last command on a key wins
+struct Importer<'a> {
+    persister: &'a mut Persister<String>,
+    opts: RedisImportOptions,
+    pending: BTreeMap<String, Option<Vec<u8>>>,
+    pending_commands: usize,
+    report: ImportReport,
+}
+
+impl<'a> Importer<'a> {
+    fn new(persister: &'a mut Persister<String>, opts: RedisImportOptions) -> Self {
+        Self {
+            persister,
+            opts,
+            pending: BTreeMap::new(),
+            pending_commands: 0,
+            report: ImportReport::default(),
+        }
+    }
+
+    fn apply(&mut self, command: Vec<Vec<u8>>) -> Result<(), KVError> {
+        let mut args = command.into_iter();
+        let name = match args.next() {
+            Some(name) => String::from_utf8_lossy(&name).to_uppercase(),
+            None => return Ok(()),
+        };
+        let args: Vec<Vec<u8>> = args.collect();
+
+        match name.as_str() {
+            "SET" => self.set(args),
+            "DEL" | "UNLINK" => self.del(&name, args),
+            _ if NON_STRING_COMMANDS.contains(&name.as_str()) => {
+                let key = args.first().map(|key| String::from_utf8_lossy(key).to_string()).unwrap_or_default();
+                self.report.failed.push((key, format!("{} needs a non-string value", name)));
+            },
+            _ => {
+                if EXPIRY_COMMANDS.contains(&name.as_str()) {
+                    self.report.expiries_ignored += 1;
+                }
+                *self.report.skipped.entry(name).or_insert(0) += 1;
+            },
+        }
+
+        if self.pending_commands >= self.opts.batch_size.max(1) {
+            self.write_pending()?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, mut args: Vec<Vec<u8>>) {
+        if args.len() < 2 {
+            let key = args.first().map(|key| String::from_utf8_lossy(key).to_string()).unwrap_or_default();
+            self.report.failed.push((key, "SET needs a key and a value".to_string()));
+            return;
+        }
+
+        let options: Vec<String> = args.split_off(2).iter().map(|option| String::from_utf8_lossy(option).to_uppercase()).collect();
+        if options.iter().any(|option| ["EX", "PX", "EXAT", "PXAT"].contains(&option.as_str())) {
+            self.report.expiries_ignored += 1;
+        }
+
+        let value = args.pop().unwrap_or_default();
+        let key = args.pop().unwrap_or_default();
+        match String::from_utf8(key) {
+            Ok(key) => {
+                self.pending.insert(key, Some(value));
+                self.pending_commands += 1;
+                self.report.applied += 1;
+            },
+            Err(error) => self.report.failed.push((String::from_utf8_lossy(error.as_bytes()).to_string(), "key is not valid utf-8".to_string())),
+        }
+    }
+
+    fn del(&mut self, name: &str, args: Vec<Vec<u8>>) {
+        if args.is_empty() {
+            self.report.failed.push((String::new(), format!("{} needs at least one key", name)));
+            return;
+        }
+
+        for key in args {
+            let key = match String::from_utf8(key) {
+                Ok(key) => key,
+                Err(error) => {
+                    self.report.failed.push((String::from_utf8_lossy(error.as_bytes()).to_string(), "key is not valid utf-8".to_string()));
+                    continue;
+                },
+            };
+
+            // deleting a missing key is a no-op in redis, only stored keys get a delete
+            if self.persister.contains_key(&key) {
+                self.pending.insert(key, None);
+            } else {
+                self.pending.remove(&key);
+            }
+        }
+        self.pending_commands += 1;
+        self.report.applied += 1;
+    }
+
+    fn write_pending(&mut self) -> Result<(), KVError> {
+        let mut batch = WriteBatch::new();
+        for (key, op) in std::mem::take(&mut self.pending) {
+            match op {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            };
+        }
+        self.pending_commands = 0;
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.persister.write_batch(batch)
+    }
+
+    fn finish(mut self) -> Result<ImportReport, KVError> {
+        self.write_pending()?;
+
+        Ok(self.report)
+    }
+}
+
+// RESP commands are arrays of bulk strings: *<count>\r\n followed by $<len>\r\n<bytes>\r\n for
+// each argument
+fn read_resp_command<R: BufRead>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>, KVError> {
+    let header = match read_resp_line(reader)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let count = parse_resp_length(&header, b'*')?;
+
+    let mut command = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = read_resp_line(reader)?
+            .ok_or_else(|| KVError::SerializationError("resp command cut short".to_string()))?;
+        let len = parse_resp_length(&header, b'$')?;
+
+        let mut argument = vec![0; len + 2];
+        reader.read_exact(&mut argument)?;
+        if !argument.ends_with(b"\r\n") {
+            return Err(KVError::SerializationError("resp bulk string not terminated by CRLF".to_string()));
+        }
+        argument.truncate(len);
+        command.push(argument);
+    }
+
+    Ok(Some(command))
+}
+
+// reads a line without its CRLF, None at the end of the stream
+fn read_resp_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>, KVError> {
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\r\n") {
+        return Err(KVError::SerializationError("resp line not terminated by CRLF".to_string()));
+    }
+    line.truncate(line.len() - 2);
+
+    Ok(Some(line))
+}
+
+fn parse_resp_length(header: &[u8], prefix: u8) -> Result<usize, KVError> {
+    match header.split_first() {
+        Some((first, digits)) if *first == prefix => std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| KVError::SerializationError(format!("invalid resp length {:?}", String::from_utf8_lossy(header)))),
+        _ => Err(KVError::SerializationError(format!("expected resp type {:?}", prefix as char))),
+    }
+}
+
+// splits an inline command on whitespace, double quoted arguments support the \n, \r, \t, \",
+// \\ and \xHH escapes and single quoted ones are taken literally
+fn parse_inline_command(line: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote = match chars.peek() {
+            None => return Ok(args),
+            Some('"') | Some('\'') => chars.next(),
+            Some(_) => None,
+        };
+
+        let mut arg: Vec<u8> = vec![];
+        loop {
+            let c = match (chars.next(), quote) {
+                (None, None) => break,
+                (None, Some(_)) => return Err("unbalanced quotes".to_string()),
+                (Some(c), None) if c.is_whitespace() => break,
+                (Some(c), Some(q)) if c == q => {
+                    if chars.peek().is_some_and(|next| !next.is_whitespace()) {
+                        return Err("closing quote must be followed by a space".to_string());
+                    }
+                    break;
+                },
+                (Some('\\'), Some('"')) => match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        let byte = u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape \\x{}", hex))?;
+                        arg.push(byte);
+                        continue;
+                    },
+                    Some(c) => c,
+                    None => return Err("unbalanced quotes".to_string()),
+                },
+                (Some(c), _) => c,
+            };
+
+            let mut buffer = [0; 4];
+            arg.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_persister() -> Persister<String> {
+        Persister::new_temporary().unwrap()
+    }
+
+    fn resp(commands: &[&[&str]]) -> Vec<u8> {
+        let mut stream = vec![];
+        for command in commands {
+            stream.extend(format!("*{}\r\n", command.len()).into_bytes());
+            for arg in command.iter() {
+                stream.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+            }
+        }
+        stream
+    }
+
+    #[test]
+    fn test_import_redis_appendonly() {
+        let mut persister = new_persister();
+        persister.insert_kv(&"stale".to_string(), b"old").unwrap();
+
+        let stream = resp(&[
+            &["SELECT", "0"],
+            &["SET", "user:1", "alice"],
+            &["set", "user:2", "bob", "EX", "60"],
+            &["SET", "user:3", "carol"],
+            &["PEXPIREAT", "user:3", "1700000000000"],
+            &["DEL", "user:3", "stale", "missing"],
+            &["SET", "line\r\nbreak", ""],
+            &["LPUSH", "queue", "job"],
+            &["SET", "lonely"],
+            &["SELECT", "1"],
+        ]);
+        let report = persister.import_redis_appendonly(stream.as_slice(), RedisImportOptions { batch_size: 2 }).unwrap();
+
+        assert_eq!(5, report.applied);
+        assert_eq!(BTreeMap::from([("SELECT".to_string(), 2), ("PEXPIREAT".to_string(), 1)]), report.skipped);
+        assert_eq!(3, report.skipped_count());
+        assert_eq!(vec![
+            ("queue".to_string(), "LPUSH needs a non-string value".to_string()),
+            ("lonely".to_string(), "SET needs a key and a value".to_string()),
+        ], report.failed);
+        assert_eq!(2, report.expiries_ignored);
+
+        assert_eq!(b"alice".to_vec(), persister.get_value(&"user:1".to_string()).unwrap());
+        assert_eq!(b"bob".to_vec(), persister.get_value(&"user:2".to_string()).unwrap());
+        assert_eq!(Vec::<u8>::new(), persister.get_value(&"line\r\nbreak".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"user:3".to_string()).unwrap_err());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"stale".to_string()).unwrap_err());
+    }
+
+    #[test]
+    fn test_import_redis_appendonly_malformed() {
+        let mut persister = new_persister();
+
+        let stream = b"*2\r\n$3\r\nDEL\r\n$10\r\nshort\r\n";
+        let result = persister.import_redis_appendonly(&stream[..], RedisImportOptions::default());
+        assert!(result.is_err());
+
+        let stream = b"SET key value\r\n";
+        assert!(matches!(
+            persister.import_redis_appendonly(&stream[..], RedisImportOptions::default()),
+            Err(KVError::SerializationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_import_redis_protocol_dump() {
+        let mut persister = new_persister();
+
+        let dump = "# exported\n\
+            SET greeting \"hello world\"\n\
+            SET bytes \"a\\x41\\n\"\n\
+            SET 'single quoted' 'as \\ is'\n\
+            \n\
+            EXPIRE greeting 10\n\
+            DEL bytes\n\
+            HSET user name alice\n";
+        let report = persister.import_redis_protocol_dump(dump.as_bytes(), RedisImportOptions::default()).unwrap();
+
+        assert_eq!(4, report.applied);
+        assert_eq!(BTreeMap::from([("EXPIRE".to_string(), 1)]), report.skipped);
+        assert_eq!(vec![("user".to_string(), "HSET needs a non-string value".to_string())], report.failed);
+        assert_eq!(1, report.expiries_ignored);
+
+        assert_eq!(b"hello world".to_vec(), persister.get_value(&"greeting".to_string()).unwrap());
+        assert_eq!(b"as \\ is".to_vec(), persister.get_value(&"single quoted".to_string()).unwrap());
+        assert_eq!(KVError::KeyDoesNotExist, persister.get_value(&"bytes".to_string()).unwrap_err());
+
+        let result = persister.import_redis_protocol_dump("SET key \"open\n".as_bytes(), RedisImportOptions::default());
+        assert_eq!(Err(KVError::SerializationError("line 1: unbalanced quotes".to_string())), result);
+    }
+
+    #[test]
+    fn test_parse_inline_command() {
+        assert_eq!(vec![b"SET".to_vec(), b"k".to_vec(), b"v".to_vec()], parse_inline_command("  SET k   v ").unwrap());
+        assert_eq!(vec![b"a\x00\xff\"".to_vec()], parse_inline_command("\"a\\x00\\xff\\\"\"").unwrap());
+        assert!(parse_inline_command("\"a\"b").is_err());
+        assert!(parse_inline_command("\"\\xzz\"").is_err());
+        assert!(parse_inline_command("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt::Debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::persist::{KVError, Persister};
+use crate::slot::Slot;
+
+/// Space claimed by `Persister::reserve` for a value written in place. The key is published by
+/// `commit`, while `abort` or dropping the reservation gives the space back
+pub struct Reservation<'a, K>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    persister: &'a mut Persister<K>,
+    key: K,
+    slot: Slot, // the whole claimed slot, header included
+    header_len: usize, // bytes written before the value, see `compression::raw_header`
+    from_freelist: bool,
+    finished: bool,
+}
+
+impl<'a, K> Reservation<'a, K>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    pub(crate) fn new(persister: &'a mut Persister<K>, key: K, slot: Slot, header_len: usize, from_freelist: bool) -> Self {
+        Self { persister, key, slot, header_len, from_freelist, finished: false }
+    }
+
+    /// Maximum length of the value
+    pub fn max_len(&self) -> usize {
+        self.slot.space - self.header_len
+    }
+
+    /// Writes the bytes at `offset` of the value, fails with `KVError::ReservationExceeded`
+    /// without writing anything if they don't fit in the reservation
+    pub fn write_at(&self, offset: usize, bytes: &[u8]) -> Result<(), KVError> {
+        let end = offset + bytes.len();
+        if end > self.max_len() {
+            return Err(KVError::ReservationExceeded { len: end, reserved: self.max_len() });
+        }
+
+        self.persister.write_reserved(&self.slot, self.header_len + offset, bytes)
+    }
+
+    /// Publishes the key holding the first `final_len` bytes of the reservation, the rest of
+    /// the space is given back. Bytes never written hold whatever the db file had there
+    pub fn commit(mut self, final_len: usize) -> Result<(), KVError> {
+        if final_len > self.max_len() {
+            return Err(KVError::ReservationExceeded { len: final_len, reserved: self.max_len() });
+        }
+
+        self.finished = true;
+        self.persister.commit_reservation(&self.key, &self.slot, self.header_len, self.from_freelist, final_len)
+    }
+
+    /// Gives the space back without publishing the key
+    pub fn abort(self) {}
+}
+
+impl<K> Drop for Reservation<'_, K>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.persister.release_reservation(&self.slot, self.from_freelist);
+        }
+    }
+}
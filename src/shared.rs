@@ -0,0 +1,172 @@
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::batch::WriteBatch;
+use crate::persist::{KVError, Persister, PutOutcome};
+
+/// Cheaply cloneable handle sharing a `Persister` between threads. Reads run concurrently,
+/// values are read at their offset so they don't compete for the position of the db file,
+/// while writes are serialized and get exclusive access to the free list and the index.
+///
+/// A panic while writing may leave the store in an inconsistent state, so once the lock is
+/// poisoned every call fails with `KVError::InvariantViolation`
+pub struct SharedPersister<K> {
+    inner: Arc<RwLock<Persister<K>>>,
+}
+
+impl<K> Clone for SharedPersister<K> {
+    fn clone(&self) -> Self {
+        Self { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<K> SharedPersister<K> where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    pub fn new(persister: Persister<K>) -> Self {
+        Self { inner: Arc::new(RwLock::new(persister)) }
+    }
+
+    pub fn get_value(&self, key: &K) -> Result<Vec<u8>, KVError> {
+        self.read()?.get_value(key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> Result<bool, KVError> {
+        Ok(self.read()?.contains_key(key))
+    }
+
+    pub fn insert_kv(&self, key: &K, value: &[u8]) -> Result<(), KVError> {
+        self.write()?.insert_kv(key, value)
+    }
+
+    pub fn update_value(&self, key: &K, value: &[u8]) -> Result<(), KVError> {
+        self.write()?.update_value(key, value)
+    }
+
+    pub fn put(&self, key: &K, value: &[u8]) -> Result<PutOutcome, KVError> {
+        self.write()?.put(key, value)
+    }
+
+    pub fn delete_kv(&self, key: &K) -> Result<(), KVError> {
+        self.write()?.delete_kv(key)
+    }
+
+    pub fn write_batch(&self, batch: WriteBatch<K>) -> Result<(), KVError> {
+        self.write()?.write_batch(batch)
+    }
+
+    pub fn flush(&self) -> Result<(), KVError> {
+        self.write()?.flush()
+    }
+
+    /// Shared access to the store for the operations without a shortcut here, e.g. iteration.
+    /// Writers wait until the guard is dropped
+    pub fn read(&self) -> Result<RwLockReadGuard<'_, Persister<K>>, KVError> {
+        self.inner.read().map_err(|_| poisoned())
+    }
+
+    /// Exclusive access to the store, readers and other writers wait until the guard is
+    /// dropped
+    pub fn write(&self) -> Result<RwLockWriteGuard<'_, Persister<K>>, KVError> {
+        self.inner.write().map_err(|_| poisoned())
+    }
+
+    /// Returns the store back if this is the last handle, or the handle otherwise
+    pub fn try_unwrap(self) -> Result<Persister<K>, Self> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(lock) => Ok(lock.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())),
+            Err(inner) => Err(Self { inner }),
+        }
+    }
+}
+
+fn poisoned() -> KVError {
+    KVError::InvariantViolation("a writer panicked while holding the shared persister".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use super::*;
+
+    fn new_shared_persister() -> SharedPersister<String> {
+        SharedPersister::new(Persister::new_temporary().unwrap())
+    }
+
+    // every value is derived from its key, so a reader can tell a torn or misplaced read
+    fn value_of(key: &str, round: usize) -> Vec<u8> {
+        let mut value = key.as_bytes().to_vec();
+        value.extend(std::iter::repeat_n(b'0' + (round % 10) as u8, round % 7));
+        value
+    }
+
+    #[test]
+    fn test_shared_persister_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedPersister<String>>();
+    }
+
+    #[test]
+    fn test_concurrent_readers_single_writer() {
+        let shared = new_shared_persister();
+        for i in 0..50 {
+            let key = format!("stable_{}", i);
+            shared.insert_kv(&key, &value_of(&key, i)).unwrap();
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4).map(|reader| {
+            let shared = shared.clone();
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut reads = 0;
+                while !done.load(Ordering::Relaxed) || reads < 1000 {
+                    let i = (reads * 7 + reader) % 50;
+                    let key = format!("stable_{}", i);
+                    assert_eq!(value_of(&key, i), shared.get_value(&key).unwrap());
+
+                    // keys churned by the writer are either missing or hold a whole value
+                    let key = format!("churn_{}", reads % 20);
+                    match shared.get_value(&key) {
+                        Ok(value) => assert!(value.starts_with(key.as_bytes()), "torn read on {}", key),
+                        Err(error) => assert_eq!(KVError::KeyDoesNotExist, error),
+                    }
+                    reads += 1;
+                }
+            })
+        }).collect();
+
+        for round in 0..2000 {
+            let key = format!("churn_{}", round % 20);
+            match round % 3 {
+                0 => { shared.put(&key, &value_of(&key, round)).unwrap(); },
+                1 => { let _ = shared.delete_kv(&key); },
+                _ => { let _ = shared.update_value(&key, &value_of(&key, round)); },
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // the free list and the index are still consistent once the dust settles
+        let mut persister = shared.try_unwrap().ok().unwrap();
+        for i in 0..50 {
+            let key = format!("stable_{}", i);
+            assert_eq!(value_of(&key, i), persister.get_value(&key).unwrap());
+        }
+        persister.insert_kv(&"after".to_string(), &[1, 2, 3]).unwrap();
+        assert_eq!(vec![1, 2, 3], persister.get_value(&"after".to_string()).unwrap());
+    }
+
+    #[test]
+    fn test_try_unwrap() {
+        let shared = new_shared_persister();
+        let other = shared.clone();
+
+        let shared = shared.try_unwrap().err().unwrap();
+        drop(other);
+        assert!(shared.try_unwrap().is_ok());
+    }
+}
@@ -1,19 +1,23 @@
 use std::cmp::Ordering;
 
-#[derive(Debug, Eq, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Slot {
     pub space: usize,
     pub cursor: usize,
 }
 
+// slots are ordered by space and then by cursor, so the free list stays sorted by space while
+// equal sized slots keep a deterministic order
 impl Ord for Slot {
     fn cmp(&self, other: &Self) -> Ordering {
-        let order = self.space.cmp(&other.space);
-        if order == Ordering::Equal {
-            // todo(): refine the ordering
-        }
+        self.space.cmp(&other.space)
+            .then(self.cursor.cmp(&other.cursor))
+    }
+}
 
-        return order
+impl PartialOrd for Slot {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -23,7 +27,7 @@ impl Slot {
             nb1.cursor > 0 && (nb1.cursor == nb2.cursor + nb2.space)
         };
 
-        return func_is_neighbour(self, spot) || func_is_neighbour(spot, self);
+        func_is_neighbour(self, spot) || func_is_neighbour(spot, self)
     }
 
     pub(crate) fn merge_with(&self, spot: &Slot) -> Slot {
@@ -32,4 +36,29 @@ impl Slot {
             space: self.space + spot.space,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ord_breaks_ties_by_cursor() {
+        let mut slots = vec![
+            Slot {space: 5, cursor: 30},
+            Slot {space: 2, cursor: 40},
+            Slot {space: 5, cursor: 10},
+            Slot {space: 5, cursor: 20},
+        ];
+        slots.sort();
+
+        assert_eq!(slots, vec![
+            Slot {space: 2, cursor: 40},
+            Slot {space: 5, cursor: 10},
+            Slot {space: 5, cursor: 20},
+            Slot {space: 5, cursor: 30},
+        ]);
+        assert_eq!(Ok(2), slots.binary_search(&Slot {space: 5, cursor: 20}));
+        assert_eq!(Err(1), slots.binary_search(&Slot {space: 5, cursor: 0}));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::batch::WriteBatch;
+use crate::persist::{KVError, Persister};
+
+/// Group of writes started by `Persister::begin`, staged in memory until they are applied
+/// together by `commit`. Reads through the transaction see its own staged writes. Dropping the
+/// transaction or calling `rollback` discards the writes without touching the store, which
+/// can't be used while the transaction is alive
+pub struct Txn<'a, K>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    persister: &'a mut Persister<K>,
+    staged: BTreeMap<K, Option<Vec<u8>>>, // None for a staged delete
+}
+
+impl<'a, K> Txn<'a, K>
+where K: Ord + Clone + Debug + Serialize + DeserializeOwned {
+    pub(crate) fn new(persister: &'a mut Persister<K>) -> Self {
+        Self { persister, staged: BTreeMap::new() }
+    }
+
+    /// Value of the key as the transaction sees it
+    pub fn get(&self, key: &K) -> Result<Vec<u8>, KVError> {
+        match self.staged.get(key) {
+            Some(Some(value)) => Ok(value.clone()),
+            Some(None) => Err(KVError::KeyDoesNotExist),
+            None => self.persister.get_value(key),
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        match self.staged.get(key) {
+            Some(staged) => staged.is_some(),
+            None => self.persister.contains_key(key),
+        }
+    }
+
+    /// Stages `value` under `key`, inserting the key or replacing its current value
+    pub fn put(&mut self, key: K, value: Vec<u8>) -> &mut Self {
+        self.staged.insert(key, Some(value));
+        self
+    }
+
+    /// Stages the removal of `key`, fails with `KVError::KeyDoesNotExist` if the transaction
+    /// doesn't see the key
+    pub fn delete(&mut self, key: &K) -> Result<&mut Self, KVError> {
+        if !self.contains_key(key) {
+            return Err(KVError::KeyDoesNotExist);
+        }
+
+        // a key only put by the transaction has nothing to remove from the store
+        match self.persister.contains_key(key) {
+            true => self.staged.insert(key.clone(), None),
+            false => self.staged.remove(key),
+        };
+
+        Ok(self)
+    }
+
+    /// Number of keys the transaction writes
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+
+    /// Applies every staged write as a single `WriteBatch`: when it fails none of them is
+    /// visible and the store is left as it was, in memory and in its files
+    pub fn commit(self) -> Result<(), KVError> {
+        let mut batch = WriteBatch::new();
+        for (key, op) in self.staged {
+            match op {
+                Some(value) => batch.put(key, value),
+                None => batch.delete(key),
+            };
+        }
+
+        self.persister.write_batch(batch)
+    }
+
+    /// Discards every staged write
+    pub fn rollback(self) {}
+}
@@ -0,0 +1,222 @@
+use std::io::{Error, ErrorKind, Read};
+use crate::slot::Slot;
+
+const OP_PUT: u8 = 1;
+const OP_DELETE: u8 = 2;
+
+/// Size past which the write-ahead log is checkpointed before logging the next write
+pub(crate) const WAL_CHECKPOINT_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Change to the store logged before it is applied
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum WalOp {
+    /// `value` holds the stored bytes to write at the slot, or None when they were written to
+    /// the db file before the operation was logged, and synced if the log was
+    Put { key: Vec<u8>, slot: Slot, expires_at: Option<u64>, value: Option<Vec<u8>> },
+    Delete { key: Vec<u8> },
+}
+
+/// Encodes the operations of a single write as one entry of the write-ahead log, so they are
+/// replayed all together or not at all.
+///
+/// Layout (integers in little endian):
+///   entry:  [payload_len: u32][crc32 of the payload: u32][payload: the operations]
+///   put:    [op: u8 = 1][key_len: u32][key bytes][cursor: u64][space: u64]
+///           [has_expiry: u8][expires_at: u64 if has_expiry][has_value: u8][space bytes if has_value]
+///   delete: [op: u8 = 2][key_len: u32][key bytes]
+pub(crate) fn encode_entry(ops: &[WalOp]) -> Vec<u8> {
+    let mut payload = vec![];
+    for op in ops {
+        match op {
+            WalOp::Put { key, slot, expires_at, value } => {
+                payload.push(OP_PUT);
+                write_key(&mut payload, key);
+                payload.extend_from_slice(&(slot.cursor as u64).to_le_bytes());
+                payload.extend_from_slice(&(slot.space as u64).to_le_bytes());
+                match expires_at {
+                    Some(expires_at) => {
+                        payload.push(1);
+                        payload.extend_from_slice(&expires_at.to_le_bytes());
+                    },
+                    None => payload.push(0),
+                }
+                match value {
+                    Some(value) => {
+                        payload.push(1);
+                        payload.extend_from_slice(value);
+                    },
+                    None => payload.push(0),
+                }
+            },
+            WalOp::Delete { key } => {
+                payload.push(OP_DELETE);
+                write_key(&mut payload, key);
+            },
+        }
+    }
+
+    let mut entry = Vec::with_capacity(8 + payload.len());
+    entry.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    entry.extend_from_slice(&crc32(&payload).to_le_bytes());
+    entry.extend_from_slice(&payload);
+    entry
+}
+
+/// Reads the next entry, returns `None` when the reader is exhausted right at an entry
+/// boundary. An entry cut in the middle fails with `UnexpectedEof` and an entry whose checksum
+/// doesn't match fails with `InvalidData`, either way nothing after it can be trusted
+pub(crate) fn decode_entry<R: Read>(reader: &mut R) -> Result<Option<Vec<WalOp>>, Error> {
+    let mut len = [0u8; 4];
+    let read = read_up_to(reader, &mut len)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read < len.len() {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "torn write-ahead log entry"));
+    }
+
+    let crc = read_u32(reader)?;
+    // the length of a torn entry can be garbage, so the payload is not allocated up front
+    let len = u32::from_le_bytes(len) as usize;
+    let mut payload = vec![];
+    reader.by_ref().take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() < len {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "torn write-ahead log entry"));
+    }
+    if crc32(&payload) != crc {
+        return Err(Error::new(ErrorKind::InvalidData, "corrupt write-ahead log entry"));
+    }
+
+    let mut ops = vec![];
+    let mut reader = payload.as_slice();
+    while !reader.is_empty() {
+        ops.push(decode_op(&mut reader)?);
+    }
+
+    Ok(Some(ops))
+}
+
+fn decode_op(reader: &mut &[u8]) -> Result<WalOp, Error> {
+    let op = read_u8(reader)?;
+    let key_len = read_u32(reader)? as usize;
+    let mut key = vec![0; key_len];
+    reader.read_exact(&mut key)?;
+
+    match op {
+        OP_PUT => {
+            let cursor = read_u64(reader)? as usize;
+            let space = read_u64(reader)? as usize;
+            let expires_at = match read_u8(reader)? {
+                0 => None,
+                _ => Some(read_u64(reader)?),
+            };
+            let value = match read_u8(reader)? {
+                0 => None,
+                _ => {
+                    let mut value = vec![0; space];
+                    reader.read_exact(&mut value)?;
+                    Some(value)
+                },
+            };
+            Ok(WalOp::Put { key, slot: Slot { cursor, space }, expires_at, value })
+        },
+        OP_DELETE => Ok(WalOp::Delete { key }),
+        unknown => Err(Error::new(ErrorKind::InvalidData, format!("unknown write-ahead log operation {}", unknown))),
+    }
+}
+
+fn write_key(buffer: &mut Vec<u8>, key: &[u8]) {
+    buffer.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(key);
+}
+
+// fills as much of the buffer as the reader can give
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {},
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(filled)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> Result<u8, Error> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Error> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+// CRC-32 (IEEE), bit by bit since entries are small next to the cost of syncing them
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_ops() -> Vec<WalOp> {
+        vec![
+            WalOp::Put { key: b"key_1".to_vec(), slot: Slot { cursor: 10, space: 3 }, expires_at: None, value: Some(b"abc".to_vec()) },
+            WalOp::Delete { key: b"key_2".to_vec() },
+            WalOp::Put { key: vec![], slot: Slot { cursor: 0, space: 0 }, expires_at: Some(42), value: Some(vec![]) },
+            WalOp::Put { key: b"key_3".to_vec(), slot: Slot { cursor: 1 << 40, space: 7 }, expires_at: Some(1), value: None },
+        ]
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut log = encode_entry(&sample_ops());
+        log.extend(encode_entry(&[WalOp::Delete { key: b"key_1".to_vec() }]));
+
+        let mut reader = log.as_slice();
+        assert_eq!(Some(sample_ops()), decode_entry(&mut reader).unwrap());
+        assert_eq!(Some(vec![WalOp::Delete { key: b"key_1".to_vec() }]), decode_entry(&mut reader).unwrap());
+        assert_eq!(None, decode_entry(&mut reader).unwrap());
+    }
+
+    #[test]
+    fn test_decode_torn_or_corrupt_entry() {
+        let entry = encode_entry(&sample_ops());
+
+        for len in [1, 4, 7, 8, entry.len() - 1] {
+            let mut reader = &entry[..len];
+            assert_eq!(ErrorKind::UnexpectedEof, decode_entry(&mut reader).unwrap_err().kind());
+        }
+
+        let mut corrupt = entry.clone();
+        corrupt[12] ^= 1;
+        assert_eq!(ErrorKind::InvalidData, decode_entry(&mut corrupt.as_slice()).unwrap_err().kind());
+    }
+}